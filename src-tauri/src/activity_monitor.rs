@@ -1,11 +1,14 @@
+use chrono::{DateTime, Duration, Utc};
 use rdev::{listen as rdev_listen, Event, EventType};
 use serde::Serialize;
 use std::sync::{
     atomic::{AtomicBool, AtomicUsize, Ordering}, // Added AtomicBool
-    Arc,
+    Arc, Mutex,
 };
 // Removed unused tokio::sync::Mutex import
 
+use crate::clock::Clock;
+
 /// Holds atomic counters for different types of user activity.
 /// Wrapped in Arc<Mutex<...>> for safe sharing across threads.
 #[derive(Default, Debug)]
@@ -16,18 +19,107 @@ pub struct ActivityCounters {
 }
 
 /// Data structure sent to the frontend.
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Clone, Debug, Default)]
 pub struct ActivityData {
-    key_presses: usize,
-    mouse_clicks: usize,
+    pub key_presses: usize,
+    pub mouse_clicks: usize,
+}
+
+impl ActivityData {
+    /// Activity between an earlier snapshot and this one.
+    pub fn since(&self, earlier: &ActivityData) -> ActivityData {
+        ActivityData {
+            key_presses: self.key_presses.saturating_sub(earlier.key_presses),
+            mouse_clicks: self.mouse_clicks.saturating_sub(earlier.mouse_clicks),
+        }
+    }
+}
+
+/// Input breaks shorter than this don't end a stretch of untracked activity.
+const UNTRACKED_IDLE_GAP: Duration = Duration::minutes(5);
+
+/// A stretch of input while no timer was running.
+#[derive(Clone, Debug)]
+struct UntrackedStretch {
+    started_at: DateTime<Utc>,
+    last_input_at: DateTime<Utc>,
+    counts: ActivityData,
+    reminded: bool,
+}
+
+/// Activity while the timer is stopped. Only kept in memory, to remind the user to start the
+/// timer and to let them start it from when they began working.
+#[derive(Default, Debug)]
+pub struct UntrackedActivity {
+    stretch: Mutex<Option<UntrackedStretch>>,
+}
+
+impl UntrackedActivity {
+    /// Counts one input event at `at`. A longer break starts a new stretch.
+    pub fn record(&self, at: DateTime<Utc>, key_press: bool) {
+        let mut stretch = self.stretch.lock().unwrap();
+        let stretch = match stretch.as_mut() {
+            Some(current) if at - current.last_input_at <= UNTRACKED_IDLE_GAP => current,
+            _ => stretch.insert(UntrackedStretch {
+                started_at: at,
+                last_input_at: at,
+                counts: ActivityData::default(),
+                reminded: false,
+            }),
+        };
+        stretch.last_input_at = at;
+        if key_press {
+            stretch.counts.key_presses += 1;
+        } else {
+            stretch.counts.mouse_clicks += 1;
+        }
+    }
+
+    /// The stretch going on at `now`: when it began and the input counted since.
+    pub fn current(&self, now: DateTime<Utc>) -> Option<(DateTime<Utc>, ActivityData)> {
+        self.stretch
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|stretch| now - stretch.last_input_at <= UNTRACKED_IDLE_GAP)
+            .map(|stretch| (stretch.started_at, stretch.counts.clone()))
+    }
+
+    /// When the current stretch began, once it has lasted `after` and only the first time
+    /// it's asked for. Each stretch gets one reminder.
+    pub fn reminder_due(&self, now: DateTime<Utc>, after: Duration) -> Option<DateTime<Utc>> {
+        let mut stretch = self.stretch.lock().unwrap();
+        let stretch = stretch
+            .as_mut()
+            .filter(|stretch| now - stretch.last_input_at <= UNTRACKED_IDLE_GAP)
+            .filter(|stretch| !stretch.reminded && now - stretch.started_at >= after)?;
+        stretch.reminded = true;
+        Some(stretch.started_at)
+    }
+
+    /// Forgets the current stretch, e.g. because the timer was started.
+    pub fn clear(&self) {
+        *self.stretch.lock().unwrap() = None;
+    }
 }
 
 /// Listens for global input events and updates the counters if the session is active.
+/// Input while no session is active only goes to `untracked`, stamped with `clock`.
 /// This function is intended to be run in a separate thread.
-pub fn listen(counters: Arc<ActivityCounters>, is_session_active: Arc<AtomicBool>) {
+pub fn listen(
+    counters: Arc<ActivityCounters>,
+    is_session_active: Arc<AtomicBool>,
+    untracked: Arc<UntrackedActivity>,
+    clock: Arc<dyn Clock>,
+) {
     let callback = move |event: Event| {
         // Only count if the session is active
         if !is_session_active.load(Ordering::Relaxed) {
+            match event.event_type {
+                EventType::KeyPress(_) => untracked.record(clock.now(), true),
+                EventType::ButtonPress(_) => untracked.record(clock.now(), false),
+                _ => {}
+            }
             return;
         }
 
@@ -65,3 +157,50 @@ pub fn get_current_counts(counters: &ActivityCounters) -> ActivityData {
         mouse_clicks: counters.mouse_clicks.load(Ordering::Relaxed),
     } // Removed semicolon to return the struct
 } // Closing brace remains
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn minutes(m: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 6, 9, 0, 0).unwrap() + Duration::minutes(m)
+    }
+
+    // A key press or a click every minute from 9:00 to 9:12
+    fn twelve_active_minutes() -> UntrackedActivity {
+        let untracked = UntrackedActivity::default();
+        for m in 0..=12 {
+            untracked.record(minutes(m), m % 2 == 0);
+        }
+        untracked
+    }
+
+    #[test]
+    fn no_reminder_before_the_stretch_is_long_enough() {
+        let untracked = twelve_active_minutes();
+        assert_eq!(untracked.reminder_due(minutes(9), Duration::minutes(10)), None);
+    }
+
+    #[test]
+    fn reminds_once_per_stretch_with_its_start() {
+        let untracked = twelve_active_minutes();
+        assert_eq!(untracked.reminder_due(minutes(12), Duration::minutes(10)), Some(minutes(0)));
+        assert_eq!(untracked.reminder_due(minutes(13), Duration::minutes(10)), None);
+    }
+
+    #[test]
+    fn counts_the_stretchs_key_presses_and_clicks() {
+        let untracked = twelve_active_minutes();
+        let (since, counts) = untracked.current(minutes(13)).unwrap();
+        assert_eq!((since, counts.key_presses, counts.mouse_clicks), (minutes(0), 7, 6));
+    }
+
+    #[test]
+    fn activity_after_a_long_break_is_a_new_stretch() {
+        let untracked = twelve_active_minutes();
+        assert!(untracked.current(minutes(20)).is_none());
+        untracked.record(minutes(20), true);
+        assert_eq!(untracked.current(minutes(20)).unwrap().0, minutes(20));
+    }
+}
@@ -0,0 +1,294 @@
+use serde::Serialize;
+use sqlx::postgres::PgRow;
+use sqlx::{Pool, Postgres, Row};
+use uuid::Uuid;
+
+use crate::errors::AppError;
+use crate::identity::{CurrentUser, TeamMemberRole};
+
+/// Why a command was refused. Every denial is logged with who asked and what for.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Denial {
+    /// No team member is configured for this install
+    NotSignedIn,
+    /// Needs a manager or an admin
+    ManagersOnly,
+    AdminsOnly,
+    /// The data belongs to another organization
+    OtherOrganization,
+    /// Employees only reach their own data
+    NotOwner,
+}
+
+impl Denial {
+    pub fn message(self) -> &'static str {
+        match self {
+            Denial::NotSignedIn => "No team member is signed in. Set TEAM_MEMBER_EMAIL in .env.",
+            Denial::ManagersOnly => "Only managers can do this.",
+            Denial::AdminsOnly => "Only admins can do this.",
+            Denial::OtherOrganization => "This belongs to another organization.",
+            Denial::NotOwner => "You can only do this with your own data.",
+        }
+    }
+}
+
+impl std::fmt::Display for Denial {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl From<Denial> for String {
+    fn from(denial: Denial) -> Self {
+        denial.message().to_string()
+    }
+}
+
+/// Who a piece of data belongs to. Data recorded before owners were tracked has no owner.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Owner {
+    pub team_member_id: Option<i32>,
+    pub organization_id: Option<i32>,
+}
+
+// Logs the denial and hands it back
+fn deny(user: Option<&CurrentUser>, action: &str, denial: Denial) -> Denial {
+    match user {
+        Some(user) => eprintln!(
+            "Access denied: team member {} ({:?}) tried to {}: {}",
+            user.team_member_id, user.role, action, denial
+        ),
+        None => eprintln!("Access denied: tried to {} without a signed-in team member.", action),
+    }
+    denial
+}
+
+/// The signed-in team member; every command but `get_current_user` needs one.
+pub fn signed_in<'a>(user: Option<&'a CurrentUser>, action: &str) -> Result<&'a CurrentUser, Denial> {
+    user.ok_or_else(|| deny(None, action, Denial::NotSignedIn))
+}
+
+/// Only the team member themselves, whatever their role; for records nobody else may change.
+pub fn require_own(user: &CurrentUser, action: &str, team_member_id: i32) -> Result<(), Denial> {
+    if team_member_id == user.team_member_id {
+        Ok(())
+    } else {
+        Err(deny(Some(user), action, Denial::NotOwner))
+    }
+}
+
+/// Managers and admins only.
+pub fn require_manager(user: &CurrentUser, action: &str) -> Result<(), Denial> {
+    if user.role.is_manager() {
+        Ok(())
+    } else {
+        Err(deny(Some(user), action, Denial::ManagersOnly))
+    }
+}
+
+/// Admins only.
+pub fn require_admin(user: &CurrentUser, action: &str) -> Result<(), Denial> {
+    if user.role == TeamMemberRole::Admin {
+        Ok(())
+    } else {
+        Err(deny(Some(user), action, Denial::AdminsOnly))
+    }
+}
+
+/// Employees reach their own data and managers also their organization's. Admins are
+/// unrestricted, including data without an owner and other organizations' data.
+pub fn check_owner(user: &CurrentUser, action: &str, owner: Owner) -> Result<(), Denial> {
+    if user.role == TeamMemberRole::Admin {
+        return Ok(());
+    }
+    let denial = match owner.team_member_id {
+        Some(id) if id == user.team_member_id => return Ok(()),
+        None => Denial::AdminsOnly,
+        Some(_) if owner.organization_id != Some(user.organization_id) => Denial::OtherOrganization,
+        Some(_) if user.role.is_manager() => return Ok(()),
+        Some(_) => Denial::NotOwner,
+    };
+    Err(deny(Some(user), action, denial))
+}
+
+/// Managers of the owner's organization, and admins, for actions on someone else's data.
+pub fn check_manager_of(user: &CurrentUser, action: &str, owner: Owner) -> Result<(), Denial> {
+    require_manager(user, action)?;
+    if user.role != TeamMemberRole::Admin && owner.organization_id != Some(user.organization_id) {
+        return Err(deny(Some(user), action, Denial::OtherOrganization));
+    }
+    Ok(())
+}
+
+fn owner_from_row(row: &PgRow) -> Result<Owner, AppError> {
+    let column_error = |name: &str, e: sqlx::Error| AppError::Db(format!("Failed to get {} column: {}", name, e));
+    Ok(Owner {
+        team_member_id: row.try_get("team_member_id").map_err(|e| column_error("team_member_id", e))?,
+        organization_id: row.try_get("organization_id").map_err(|e| column_error("organization_id", e))?,
+    })
+}
+
+/// The team member themselves, with their organization.
+pub async fn team_member_owner(pool: &Pool<Postgres>, team_member_id: i32) -> Result<Owner, AppError> {
+    let sql = r#"SELECT id AS team_member_id, "organizationId" AS organization_id FROM team_members WHERE id = $1"#;
+    let row = sqlx::query(sql)
+        .bind(team_member_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::Db(format!("Failed to load team member {}: {}", team_member_id, e)))?
+        .ok_or_else(|| AppError::NotFound(format!("Team member {} not found.", team_member_id)))?;
+    owner_from_row(&row)
+}
+
+/// The owner of a session.
+pub async fn session_owner(pool: &Pool<Postgres>, session_id: Uuid) -> Result<Owner, AppError> {
+    let sql = r#"
+        SELECT s.team_member_id, m."organizationId" AS organization_id
+        FROM sessions s
+        LEFT JOIN team_members m ON m.id = s.team_member_id
+        WHERE s.id = $1
+    "#;
+    let row = sqlx::query(sql)
+        .bind(session_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::Db(format!("Database error fetching session: {}", e)))?
+        .ok_or_else(|| AppError::NotFound(format!("Session {} not found.", session_id)))?;
+    owner_from_row(&row)
+}
+
+/// The owner of the session a screenshot was taken in.
+pub async fn screenshot_owner(pool: &Pool<Postgres>, screenshot_id: Uuid) -> Result<Owner, AppError> {
+    let sql = r#"
+        SELECT s.team_member_id, m."organizationId" AS organization_id
+        FROM screenshots sc
+        LEFT JOIN sessions s ON s.id = sc.session_id
+        LEFT JOIN team_members m ON m.id = s.team_member_id
+        WHERE sc.id = $1
+    "#;
+    let row = sqlx::query(sql)
+        .bind(screenshot_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::Db(format!("Database error fetching screenshot: {}", e)))?
+        .ok_or_else(|| AppError::NotFound(format!("Screenshot with ID {} not found", screenshot_id)))?;
+    owner_from_row(&row)
+}
+
+/// The user themselves, or a manager of the team member's organization.
+pub async fn authorize_team_member(
+    pool: &Pool<Postgres>,
+    user: &CurrentUser,
+    action: &str,
+    team_member_id: i32,
+) -> Result<(), AppError> {
+    if team_member_id == user.team_member_id {
+        return Ok(());
+    }
+    Ok(check_owner(user, action, team_member_owner(pool, team_member_id).await?)?)
+}
+
+/// A manager of the team member's organization, even for their own record.
+pub async fn require_manager_of(
+    pool: &Pool<Postgres>,
+    user: &CurrentUser,
+    action: &str,
+    team_member_id: i32,
+) -> Result<(), AppError> {
+    require_manager(user, action)?;
+    Ok(check_manager_of(user, action, team_member_owner(pool, team_member_id).await?)?)
+}
+
+/// The session's owner, or a manager of their organization.
+pub async fn authorize_session(
+    pool: &Pool<Postgres>,
+    user: &CurrentUser,
+    action: &str,
+    session_id: Uuid,
+) -> Result<(), AppError> {
+    Ok(check_owner(user, action, session_owner(pool, session_id).await?)?)
+}
+
+/// The owner of the screenshot's session, or a manager of their organization.
+pub async fn authorize_screenshot(
+    pool: &Pool<Postgres>,
+    user: &CurrentUser,
+    action: &str,
+    screenshot_id: Uuid,
+) -> Result<(), AppError> {
+    Ok(check_owner(user, action, screenshot_owner(pool, screenshot_id).await?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A team member of organization 1
+    fn user(team_member_id: i32, role: TeamMemberRole) -> CurrentUser {
+        CurrentUser { team_member_id, organization_id: 1, email: format!("{}@example.com", team_member_id), role }
+    }
+
+    fn owned_by(team_member_id: Option<i32>, organization_id: Option<i32>) -> Owner {
+        Owner { team_member_id, organization_id }
+    }
+
+    #[test]
+    fn employees_reach_their_own_data() {
+        assert_eq!(check_owner(&user(7, TeamMemberRole::Employee), "view", owned_by(Some(7), Some(1))), Ok(()));
+    }
+
+    #[test]
+    fn employees_cannot_reach_a_colleagues_data() {
+        let result = check_owner(&user(7, TeamMemberRole::Employee), "view", owned_by(Some(6), Some(1)));
+        assert_eq!(result, Err(Denial::NotOwner));
+    }
+
+    #[test]
+    fn managers_reach_their_organizations_data() {
+        assert_eq!(check_owner(&user(8, TeamMemberRole::Manager), "view", owned_by(Some(6), Some(1))), Ok(()));
+    }
+
+    #[test]
+    fn managers_cannot_reach_another_organizations_data() {
+        let result = check_owner(&user(8, TeamMemberRole::Manager), "view", owned_by(Some(6), Some(2)));
+        assert_eq!(result, Err(Denial::OtherOrganization));
+    }
+
+    #[test]
+    fn admins_reach_every_organizations_data() {
+        assert_eq!(check_owner(&user(9, TeamMemberRole::Admin), "view", owned_by(Some(6), Some(2))), Ok(()));
+    }
+
+    #[test]
+    fn data_from_before_owners_were_tracked_is_for_admins_only() {
+        assert_eq!(check_owner(&user(8, TeamMemberRole::Manager), "view", owned_by(None, None)), Err(Denial::AdminsOnly));
+        assert_eq!(check_owner(&user(9, TeamMemberRole::Admin), "view", owned_by(None, None)), Ok(()));
+    }
+
+    #[test]
+    fn employees_cannot_review_even_their_own_data() {
+        let result = check_manager_of(&user(7, TeamMemberRole::Employee), "review", owned_by(Some(7), Some(1)));
+        assert_eq!(result, Err(Denial::ManagersOnly));
+    }
+
+    #[test]
+    fn managers_review_their_organizations_team_members() {
+        assert_eq!(check_manager_of(&user(8, TeamMemberRole::Manager), "review", owned_by(Some(6), Some(1))), Ok(()));
+    }
+
+    #[test]
+    fn managers_are_not_admins() {
+        assert_eq!(require_admin(&user(8, TeamMemberRole::Manager), "open"), Err(Denial::AdminsOnly));
+    }
+
+    #[test]
+    fn commands_need_a_team_member() {
+        assert_eq!(signed_in(None, "start").unwrap_err(), Denial::NotSignedIn);
+    }
+
+    #[test]
+    fn denials_read_as_their_message() {
+        assert_eq!(String::from(Denial::NotOwner), "You can only do this with your own data.");
+    }
+}
@@ -0,0 +1,88 @@
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+/// How strongly a screenshot is pixelated before it is stored.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BlurLevel {
+    #[default]
+    None,
+    /// Text becomes unreadable, layout and colours stay recognisable.
+    Light,
+    /// Only large shapes survive.
+    Heavy,
+}
+
+impl BlurLevel {
+    /// Value stored in the `blur_level` column.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BlurLevel::None => "none",
+            BlurLevel::Light => "light",
+            BlurLevel::Heavy => "heavy",
+        }
+    }
+
+    /// Pixelation block size, scaled to the capture so the effect is the same on any resolution.
+    pub fn block_size(self, image_width: u32) -> Option<u32> {
+        match self {
+            BlurLevel::None => None,
+            BlurLevel::Light => Some((image_width / 160).max(4)),
+            BlurLevel::Heavy => Some((image_width / 48).max(12)),
+        }
+    }
+}
+
+/// Pixelates the whole image according to the level. Does nothing for `BlurLevel::None`.
+pub fn apply_blur(image: &mut RgbaImage, level: BlurLevel) {
+    if let Some(block) = level.block_size(image.width()) {
+        let (width, height) = image.dimensions();
+        pixelate_region(image, 0, 0, width, height, block);
+    }
+}
+
+/// Replaces each `block`x`block` square inside the rectangle with its average colour.
+/// The rectangle is clipped to the image bounds.
+pub fn pixelate_region(image: &mut RgbaImage, x: u32, y: u32, width: u32, height: u32, block: u32) {
+    let block = block.max(1);
+    let x_end = x.saturating_add(width).min(image.width());
+    let y_end = y.saturating_add(height).min(image.height());
+
+    for block_y in (y..y_end).step_by(block as usize) {
+        let block_y_end = (block_y + block).min(y_end);
+        for block_x in (x..x_end).step_by(block as usize) {
+            let block_x_end = (block_x + block).min(x_end);
+
+            let mut sum = [0u64; 4];
+            for py in block_y..block_y_end {
+                for px in block_x..block_x_end {
+                    for (total, channel) in sum.iter_mut().zip(image.get_pixel(px, py).0) {
+                        *total += u64::from(channel);
+                    }
+                }
+            }
+            let count = u64::from((block_x_end - block_x) * (block_y_end - block_y));
+            let average = Rgba(sum.map(|total| (total / count) as u8));
+
+            for py in block_y..block_y_end {
+                for px in block_x..block_x_end {
+                    image.put_pixel(px, py, average);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixelation_averages_each_block() {
+        let mut image = RgbaImage::from_fn(4, 2, |x, _| {
+            if x % 2 == 0 { Rgba([0, 0, 0, 255]) } else { Rgba([200, 100, 50, 255]) }
+        });
+        pixelate_region(&mut image, 0, 0, 4, 2, 2);
+        assert!(image.pixels().all(|p| *p == Rgba([100, 50, 25, 255])));
+    }
+}
@@ -0,0 +1,360 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::errors::AppError;
+
+/// A pause at least this long counts as a break and resets the "time for a break" nudges.
+const MIN_BREAK: Duration = Duration::minutes(5);
+
+/// Lengths of a Pomodoro cycle: work intervals separated by short breaks, with a long break
+/// after every `work_intervals_per_long_break` intervals.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct PomodoroSettings {
+    pub work_minutes: u32,
+    pub short_break_minutes: u32,
+    pub long_break_minutes: u32,
+    pub work_intervals_per_long_break: u32,
+}
+
+impl Default for PomodoroSettings {
+    fn default() -> Self {
+        PomodoroSettings {
+            work_minutes: 25,
+            short_break_minutes: 5,
+            long_break_minutes: 15,
+            work_intervals_per_long_break: 4,
+        }
+    }
+}
+
+impl PomodoroSettings {
+    pub fn validated(self) -> Result<Self, AppError> {
+        if self.work_minutes == 0 || self.short_break_minutes == 0 || self.long_break_minutes == 0 {
+            return Err(AppError::Invalid("Pomodoro work and break lengths must be at least one minute.".to_string()));
+        }
+        if self.work_intervals_per_long_break == 0 {
+            return Err(AppError::Invalid("There must be at least one work interval before a long break.".to_string()));
+        }
+        Ok(self)
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PomodoroPhase {
+    Work,
+    ShortBreak,
+    LongBreak,
+}
+
+/// Where the current Pomodoro cycle stands, as shown to the user.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct PomodoroState {
+    pub phase: PomodoroPhase,
+    /// Work intervals finished since the last long break
+    pub completed_work_intervals: u32,
+    /// When the phase is expected to end; `None` while a work interval is paused
+    pub phase_ends_at: Option<DateTime<Utc>>,
+}
+
+/// Something the break tracker wants done.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakDue {
+    /// The work interval is over; start a break
+    WorkDone,
+    /// The Pomodoro break is over; back to work
+    BreakOver,
+    /// Worked a long time without a break; nudge the user
+    Nudge,
+}
+
+/// Tracks active time for Pomodoro cycles and break nudges. Active time is measured with the
+/// monotonic clock while the timer runs, so pauses, suspends and clock changes don't count.
+#[derive(Debug, Clone)]
+pub struct BreakTracker {
+    pomodoro: Option<PomodoroSettings>,
+    nudge_after: Option<Duration>,
+    phase: PomodoroPhase,
+    completed: u32,
+    phase_active: Duration,  // Banked active time in the current work interval
+    since_break: Duration,   // Banked active time since the last real break
+    nudges: i32,             // Nudges sent since the last real break
+    running_since: Option<Instant>,
+    paused_at: Option<DateTime<Utc>>,
+    break_until: Option<DateTime<Utc>>,
+}
+
+impl BreakTracker {
+    /// `nudge_after` of `None` disables the nudges.
+    pub fn new(pomodoro: Option<PomodoroSettings>, nudge_after: Option<Duration>) -> Self {
+        BreakTracker {
+            pomodoro,
+            nudge_after,
+            phase: PomodoroPhase::Work,
+            completed: 0,
+            phase_active: Duration::zero(),
+            since_break: Duration::zero(),
+            nudges: 0,
+            running_since: None,
+            paused_at: None,
+            break_until: None,
+        }
+    }
+
+    fn running_for(&self, now: Instant) -> Duration {
+        self.running_since
+            .map_or(Duration::zero(), |since| Duration::from_std(now - since).unwrap_or(Duration::zero()))
+    }
+
+    /// The timer started or resumed. Ends a Pomodoro break early if one was going on.
+    pub fn resume(&mut self, now: Instant, wall: DateTime<Utc>) {
+        if self.running_since.is_some() {
+            return;
+        }
+        if self.paused_at.take().is_some_and(|paused_at| wall - paused_at >= MIN_BREAK) {
+            self.since_break = Duration::zero();
+            self.nudges = 0;
+        }
+        if self.phase != PomodoroPhase::Work {
+            self.end_break();
+        }
+        self.running_since = Some(now);
+    }
+
+    /// The timer paused, by the user or for a Pomodoro break.
+    pub fn pause(&mut self, now: Instant, wall: DateTime<Utc>) {
+        if self.running_since.is_none() {
+            return;
+        }
+        let active = self.running_for(now);
+        self.phase_active += active;
+        self.since_break += active;
+        self.running_since = None;
+        self.paused_at = Some(wall);
+    }
+
+    /// Switches Pomodoro mode on, off or to other lengths, starting a fresh cycle.
+    pub fn set_pomodoro(&mut self, pomodoro: Option<PomodoroSettings>, now: Instant) {
+        self.pomodoro = pomodoro;
+        self.phase = PomodoroPhase::Work;
+        self.completed = 0;
+        self.phase_active = Duration::zero();
+        self.break_until = None;
+        if self.running_since.is_some() {
+            self.running_since = Some(now);
+        }
+    }
+
+    /// The next thing due and when, by the wall clock.
+    pub fn next_due(&self, now: Instant, wall: DateTime<Utc>) -> Option<(DateTime<Utc>, BreakDue)> {
+        if let Some(break_until) = self.break_until {
+            return Some((break_until, BreakDue::BreakOver));
+        }
+        self.running_since?;
+        let running = self.running_for(now);
+        let work_done = self.pomodoro.map(|pomodoro| {
+            let left = Duration::minutes(i64::from(pomodoro.work_minutes)) - self.phase_active - running;
+            (wall + left, BreakDue::WorkDone)
+        });
+        let nudge = self.nudge_after.map(|after| {
+            let left = after * (self.nudges + 1) - self.since_break - running;
+            (wall + left, BreakDue::Nudge)
+        });
+        work_done.into_iter().chain(nudge).min_by_key(|(at, _)| *at)
+    }
+
+    /// Ends the work interval and starts the break that follows it.
+    pub fn start_break(&mut self, now: Instant, wall: DateTime<Utc>) {
+        let Some(pomodoro) = self.pomodoro else {
+            return;
+        };
+        self.pause(now, wall);
+        self.completed += 1;
+        let minutes = if self.completed.is_multiple_of(pomodoro.work_intervals_per_long_break) {
+            self.phase = PomodoroPhase::LongBreak;
+            pomodoro.long_break_minutes
+        } else {
+            self.phase = PomodoroPhase::ShortBreak;
+            pomodoro.short_break_minutes
+        };
+        self.break_until = Some(wall + Duration::minutes(i64::from(minutes)));
+    }
+
+    /// Ends the Pomodoro break and starts the next work interval, which only counts once resumed.
+    pub fn end_break(&mut self) {
+        self.phase = PomodoroPhase::Work;
+        self.phase_active = Duration::zero();
+        self.break_until = None;
+    }
+
+    /// Records a nudge and returns the active time since the last break.
+    pub fn nudge(&mut self, now: Instant) -> Duration {
+        self.nudges += 1;
+        self.since_break + self.running_for(now)
+    }
+
+    /// Pomodoro progress to show, or `None` outside Pomodoro mode.
+    pub fn pomodoro_state(&self, now: Instant, wall: DateTime<Utc>) -> Option<PomodoroState> {
+        let pomodoro = self.pomodoro?;
+        let phase_ends_at = match self.phase {
+            PomodoroPhase::Work => self.running_since.map(|_| {
+                wall + Duration::minutes(i64::from(pomodoro.work_minutes)) - self.phase_active - self.running_for(now)
+            }),
+            PomodoroPhase::ShortBreak | PomodoroPhase::LongBreak => self.break_until,
+        };
+        Some(PomodoroState {
+            phase: self.phase,
+            completed_work_intervals: self.completed % pomodoro.work_intervals_per_long_break,
+            phase_ends_at,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const POMODORO: PomodoroSettings = PomodoroSettings {
+        work_minutes: 25,
+        short_break_minutes: 5,
+        long_break_minutes: 15,
+        work_intervals_per_long_break: 2,
+    };
+
+    // Monotonic and wall-clock times `m` minutes into the test
+    struct Times(Instant);
+
+    impl Times {
+        fn new() -> Self {
+            Times(Instant::now())
+        }
+
+        fn instant(&self, m: i64) -> Instant {
+            self.0 + Duration::minutes(m).to_std().unwrap()
+        }
+
+        fn wall(&self, m: i64) -> DateTime<Utc> {
+            Utc.with_ymd_and_hms(2024, 5, 6, 9, 0, 0).unwrap() + Duration::minutes(m)
+        }
+    }
+
+    fn nudging_every(minutes: i64) -> BreakTracker {
+        BreakTracker::new(None, Some(Duration::minutes(minutes)))
+    }
+
+    #[test]
+    fn work_interval_ends_after_the_work_minutes() {
+        let times = Times::new();
+        let mut breaks = BreakTracker::new(Some(POMODORO), None);
+        breaks.resume(times.instant(0), times.wall(0));
+        assert_eq!(breaks.next_due(times.instant(10), times.wall(10)), Some((times.wall(25), BreakDue::WorkDone)));
+    }
+
+    #[test]
+    fn paused_time_does_not_count_as_work() {
+        let times = Times::new();
+        let mut breaks = BreakTracker::new(Some(POMODORO), None);
+        breaks.resume(times.instant(0), times.wall(0));
+        breaks.pause(times.instant(10), times.wall(10));
+        breaks.resume(times.instant(13), times.wall(13));
+        assert_eq!(breaks.next_due(times.instant(13), times.wall(13)), Some((times.wall(28), BreakDue::WorkDone)));
+    }
+
+    #[test]
+    fn a_break_is_over_after_the_break_minutes() {
+        let times = Times::new();
+        let mut breaks = BreakTracker::new(Some(POMODORO), None);
+        breaks.resume(times.instant(0), times.wall(0));
+        breaks.start_break(times.instant(25), times.wall(25));
+        assert_eq!(breaks.next_due(times.instant(25), times.wall(25)), Some((times.wall(30), BreakDue::BreakOver)));
+    }
+
+    #[test]
+    fn every_nth_break_is_long() {
+        let times = Times::new();
+        let mut breaks = BreakTracker::new(Some(POMODORO), None);
+        breaks.resume(times.instant(0), times.wall(0));
+        breaks.start_break(times.instant(25), times.wall(25));
+        assert_eq!(breaks.pomodoro_state(times.instant(25), times.wall(25)).unwrap().phase, PomodoroPhase::ShortBreak);
+
+        breaks.resume(times.instant(30), times.wall(30));
+        breaks.start_break(times.instant(55), times.wall(55));
+        let state = breaks.pomodoro_state(times.instant(55), times.wall(55)).unwrap();
+        assert_eq!(state.phase, PomodoroPhase::LongBreak);
+        assert_eq!(state.phase_ends_at, Some(times.wall(70)));
+    }
+
+    #[test]
+    fn the_count_of_intervals_starts_over_after_a_long_break() {
+        let times = Times::new();
+        let mut breaks = BreakTracker::new(Some(POMODORO), None);
+        breaks.resume(times.instant(0), times.wall(0));
+        breaks.start_break(times.instant(25), times.wall(25));
+        assert_eq!(breaks.pomodoro_state(times.instant(25), times.wall(25)).unwrap().completed_work_intervals, 1);
+
+        breaks.resume(times.instant(30), times.wall(30));
+        breaks.start_break(times.instant(55), times.wall(55));
+        assert_eq!(breaks.pomodoro_state(times.instant(55), times.wall(55)).unwrap().completed_work_intervals, 0);
+    }
+
+    #[test]
+    fn resuming_ends_a_break_early() {
+        let times = Times::new();
+        let mut breaks = BreakTracker::new(Some(POMODORO), None);
+        breaks.resume(times.instant(0), times.wall(0));
+        breaks.start_break(times.instant(25), times.wall(25));
+        breaks.resume(times.instant(27), times.wall(27));
+        assert_eq!(breaks.next_due(times.instant(27), times.wall(27)), Some((times.wall(52), BreakDue::WorkDone)));
+    }
+
+    #[test]
+    fn an_ended_break_waits_to_be_resumed() {
+        let times = Times::new();
+        let mut breaks = BreakTracker::new(Some(POMODORO), None);
+        breaks.resume(times.instant(0), times.wall(0));
+        breaks.start_break(times.instant(25), times.wall(25));
+        breaks.end_break();
+        assert_eq!(breaks.next_due(times.instant(30), times.wall(30)), None);
+    }
+
+    #[test]
+    fn nudges_after_the_active_time() {
+        let times = Times::new();
+        let mut breaks = nudging_every(30);
+        breaks.resume(times.instant(0), times.wall(0));
+        assert_eq!(breaks.next_due(times.instant(0), times.wall(0)), Some((times.wall(30), BreakDue::Nudge)));
+    }
+
+    #[test]
+    fn nudges_repeat_after_each_further_interval() {
+        let times = Times::new();
+        let mut breaks = nudging_every(30);
+        breaks.resume(times.instant(0), times.wall(0));
+        assert_eq!(breaks.nudge(times.instant(30)), Duration::minutes(30));
+        assert_eq!(breaks.next_due(times.instant(30), times.wall(30)), Some((times.wall(60), BreakDue::Nudge)));
+    }
+
+    #[test]
+    fn a_pause_shorter_than_a_break_keeps_counting_towards_the_nudge() {
+        let times = Times::new();
+        let mut breaks = nudging_every(30);
+        breaks.resume(times.instant(0), times.wall(0));
+        breaks.pause(times.instant(20), times.wall(20));
+        breaks.resume(times.instant(24), times.wall(24));
+        assert_eq!(breaks.next_due(times.instant(24), times.wall(24)), Some((times.wall(34), BreakDue::Nudge)));
+    }
+
+    #[test]
+    fn a_pause_of_at_least_min_break_starts_the_nudges_over() {
+        let times = Times::new();
+        let mut breaks = nudging_every(30);
+        breaks.resume(times.instant(0), times.wall(0));
+        assert_eq!(breaks.nudge(times.instant(30)), Duration::minutes(30));
+        breaks.pause(times.instant(40), times.wall(40));
+        breaks.resume(times.instant(45), times.wall(45));
+        assert_eq!(breaks.next_due(times.instant(45), times.wall(45)), Some((times.wall(75), BreakDue::Nudge)));
+    }
+}
@@ -0,0 +1,368 @@
+use serde::Serialize;
+use sqlx::{Pool, Postgres, Row};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Names browsers append to window titles, and the app names they run under. Edge puts a
+/// zero-width space in its name, which is removed before matching.
+const BROWSERS: &[(&str, &[&str])] = &[
+    ("Google Chrome", &["Google Chrome", "Chrome"]),
+    ("Chromium", &["Chromium"]),
+    ("Microsoft Edge", &["Microsoft Edge", "msedge"]),
+    ("Brave", &["Brave Browser", "Brave"]),
+    ("Opera", &["Opera"]),
+    ("Vivaldi", &["Vivaldi"]),
+    ("Firefox", &["Mozilla Firefox", "Firefox Developer Edition", "Firefox Nightly", "Firefox"]),
+    ("LibreWolf", &["LibreWolf"]),
+    ("Waterfox", &["Waterfox"]),
+    ("Tor Browser", &["Tor Browser"]),
+    ("Safari", &["Safari"]),
+];
+
+/// Separators between the page title and the browser name, or between parts of a page title.
+const SEPARATORS: &[&str] = &[" — ", " – ", " - ", " | ", " · "];
+
+/// Sites whose name ends page titles, for when no URL has been seen for a title yet.
+const KNOWN_SITES: &[(&str, &str)] = &[
+    ("youtube", "youtube.com"),
+    ("github", "github.com"),
+    ("gitlab", "gitlab.com"),
+    ("stack overflow", "stackoverflow.com"),
+    ("gmail", "mail.google.com"),
+    ("google docs", "docs.google.com"),
+    ("google sheets", "docs.google.com"),
+    ("google drive", "drive.google.com"),
+    ("slack", "app.slack.com"),
+    ("figma", "figma.com"),
+    ("reddit", "reddit.com"),
+    ("netflix", "netflix.com"),
+    ("wikipedia", "wikipedia.org"),
+    ("linkedin", "linkedin.com"),
+    ("twitch", "twitch.tv"),
+];
+
+/// A browser window's title taken apart.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct BrowserTitle {
+    pub browser: &'static str,
+    /// The title without the browser's name, profile and tab count
+    pub page_title: String,
+}
+
+/// Recognizes Chromium- and Firefox-family titles (`Page - Google Chrome`, `Page — Mozilla Firefox
+/// Private Browsing`, `Page and 3 more pages - Personal - Microsoft Edge`) and windows of known
+/// browser apps whose titles are the bare page title (Safari, Chrome on macOS).
+pub fn parse_title(app_name: &str, title: &str) -> Option<BrowserTitle> {
+    let title = title.replace('\u{200b}', "");
+    let from_title = SEPARATORS.iter().find_map(|separator| {
+        let (page, suffix) = title.rsplit_once(separator)?;
+        browser_named(suffix).map(|browser| (browser, page.to_string()))
+    });
+    let (browser, page_title) = match from_title {
+        Some(parsed) => parsed,
+        None => (browser_named(app_name)?, title.clone()),
+    };
+    Some(BrowserTitle { browser, page_title: strip_tab_details(&page_title) })
+}
+
+// The browser whose name `text` starts with, ignoring what follows it (profiles, private mode)
+fn browser_named(text: &str) -> Option<&'static str> {
+    let text = text.trim().to_lowercase();
+    BROWSERS.iter().find_map(|(browser, names)| {
+        names
+            .iter()
+            .any(|name| {
+                let name = name.to_lowercase();
+                text == name || text.starts_with(&format!("{} ", name))
+            })
+            .then_some(*browser)
+    })
+}
+
+// Removes Edge's profile name and "and N more pages" from a page title
+fn strip_tab_details(page_title: &str) -> String {
+    let mut page_title = page_title.trim();
+    for profile in [" - Personal", " - Work"] {
+        page_title = page_title.strip_suffix(profile).unwrap_or(page_title);
+    }
+    if let Some((page, profile)) = page_title.rsplit_once(" - Profile ") {
+        if profile.chars().all(|c| c.is_ascii_digit()) {
+            page_title = page;
+        }
+    }
+    if let Some((page, more)) = page_title.rsplit_once(" and ") {
+        let words: Vec<&str> = more.split_whitespace().collect();
+        if let [count, "more", "page" | "pages"] = words[..] {
+            if count.chars().all(|c| c.is_ascii_digit()) {
+                page_title = page;
+            }
+        }
+    }
+    page_title.trim().to_string()
+}
+
+/// The site a title points at, for titles that include a URL. Without a scheme, only `www.`
+/// hosts or hosts followed by a path count, so file names like `main.rs` aren't taken for sites.
+pub fn url_domain(title: &str) -> Option<String> {
+    title.split_whitespace().find_map(|word| {
+        let word = word.trim_matches(|c: char| matches!(c, '(' | ')' | '[' | ']' | '"' | '\'' | ',' | '|'));
+        let (url, has_scheme) = match word.strip_prefix("https://").or_else(|| word.strip_prefix("http://")) {
+            Some(url) => (url, true),
+            None => (word, false),
+        };
+        let url = url.to_lowercase();
+        if !has_scheme && !url.starts_with("www.") && !url.contains('/') {
+            return None;
+        }
+        let host = url.split(['/', '?', '#']).next()?.split(':').next()?.trim_start_matches("www.").to_string();
+        let labels: Vec<&str> = host.split('.').collect();
+        let valid = labels.len() >= 2
+            && labels.iter().all(|label| !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+            && labels.last().is_some_and(|tld| tld.len() >= 2 && tld.chars().all(|c| c.is_ascii_alphabetic()));
+        valid.then_some(host)
+    })
+}
+
+// Key a page title is learned under: lower case, without URLs or a leading "(3)" unread count
+fn title_key(page_title: &str) -> String {
+    let without_urls: Vec<&str> = page_title.split_whitespace().filter(|word| url_domain(word).is_none()).collect();
+    let mut key = without_urls.join(" ").to_lowercase();
+    if key.starts_with('(') {
+        if let Some((count, rest)) = key[1..].split_once(") ") {
+            if count.chars().all(|c| c.is_ascii_digit()) {
+                key = rest.to_string();
+            }
+        }
+    }
+    key.trim_end_matches(|c: char| c.is_whitespace() || matches!(c, '-' | '|' | '·' | '—' | '–')).trim().to_string()
+}
+
+// Last part of a page title, usually the site's name ("Pull requests · org/repo · GitHub")
+fn site_name(key: &str) -> Option<&str> {
+    SEPARATORS
+        .iter()
+        .filter_map(|separator| key.rsplit_once(separator).map(|(_, site)| site.trim()))
+        .min_by_key(|site| site.len())
+}
+
+/// Most page titles remembered per organization; older ones are forgotten first.
+const MAX_LEARNED_TITLES: i64 = 5000;
+
+/// Domains of page titles seen with a URL before, so the same pages resolve without one.
+/// Kept in memory and in `learned_title_domains`, per organization since titles can name
+/// customers or documents. Without a signed-in team member nothing is saved.
+#[derive(Default)]
+pub struct TitleDomains {
+    organization_id: Option<i32>,
+    learned: Mutex<HashMap<String, String>>,
+}
+
+impl TitleDomains {
+    pub async fn load(pool: &Pool<Postgres>, organization_id: Option<i32>) -> Result<Self, String> {
+        let Some(org) = organization_id else {
+            return Ok(TitleDomains::default());
+        };
+        // Forget the oldest titles beyond the cap, then load the rest
+        sqlx::query(
+            r#"
+            DELETE FROM learned_title_domains
+            WHERE organization_id = $1 AND title_key NOT IN (
+                SELECT title_key FROM learned_title_domains WHERE organization_id = $1
+                ORDER BY learned_at DESC LIMIT $2
+            )
+            "#,
+        )
+        .bind(org)
+        .bind(MAX_LEARNED_TITLES)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to prune learned title domains: {}", e))?;
+        let rows = sqlx::query("SELECT title_key, domain FROM learned_title_domains WHERE organization_id = $1")
+            .bind(org)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| format!("Failed to load learned title domains: {}", e))?;
+        let learned = rows
+            .iter()
+            .map(|row| {
+                let key: String = row.try_get("title_key").map_err(|e| format!("Failed to get title_key column: {}", e))?;
+                let domain: String = row.try_get("domain").map_err(|e| format!("Failed to get domain column: {}", e))?;
+                Ok((key, domain))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(TitleDomains { organization_id, learned: Mutex::new(learned) })
+    }
+
+    /// Remembers the page title, and the site name ending it, as showing `domain`. Returns the
+    /// entries that are new or changed, to be saved. Once the cap is reached only known titles
+    /// are updated.
+    fn learn(&self, page_title: &str, domain: &str) -> Vec<(String, String)> {
+        let key = title_key(page_title);
+        let keys = std::iter::once(key.as_str()).chain(site_name(&key));
+        let mut learned = self.learned.lock().unwrap();
+        let full = learned.len() as i64 >= MAX_LEARNED_TITLES;
+        let changed: Vec<(String, String)> = keys
+            .filter(|key| !key.is_empty() && learned.get(*key).map(String::as_str) != Some(domain))
+            .filter(|key| !full || learned.contains_key(*key))
+            .map(|key| (key.to_string(), domain.to_string()))
+            .collect();
+        for (key, domain) in &changed {
+            learned.insert(key.clone(), domain.clone());
+        }
+        changed
+    }
+
+    /// The domain of a page title without a URL: learned for the whole title, then for the
+    /// site name ending it, then a well-known site.
+    pub fn lookup(&self, page_title: &str) -> Option<String> {
+        let key = title_key(page_title);
+        let site = site_name(&key).unwrap_or(&key);
+        let learned = self.learned.lock().unwrap();
+        learned
+            .get(&key)
+            .or_else(|| learned.get(site))
+            .cloned()
+            .or_else(|| KNOWN_SITES.iter().find(|(name, _)| *name == site).map(|(_, domain)| domain.to_string()))
+    }
+
+    /// The domain of a browser page: from a URL in its title (learning the title on the way),
+    /// or looked up from titles seen before. A failed save only costs the learning.
+    pub async fn resolve(&self, pool: &Pool<Postgres>, page_title: &str) -> Option<String> {
+        let Some(domain) = url_domain(page_title) else {
+            return self.lookup(page_title);
+        };
+        let changed = self.learn(page_title, &domain);
+        let Some(org) = self.organization_id else {
+            return Some(domain);
+        };
+        for (key, domain) in changed {
+            let saved = sqlx::query(
+                r#"
+                INSERT INTO learned_title_domains (organization_id, title_key, domain, learned_at)
+                VALUES ($1, $2, $3, now())
+                ON CONFLICT (organization_id, title_key) DO UPDATE SET domain = EXCLUDED.domain, learned_at = now()
+                "#,
+            )
+            .bind(org)
+            .bind(&key)
+            .bind(&domain)
+            .execute(pool)
+            .await;
+            if let Err(e) = saved {
+                eprintln!("Warning: failed to save the domain of '{}': {}", key, e);
+            }
+        }
+        Some(domain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(app: &str, title: &str) -> Option<(&'static str, String)> {
+        parse_title(app, title).map(|t| (t.browser, t.page_title))
+    }
+
+    fn page(browser: &'static str, title: &str) -> Option<(&'static str, String)> {
+        Some((browser, title.to_string()))
+    }
+
+    #[test]
+    fn strips_the_browser_name_from_titles() {
+        assert_eq!(parsed("chrome", "Pull requests · org/repo · GitHub - Google Chrome"), page("Google Chrome", "Pull requests · org/repo · GitHub"));
+    }
+
+    #[test]
+    fn strips_private_browsing_suffixes() {
+        assert_eq!(parsed("firefox", "Inbox (3) — Mozilla Firefox Private Browsing"), page("Firefox", "Inbox (3)"));
+    }
+
+    #[test]
+    fn strips_profiles_and_extra_tab_counts() {
+        assert_eq!(
+            parsed("msedge", "Dashboard and 4 more pages - Personal - Microsoft\u{200b} Edge"),
+            page("Microsoft Edge", "Dashboard")
+        );
+    }
+
+    #[test]
+    fn keeps_titles_without_a_browser_suffix() {
+        assert_eq!(parsed("Safari", "Apple"), page("Safari", "Apple"));
+    }
+
+    #[test]
+    fn other_apps_are_not_browsers() {
+        assert_eq!(parsed("Code", "main.rs - crate - Visual Studio Code"), None);
+    }
+
+    #[test]
+    fn finds_the_domain_of_a_url_in_a_title() {
+        assert_eq!(url_domain("Pull requests · https://github.com/org/repo/pulls").as_deref(), Some("github.com"));
+    }
+
+    #[test]
+    fn finds_domains_without_a_scheme_and_drops_www() {
+        assert_eq!(url_domain("(3) www.YouTube.com/watch?v=x").as_deref(), Some("youtube.com"));
+    }
+
+    #[test]
+    fn file_names_and_version_numbers_are_not_domains() {
+        assert_eq!(url_domain("main.rs - crate"), None);
+        assert_eq!(url_domain("v1.2 release notes"), None);
+    }
+
+    // Learned from a title showing the board's URL
+    fn learned_acme_board() -> TitleDomains {
+        let domains = TitleDomains::default();
+        domains.learn("Sprint board | Acme Tracker - https://tracker.acme.io/board/1", "tracker.acme.io");
+        domains
+    }
+
+    #[test]
+    fn unknown_titles_have_no_domain() {
+        assert_eq!(TitleDomains::default().lookup("Sprint board | Acme Tracker"), None);
+    }
+
+    #[test]
+    fn learns_the_page_and_the_site_name() {
+        let domains = TitleDomains::default();
+        let learned = domains.learn("Sprint board | Acme Tracker - https://tracker.acme.io/board/1", "tracker.acme.io");
+        assert_eq!(learned.len(), 2);
+    }
+
+    #[test]
+    fn known_titles_are_not_saved_again() {
+        let domains = learned_acme_board();
+        assert!(domains.learn("(2) Sprint board | Acme Tracker https://tracker.acme.io", "tracker.acme.io").is_empty());
+    }
+
+    #[test]
+    fn a_learned_page_resolves_without_a_url() {
+        assert_eq!(learned_acme_board().lookup("(5) Sprint board | Acme Tracker").as_deref(), Some("tracker.acme.io"));
+    }
+
+    #[test]
+    fn other_pages_of_a_learned_site_resolve() {
+        assert_eq!(learned_acme_board().lookup("Backlog | Acme Tracker").as_deref(), Some("tracker.acme.io"));
+    }
+
+    #[test]
+    fn well_known_sites_resolve_without_learning() {
+        assert_eq!(TitleDomains::default().lookup("Some video - YouTube").as_deref(), Some("youtube.com"));
+    }
+
+    #[test]
+    fn stops_learning_new_titles_at_the_cap() {
+        let domains = TitleDomains::default();
+        let learned: HashMap<String, String> =
+            (0..MAX_LEARNED_TITLES).map(|i| (title_key(&format!("Page {}", i)), "example.com".to_string())).collect();
+        *domains.learned.lock().unwrap() = learned;
+
+        assert!(domains.learn("New page", "example.org").is_empty());
+        assert_eq!(domains.lookup("New page"), None);
+        // Titles already known still follow the site they were last seen on
+        assert_eq!(domains.learn("Page 1", "example.org").len(), 1);
+        assert_eq!(domains.lookup("Page 1").as_deref(), Some("example.org"));
+    }
+}
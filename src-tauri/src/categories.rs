@@ -0,0 +1,322 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, Row};
+use uuid::Uuid;
+
+use crate::authorization;
+use crate::browser;
+use crate::errors::AppError;
+use crate::identity::CurrentUser;
+use crate::workday;
+
+/// Category of focus time no rule matched.
+const UNCATEGORIZED: &str = "uncategorized";
+
+/// How time in a category counts towards the productivity score.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Productivity {
+    Productive,
+    #[default]
+    Neutral,
+    Unproductive,
+}
+
+impl Productivity {
+    fn weight(self) -> f64 {
+        match self {
+            Productivity::Productive => 1.0,
+            Productivity::Neutral => 0.5,
+            Productivity::Unproductive => 0.0,
+        }
+    }
+}
+
+/// Puts a window into a category when every condition that is set matches (case-insensitive).
+/// `domain` matches the site and its subdomains. A rule with no conditions matches nothing.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(default)]
+pub struct CategoryRule {
+    pub app_contains: Option<String>,
+    pub title_contains: Option<String>,
+    pub domain: Option<String>,
+    pub category: String,
+    pub productivity: Productivity,
+}
+
+impl CategoryRule {
+    fn new(category: &str, productivity: Productivity) -> Self {
+        CategoryRule {
+            category: category.to_string(),
+            productivity,
+            ..Default::default()
+        }
+    }
+
+    fn app(category: &str, productivity: Productivity, app: &str) -> Self {
+        CategoryRule {
+            app_contains: Some(app.to_string()),
+            ..CategoryRule::new(category, productivity)
+        }
+    }
+
+    fn domain(category: &str, productivity: Productivity, domain: &str) -> Self {
+        CategoryRule {
+            domain: Some(domain.to_string()),
+            ..CategoryRule::new(category, productivity)
+        }
+    }
+
+    pub fn matches(&self, app_name: &str, title: &str, domain: Option<&str>) -> bool {
+        if self.app_contains.is_none() && self.title_contains.is_none() && self.domain.is_none() {
+            return false;
+        }
+        let contains = |haystack: &str, needle: &Option<String>| {
+            needle
+                .as_ref()
+                .is_none_or(|n| haystack.to_lowercase().contains(&n.to_lowercase()))
+        };
+        let on_site = self.domain.as_ref().is_none_or(|site| {
+            let site = site.to_lowercase();
+            domain.is_some_and(|domain| domain == site || domain.ends_with(&format!(".{}", site)))
+        });
+        contains(app_name, &self.app_contains) && contains(title, &self.title_contains) && on_site
+    }
+}
+
+/// Rules applied when the organization does not configure its own. Domains come first, so a
+/// browser showing a known site isn't just counted as the browser.
+pub fn default_rules() -> Vec<CategoryRule> {
+    use Productivity::*;
+    let domains = [
+        ("development", Productive, &["github.com", "gitlab.com", "bitbucket.org", "stackoverflow.com", "docs.rs"][..]),
+        ("communication", Productive, &["mail.google.com", "outlook.office.com", "slack.com", "teams.microsoft.com"][..]),
+        ("design", Productive, &["figma.com", "canva.com"][..]),
+        ("social", Unproductive, &["facebook.com", "instagram.com", "twitter.com", "x.com", "reddit.com", "tiktok.com"][..]),
+        ("entertainment", Unproductive, &["youtube.com", "netflix.com", "twitch.tv", "primevideo.com"][..]),
+    ];
+    let apps = [
+        ("development", Productive, &["Code", "IntelliJ", "PyCharm", "WebStorm", "Xcode", "Terminal", "iTerm", "Konsole", "Visual Studio"][..]),
+        ("communication", Productive, &["Slack", "Teams", "Zoom", "Outlook", "Thunderbird", "Mail"][..]),
+        ("design", Productive, &["Figma", "Photoshop", "Illustrator", "Sketch", "GIMP", "Inkscape"][..]),
+        ("office", Productive, &["Word", "Excel", "PowerPoint", "LibreOffice", "Notion"][..]),
+        ("entertainment", Unproductive, &["Spotify", "Steam", "VLC"][..]),
+    ];
+    let by_domain = domains.into_iter().flat_map(|(category, productivity, sites)| {
+        sites.iter().map(move |site| CategoryRule::domain(category, productivity, site))
+    });
+    let by_app = apps.into_iter().flat_map(|(category, productivity, names)| {
+        names.iter().map(move |name| CategoryRule::app(category, productivity, name))
+    });
+    by_domain.chain(by_app).collect()
+}
+
+/// The first matching rule's category and rating, or uncategorized and neutral. Without a
+/// stored domain, one is looked for in the title.
+pub fn categorize(rules: &[CategoryRule], app_name: &str, title: &str, domain: Option<&str>) -> (String, Productivity) {
+    let domain = domain.map(str::to_string).or_else(|| browser::url_domain(title));
+    rules
+        .iter()
+        .find(|rule| rule.matches(app_name, title, domain.as_deref()))
+        .map_or((UNCATEGORIZED.to_string(), Productivity::Neutral), |rule| {
+            (rule.category.clone(), rule.productivity)
+        })
+}
+
+/// A stretch of time one window had focus, as sampled by a screenshot.
+#[derive(Debug, Clone)]
+pub struct FocusSample {
+    pub app_name: String,
+    pub title: String,
+    pub domain: Option<String>,
+    pub seconds: f64,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct CategoryTime {
+    pub category: String,
+    pub productivity: Productivity,
+    pub seconds: i64,
+}
+
+/// Focus time by category and the resulting score, for a session or a day.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ProductivityReport {
+    pub focus_seconds: i64,
+    /// 0 (all unproductive) to 100 (all productive), neutral time counting half; `None` without focus time
+    pub score: Option<u32>,
+    /// Most time first
+    pub categories: Vec<CategoryTime>,
+}
+
+pub fn productivity_report(rules: &[CategoryRule], samples: &[FocusSample]) -> ProductivityReport {
+    let mut categories: Vec<(CategoryTime, f64)> = Vec::new();
+    for sample in samples {
+        let (category, productivity) = categorize(rules, &sample.app_name, &sample.title, sample.domain.as_deref());
+        match categories.iter_mut().find(|(time, _)| time.category == category && time.productivity == productivity) {
+            Some((_, seconds)) => *seconds += sample.seconds,
+            None => categories.push((CategoryTime { category, productivity, seconds: 0 }, sample.seconds)),
+        }
+    }
+    let total: f64 = categories.iter().map(|(_, seconds)| seconds).sum();
+    let weighted: f64 = categories.iter().map(|(time, seconds)| time.productivity.weight() * seconds).sum();
+    let mut categories: Vec<CategoryTime> = categories
+        .into_iter()
+        .map(|(time, seconds)| CategoryTime { seconds: seconds.round() as i64, ..time })
+        .collect();
+    categories.sort_by(|a, b| b.seconds.cmp(&a.seconds).then_with(|| a.category.cmp(&b.category)));
+    ProductivityReport {
+        focus_seconds: total.round() as i64,
+        score: (total > 0.0).then(|| (weighted / total * 100.0).round() as u32),
+        categories,
+    }
+}
+
+/// Which sessions' focus time to report on.
+pub enum FocusScope {
+    Session(Uuid),
+    /// Sessions of a team member that started on a local day
+    Day { team_member_id: i32, date: NaiveDate, timezone: Tz },
+}
+
+/// Focus samples of the sessions in `scope`. Each screenshot's focused window counts until the
+/// next screenshot or the end of the session (`now` while it runs), but for at most `max_sample`;
+/// screenshots cut out by a session edit, and older ones without a focused window, don't count.
+pub async fn focus_samples(
+    pool: &Pool<Postgres>,
+    scope: &FocusScope,
+    max_sample: chrono::Duration,
+    now: DateTime<Utc>,
+) -> Result<Vec<FocusSample>, AppError> {
+    let (session_id, team_member_id, from, to) = match scope {
+        FocusScope::Session(session_id) => (Some(*session_id), None, None, None),
+        FocusScope::Day { team_member_id, date, timezone } => {
+            let from = workday::day_start(*date, *timezone);
+            (None, Some(*team_member_id), Some(from), Some(workday::next_day_start(from, *timezone)))
+        }
+    };
+    let rows = sqlx::query(
+        r#"
+        SELECT focused_app, COALESCE(focused_title, '') AS focused_title, focused_domain, seconds FROM (
+            SELECT sc.focused_app, sc.focused_title, sc.focused_domain,
+                   EXTRACT(EPOCH FROM LEAST(
+                       COALESCE(LEAD(sc.capture_time) OVER (PARTITION BY sc.session_id ORDER BY sc.capture_time), s.end_time, $5),
+                       sc.capture_time + make_interval(secs => $6)
+                   ) - sc.capture_time)::float8 AS seconds
+            FROM screenshots sc
+            JOIN sessions s ON s.id = sc.session_id
+            WHERE sc.removed_by_edit IS NULL
+              AND (sc.session_id = $1
+                   OR ($1 IS NULL AND s.team_member_id = $2 AND s.start_time >= $3 AND s.start_time < $4))
+        ) samples
+        WHERE focused_app IS NOT NULL
+        "#,
+    )
+    .bind(session_id)
+    .bind(team_member_id)
+    .bind(from)
+    .bind(to)
+    .bind(now)
+    .bind(max_sample.num_seconds() as f64)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to load focus time: {}", e)))?;
+
+    rows.iter()
+        .map(|row| {
+            Ok(FocusSample {
+                app_name: row.try_get("focused_app").map_err(|e| AppError::Db(format!("Failed to get focused_app column: {}", e)))?,
+                title: row.try_get("focused_title").map_err(|e| AppError::Db(format!("Failed to get focused_title column: {}", e)))?,
+                domain: row.try_get("focused_domain").map_err(|e| AppError::Db(format!("Failed to get focused_domain column: {}", e)))?,
+                seconds: row.try_get::<f64, _>("seconds").map_err(|e| AppError::Db(format!("Failed to get seconds column: {}", e)))?.max(0.0),
+            })
+        })
+        .collect()
+}
+
+/// Replaces the organization's category rules, keeping the rest of its policy. Managers only.
+pub async fn save_rules(pool: &Pool<Postgres>, user: &CurrentUser, rules: &[CategoryRule]) -> Result<(), AppError> {
+    authorization::require_manager(user, "change category rules")?;
+    if rules.iter().any(|rule| rule.category.trim().is_empty()) {
+        return Err(AppError::Invalid("Every category rule needs a category.".to_string()));
+    }
+    let json =
+        serde_json::to_string(rules).map_err(|e| AppError::Db(format!("Failed to serialize category rules: {}", e)))?;
+    sqlx::query(
+        r#"
+        INSERT INTO org_policies (organization_id, capture_policy, updated_at)
+        VALUES ($1, jsonb_build_object('category_rules', $2::jsonb), now())
+        ON CONFLICT (organization_id) DO UPDATE
+        SET capture_policy = org_policies.capture_policy || EXCLUDED.capture_policy, updated_at = now()
+        "#,
+    )
+    .bind(user.organization_id)
+    .bind(json)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to save category rules: {}", e)))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(app: &str, title: &str, domain: Option<&str>, minutes: f64) -> FocusSample {
+        FocusSample {
+            app_name: app.to_string(),
+            title: title.to_string(),
+            domain: domain.map(str::to_string),
+            seconds: minutes * 60.0,
+        }
+    }
+
+    // An hour: 40 minutes productive, 10 unproductive and 10 uncategorized
+    fn hour_of_work() -> Vec<FocusSample> {
+        vec![
+            sample("Code", "main.rs - crate", None, 30.0),
+            sample("Firefox", "gist.github.com/abc - Mozilla Firefox", None, 10.0),
+            sample("Firefox", "Some video - YouTube - Mozilla Firefox", Some("youtube.com"), 10.0),
+            sample("Calculator", "Calculator", None, 10.0),
+        ]
+    }
+
+    #[test]
+    fn a_known_site_counts_rather_than_the_browser() {
+        let (category, productivity) = categorize(&default_rules(), "Firefox", "Some video - YouTube", Some("youtube.com"));
+        assert_eq!((category.as_str(), productivity), ("entertainment", Productivity::Unproductive));
+    }
+
+    #[test]
+    fn a_site_is_found_in_the_title_without_a_stored_domain() {
+        let (category, _) = categorize(&default_rules(), "Firefox", "gist.github.com/abc - Mozilla Firefox", None);
+        assert_eq!(category, "development");
+    }
+
+    #[test]
+    fn unknown_windows_are_uncategorized_and_neutral() {
+        let (category, productivity) = categorize(&default_rules(), "Calculator", "Calculator", None);
+        assert_eq!((category.as_str(), productivity), (UNCATEGORIZED, Productivity::Neutral));
+    }
+
+    #[test]
+    fn the_score_counts_neutral_time_half() {
+        let report = productivity_report(&default_rules(), &hour_of_work());
+        assert_eq!(report.focus_seconds, 3600);
+        // (40 productive + 10 neutral / 2) out of 60 minutes
+        assert_eq!(report.score, Some(75));
+    }
+
+    #[test]
+    fn categories_are_listed_most_time_first() {
+        let report = productivity_report(&default_rules(), &hour_of_work());
+        let categories: Vec<_> = report.categories.iter().map(|time| (time.category.as_str(), time.seconds)).collect();
+        assert_eq!(categories, vec![("development", 2400), ("entertainment", 600), ("uncategorized", 600)]);
+    }
+
+    #[test]
+    fn no_focus_time_has_no_score() {
+        assert_eq!(productivity_report(&default_rules(), &[]).score, None);
+    }
+}
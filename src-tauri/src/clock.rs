@@ -0,0 +1,123 @@
+use chrono::{DateTime, Utc};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// Boxed future returned by the object-safe traits used by the timer.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Source of wall-clock time and sleeping for the timer. Swapped for a
+/// `MockClock` in tests so timing can be driven deterministically.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+    /// Monotonic time. Unlike `now`, it doesn't jump when the clock is set, and on Linux and
+    /// macOS it stands still while the system is suspended.
+    fn instant(&self) -> tokio::time::Instant;
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// The real clock: `Utc::now()` and `tokio::time::sleep`.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn instant(&self) -> tokio::time::Instant {
+        tokio::time::Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// How far the wall clock may drift from monotonic time between two checks before it
+/// counts as a jump. NTP adjustments stay well below this.
+const JUMP_THRESHOLD: chrono::Duration = chrono::Duration::seconds(30);
+
+/// A jump of the wall clock that monotonic time didn't follow: the system was suspended
+/// or the clock was set forwards (`to` after `from`), or the clock was set back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockJump {
+    /// Wall-clock time when the jump happened, as far as monotonic time can tell
+    pub from: DateTime<Utc>,
+    /// Wall-clock time after the jump
+    pub to: DateTime<Utc>,
+}
+
+impl ClockJump {
+    pub fn is_backwards(&self) -> bool {
+        self.to < self.from
+    }
+}
+
+/// Compares wall-clock and monotonic time on each check to notice jumps in between.
+pub struct ClockJumpDetector {
+    wall: DateTime<Utc>,
+    instant: tokio::time::Instant,
+}
+
+impl ClockJumpDetector {
+    pub fn new(clock: &dyn Clock) -> Self {
+        ClockJumpDetector {
+            wall: clock.now(),
+            instant: clock.instant(),
+        }
+    }
+
+    /// The jump since the last check, if any. Check often: a jump is only placed in time
+    /// as precisely as the interval between checks.
+    pub fn check(&mut self, clock: &dyn Clock) -> Option<ClockJump> {
+        let (wall, instant) = (clock.now(), clock.instant());
+        let monotonic = chrono::Duration::from_std(instant - self.instant).unwrap_or(chrono::Duration::MAX);
+        let expected = self.wall + monotonic;
+        self.wall = wall;
+        self.instant = instant;
+        ((wall - expected).abs() > JUMP_THRESHOLD).then_some(ClockJump { from: expected, to: wall })
+    }
+}
+
+/// Clock for tests, driven by tokio's virtual time: wall-clock time starts at a fixed
+/// instant and advances exactly as far as `tokio::time` does. Run tests with
+/// `#[tokio::test(start_paused = true)]` so sleeps complete instantly.
+#[cfg(test)]
+pub struct MockClock {
+    start_wall: DateTime<Utc>,
+    start_instant: tokio::time::Instant,
+    offset: std::sync::Mutex<chrono::Duration>, // Sum of all jumps
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new(start_wall: DateTime<Utc>) -> Self {
+        MockClock {
+            start_wall,
+            start_instant: tokio::time::Instant::now(),
+            offset: std::sync::Mutex::new(chrono::Duration::zero()),
+        }
+    }
+
+    /// Moves wall-clock time without moving monotonic time, like a suspend (forwards)
+    /// or setting the system clock.
+    pub fn jump(&self, by: chrono::Duration) {
+        *self.offset.lock().unwrap() += by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        let elapsed = tokio::time::Instant::now() - self.start_instant;
+        self.start_wall + chrono::Duration::from_std(elapsed).expect("virtual time overflow") + *self.offset.lock().unwrap()
+    }
+
+    fn instant(&self) -> tokio::time::Instant {
+        tokio::time::Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
@@ -0,0 +1,213 @@
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use serde_json::json;
+
+use crate::authorization::Denial;
+
+/// What a command failed with. The frontend receives it as `{code, message, details}` and
+/// branches on `code`, which stays the same while messages are reworded.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AppError {
+    /// Start while the timer is running or paused
+    AlreadyRunning,
+    AlreadyStopped,
+    NotRunning,
+    NotPaused,
+    /// The timer task is still handling an earlier command
+    Busy,
+    /// The timer task is gone; the timer has been reset to stopped
+    TimerUnavailable,
+    /// Tracking refused outside the work schedule, with when the user may start
+    OutsideWorkHours(String),
+    HourLimitReached(String),
+    /// A week already submitted or approved
+    TimesheetLocked(String),
+    /// Input the user can correct, explained in the message
+    Invalid(String),
+    NotFound(String),
+    Unauthorized(Denial),
+    Db(String),
+    /// Taking or saving a screenshot
+    Capture(String),
+    /// Reading or writing the screenshot store
+    Storage(String),
+}
+
+impl AppError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::AlreadyRunning => "AlreadyRunning",
+            AppError::AlreadyStopped => "AlreadyStopped",
+            AppError::NotRunning => "NotRunning",
+            AppError::NotPaused => "NotPaused",
+            AppError::Busy => "Busy",
+            AppError::TimerUnavailable => "TimerUnavailable",
+            AppError::OutsideWorkHours(_) => "OutsideWorkHours",
+            AppError::HourLimitReached(_) => "HourLimitReached",
+            AppError::TimesheetLocked(_) => "TimesheetLocked",
+            AppError::Invalid(_) => "Invalid",
+            AppError::NotFound(_) => "NotFound",
+            AppError::Unauthorized(_) => "Unauthorized",
+            AppError::Db(_) => "Db",
+            AppError::Capture(_) => "Capture",
+            AppError::Storage(_) => "Storage",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            AppError::AlreadyRunning => "Timer is already running or paused.".to_string(),
+            AppError::AlreadyStopped => "Timer is already stopped.".to_string(),
+            AppError::NotRunning => "Timer is not running.".to_string(),
+            AppError::NotPaused => "Timer is not paused.".to_string(),
+            AppError::Busy => "Timer is busy, try again.".to_string(),
+            AppError::TimerUnavailable => "Timer command channel not found, task may have stopped.".to_string(),
+            AppError::Unauthorized(denial) => denial.message().to_string(),
+            // The cause goes in the details; it means little to the user
+            AppError::Db(_) => "Something went wrong with the database.".to_string(),
+            AppError::Capture(_) => "Taking a screenshot failed.".to_string(),
+            AppError::Storage(_) => "Something went wrong with the screenshot store.".to_string(),
+            AppError::OutsideWorkHours(message)
+            | AppError::HourLimitReached(message)
+            | AppError::TimesheetLocked(message)
+            | AppError::Invalid(message)
+            | AppError::NotFound(message) => message.clone(),
+        }
+    }
+
+    fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            AppError::Unauthorized(denial) => Some(json!({ "denial": denial })),
+            AppError::Db(cause) | AppError::Capture(cause) | AppError::Storage(cause) => Some(json!({ "cause": cause })),
+            _ => None,
+        }
+    }
+
+    /// Faults rather than refusals: nothing the user did explains them, so they go to Sentry.
+    pub fn is_unexpected(&self) -> bool {
+        matches!(
+            self,
+            AppError::TimerUnavailable | AppError::Db(_) | AppError::Capture(_) | AppError::Storage(_)
+        )
+    }
+
+    /// Sends the error to Sentry, tagged with its code.
+    pub fn report(&self) {
+        sentry::with_scope(
+            |scope| scope.set_tag("error_code", self.code()),
+            || sentry::capture_message(&self.to_string(), sentry::Level::Error),
+        );
+    }
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            // Logs want the cause
+            AppError::Db(cause) | AppError::Capture(cause) | AppError::Storage(cause) => f.write_str(cause),
+            _ => f.write_str(&self.message()),
+        }
+    }
+}
+
+impl Serialize for AppError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut error = serializer.serialize_struct("AppError", 3)?;
+        error.serialize_field("code", self.code())?;
+        error.serialize_field("message", &self.message())?;
+        error.serialize_field("details", &self.details())?;
+        error.end()
+    }
+}
+
+impl From<Denial> for AppError {
+    fn from(denial: Denial) -> Self {
+        AppError::Unauthorized(denial)
+    }
+}
+
+/// An error on its way out of a command, serialized like the `AppError` it wraps. Converting
+/// into it is the command boundary: unexpected errors are logged and reported to Sentry there,
+/// once per failed command.
+#[derive(Debug)]
+pub struct CommandError(AppError);
+
+impl From<AppError> for CommandError {
+    fn from(error: AppError) -> Self {
+        if error.is_unexpected() {
+            eprintln!("Command failed: {}", error);
+            error.report();
+        }
+        CommandError(error)
+    }
+}
+
+impl From<Denial> for CommandError {
+    fn from(denial: Denial) -> Self {
+        AppError::from(denial).into()
+    }
+}
+
+impl Serialize for CommandError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db_error() -> AppError {
+        AppError::Db("Failed to begin transaction: connection refused".to_string())
+    }
+
+    #[test]
+    fn denials_serialize_with_their_reason() {
+        let denied = serde_json::to_value(AppError::from(Denial::ManagersOnly)).unwrap();
+        assert_eq!(
+            denied,
+            json!({ "code": "Unauthorized", "message": "Only managers can do this.", "details": { "denial": "managers_only" } })
+        );
+    }
+
+    #[test]
+    fn timer_states_serialize_without_details() {
+        let not_paused = serde_json::to_value(AppError::NotPaused).unwrap();
+        assert_eq!(not_paused, json!({ "code": "NotPaused", "message": "Timer is not paused.", "details": null }));
+    }
+
+    #[test]
+    fn faults_keep_their_cause_out_of_the_message() {
+        let db = serde_json::to_value(db_error()).unwrap();
+        assert_eq!(
+            db,
+            json!({
+                "code": "Db",
+                "message": "Something went wrong with the database.",
+                "details": { "cause": "Failed to begin transaction: connection refused" }
+            })
+        );
+    }
+
+    #[test]
+    fn only_faults_are_unexpected() {
+        assert!(db_error().is_unexpected());
+        assert!(!AppError::AlreadyRunning.is_unexpected());
+    }
+
+    #[test]
+    fn logs_get_the_cause() {
+        assert_eq!(db_error().to_string(), "Failed to begin transaction: connection refused");
+    }
+
+    #[test]
+    fn invalid_input_has_its_own_code() {
+        assert_eq!(AppError::Invalid("Task name cannot be empty.".to_string()).code(), "Invalid");
+    }
+
+    #[test]
+    fn command_errors_serialize_like_the_error_they_wrap() {
+        let from_command = serde_json::to_value(CommandError::from(AppError::NotPaused)).unwrap();
+        assert_eq!(from_command, serde_json::to_value(AppError::NotPaused).unwrap());
+    }
+}
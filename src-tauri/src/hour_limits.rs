@@ -0,0 +1,450 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, Row};
+
+use crate::authorization::require_manager_of;
+use crate::errors::AppError;
+use crate::identity::CurrentUser;
+use crate::workday;
+
+/// A team member's targets and limits on worked time. Soft limits only warn; reaching a hard
+/// limit stops the timer. Weeks start on Monday.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct HourLimits {
+    pub daily_soft_minutes: Option<u32>,
+    pub daily_hard_minutes: Option<u32>,
+    pub weekly_soft_minutes: Option<u32>,
+    pub weekly_hard_minutes: Option<u32>,
+    /// Hours owed per week, spread evenly over `contracted_days`; the basis for overtime
+    pub weekly_contracted_minutes: Option<u32>,
+    pub contracted_days: Vec<Weekday>,
+    /// Share of each limit at which a warning is sent
+    pub warn_at_percent: Vec<u32>,
+}
+
+impl Default for HourLimits {
+    fn default() -> Self {
+        HourLimits {
+            daily_soft_minutes: None,
+            daily_hard_minutes: None,
+            weekly_soft_minutes: None,
+            weekly_hard_minutes: None,
+            weekly_contracted_minutes: None,
+            contracted_days: vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri],
+            warn_at_percent: vec![80, 100],
+        }
+    }
+}
+
+impl HourLimits {
+    pub fn validated(self) -> Result<Self, AppError> {
+        let pairs = [
+            ("daily", self.daily_soft_minutes, self.daily_hard_minutes),
+            ("weekly", self.weekly_soft_minutes, self.weekly_hard_minutes),
+        ];
+        for (period, soft, hard) in pairs {
+            if soft == Some(0) || hard == Some(0) {
+                return Err(AppError::Invalid(format!("The {} limits must be at least one minute.", period)));
+            }
+            if let (Some(soft), Some(hard)) = (soft, hard) {
+                if soft > hard {
+                    return Err(AppError::Invalid(format!("The {} soft limit cannot be above the hard limit.", period)));
+                }
+            }
+        }
+        if self.weekly_contracted_minutes.is_some() && self.contracted_days.is_empty() {
+            return Err(AppError::Invalid("Contracted hours need at least one contracted day.".to_string()));
+        }
+        if self.warn_at_percent.iter().any(|percent| !(1..=100).contains(percent)) {
+            return Err(AppError::Invalid("Warning thresholds must be between 1 and 100 percent.".to_string()));
+        }
+        Ok(self)
+    }
+
+    // Contracted minutes on a day, an even share of the week's on contracted days
+    fn contracted_on(&self, date: NaiveDate) -> i64 {
+        match self.weekly_contracted_minutes {
+            Some(weekly) if self.contracted_days.contains(&date.weekday()) => {
+                i64::from(weekly) / self.contracted_days.len() as i64
+            }
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Period {
+    Day,
+    Week,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitKind {
+    Soft,
+    Hard,
+}
+
+/// How far one limit has been used up.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct LimitProgress {
+    pub limit_minutes: u32,
+    /// Never below zero
+    pub remaining_minutes: i64,
+    pub percent: u32,
+}
+
+impl LimitProgress {
+    fn new(limit_minutes: u32, worked_minutes: i64) -> Self {
+        LimitProgress {
+            limit_minutes,
+            remaining_minutes: (i64::from(limit_minutes) - worked_minutes).max(0),
+            percent: (worked_minutes.max(0) * 100 / i64::from(limit_minutes.max(1))) as u32,
+        }
+    }
+}
+
+/// Worked time in the current day or week, against its limits and contracted hours.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct PeriodProgress {
+    pub period: Period,
+    /// First day of the period
+    pub start_date: NaiveDate,
+    pub worked_minutes: i64,
+    pub soft: Option<LimitProgress>,
+    pub hard: Option<LimitProgress>,
+    /// Contracted minutes up to and including today
+    pub contracted_minutes: i64,
+    /// Worked minus contracted minutes; negative when behind
+    pub overtime_minutes: i64,
+}
+
+impl PeriodProgress {
+    fn new(
+        period: Period,
+        start_date: NaiveDate,
+        worked_minutes: i64,
+        contracted_minutes: i64,
+        soft: Option<u32>,
+        hard: Option<u32>,
+    ) -> Self {
+        PeriodProgress {
+            period,
+            start_date,
+            worked_minutes,
+            soft: soft.map(|limit| LimitProgress::new(limit, worked_minutes)),
+            hard: hard.map(|limit| LimitProgress::new(limit, worked_minutes)),
+            contracted_minutes,
+            overtime_minutes: worked_minutes - contracted_minutes,
+        }
+    }
+
+    fn limit(&self, kind: LimitKind) -> Option<&LimitProgress> {
+        match kind {
+            LimitKind::Soft => self.soft.as_ref(),
+            LimitKind::Hard => self.hard.as_ref(),
+        }
+    }
+}
+
+/// Worked time and overtime on one day of the week.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct DayOvertime {
+    pub date: NaiveDate,
+    pub worked_minutes: i64,
+    pub contracted_minutes: i64,
+    pub overtime_minutes: i64,
+}
+
+/// Progress towards the limits, as shown to the user.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct HourProgress {
+    pub today: PeriodProgress,
+    pub week: PeriodProgress,
+    /// Monday through today
+    pub days: Vec<DayOvertime>,
+}
+
+impl HourProgress {
+    /// The period whose hard limit has been reached, if any.
+    pub fn hard_limit_reached(&self) -> Option<Period> {
+        [&self.today, &self.week]
+            .into_iter()
+            .find(|progress| progress.hard.as_ref().is_some_and(|hard| hard.remaining_minutes == 0))
+            .map(|progress| progress.period)
+    }
+}
+
+/// A warning threshold of a limit that worked time has reached.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct LimitWarning {
+    pub period: Period,
+    pub kind: LimitKind,
+    pub percent: u32,
+    pub worked_minutes: i64,
+    pub limit_minutes: u32,
+}
+
+impl LimitWarning {
+    /// Identifies the warning within its period, so each one is only sent once.
+    pub fn key(&self, progress: &HourProgress) -> (NaiveDate, Period, LimitKind, u32) {
+        let start_date = match self.period {
+            Period::Day => progress.today.start_date,
+            Period::Week => progress.week.start_date,
+        };
+        (start_date, self.period, self.kind, self.percent)
+    }
+}
+
+/// Every threshold reached so far, lowest first within each limit.
+pub fn reached_thresholds(limits: &HourLimits, progress: &HourProgress) -> Vec<LimitWarning> {
+    let mut percents = limits.warn_at_percent.clone();
+    percents.sort_unstable();
+    let mut warnings = Vec::new();
+    for period in [&progress.today, &progress.week] {
+        for kind in [LimitKind::Soft, LimitKind::Hard] {
+            let Some(limit) = period.limit(kind) else {
+                continue;
+            };
+            warnings.extend(percents.iter().filter(|percent| limit.percent >= **percent).map(|percent| LimitWarning {
+                period: period.period,
+                kind,
+                percent: *percent,
+                worked_minutes: period.worked_minutes,
+                limit_minutes: limit.limit_minutes,
+            }));
+        }
+    }
+    warnings
+}
+
+/// Builds the progress for the week containing `today` from the time worked on each day.
+pub fn progress(limits: &HourLimits, worked: &[(NaiveDate, Duration)], today: NaiveDate) -> HourProgress {
+    let monday = workday::week_start(today);
+    let days: Vec<DayOvertime> = monday
+        .iter_days()
+        .take_while(|date| *date <= today)
+        .map(|date| {
+            let worked_minutes = worked
+                .iter()
+                .filter(|(day, _)| *day == date)
+                .map(|(_, worked)| *worked)
+                .fold(Duration::zero(), |total, worked| total + worked)
+                .num_minutes();
+            let contracted_minutes = limits.contracted_on(date);
+            DayOvertime { date, worked_minutes, contracted_minutes, overtime_minutes: worked_minutes - contracted_minutes }
+        })
+        .collect();
+    let today_overtime = days.last().expect("the week includes today");
+    let today_progress = PeriodProgress::new(
+        Period::Day,
+        today,
+        today_overtime.worked_minutes,
+        today_overtime.contracted_minutes,
+        limits.daily_soft_minutes,
+        limits.daily_hard_minutes,
+    );
+    let week_progress = PeriodProgress::new(
+        Period::Week,
+        monday,
+        days.iter().map(|day| day.worked_minutes).sum(),
+        days.iter().map(|day| day.contracted_minutes).sum(),
+        limits.weekly_soft_minutes,
+        limits.weekly_hard_minutes,
+    );
+    HourProgress { today: today_progress, week: week_progress, days }
+}
+
+/// Time worked on each local day of the current week, from the stored sessions minus their
+/// pauses. A session counts towards the day it started on; sessions are split at local midnight.
+/// The running session and an open pause count up to `now`.
+pub async fn worked_this_week(
+    pool: &Pool<Postgres>,
+    team_member_id: i32,
+    now: DateTime<Utc>,
+    tz: Tz,
+) -> Result<Vec<(NaiveDate, Duration)>, AppError> {
+    let from = workday::day_start(workday::week_start(now.with_timezone(&tz).date_naive()), tz);
+    let rows = sqlx::query(
+        r#"
+        SELECT s.start_time,
+               EXTRACT(EPOCH FROM (COALESCE(s.end_time, $3) - s.start_time))::float8 AS seconds,
+               COALESCE((
+                   SELECT SUM(EXTRACT(EPOCH FROM (COALESCE(p.end_time, $3) - p.start_time)))
+                   FROM pause_segments p
+                   WHERE p.session_id = s.id
+               ), 0)::float8 AS paused_seconds
+        FROM sessions s
+        WHERE s.team_member_id = $1 AND s.start_time >= $2 AND s.start_time <= $3
+        "#,
+    )
+    .bind(team_member_id)
+    .bind(from)
+    .bind(now)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to load this week's sessions: {}", e)))?;
+
+    rows.iter()
+        .map(|row| {
+            let start_time: DateTime<Utc> =
+                row.try_get("start_time").map_err(|e| AppError::Db(format!("Failed to get start_time column: {}", e)))?;
+            let seconds: f64 = row.try_get("seconds").map_err(|e| AppError::Db(format!("Failed to get seconds column: {}", e)))?;
+            let paused: f64 =
+                row.try_get("paused_seconds").map_err(|e| AppError::Db(format!("Failed to get paused_seconds column: {}", e)))?;
+            let worked = Duration::seconds((seconds - paused).max(0.0) as i64);
+            Ok((start_time.with_timezone(&tz).date_naive(), worked))
+        })
+        .collect()
+}
+
+/// The team member's limits, or `None` if they have none.
+pub async fn load_limits(pool: &Pool<Postgres>, team_member_id: i32) -> Result<Option<HourLimits>, AppError> {
+    let row = sqlx::query("SELECT limits::text AS limits FROM hour_limits WHERE team_member_id = $1")
+        .bind(team_member_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::Db(format!("Failed to load hour limits: {}", e)))?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let json: String = row.try_get("limits").map_err(|e| AppError::Db(format!("Failed to get limits column: {}", e)))?;
+    serde_json::from_str(&json)
+        .map(Some)
+        .map_err(|e| AppError::Db(format!("Invalid hour limits JSON: {}", e)))
+}
+
+/// Sets or (with `None`) removes a team member's limits. Only managers of their organization may.
+pub async fn save_limits(
+    pool: &Pool<Postgres>,
+    user: &CurrentUser,
+    team_member_id: i32,
+    limits: Option<HourLimits>,
+) -> Result<(), AppError> {
+    require_manager_of(pool, user, "set hour limits", team_member_id).await?;
+    match limits {
+        Some(limits) => {
+            let json = serde_json::to_string(&limits.validated()?)
+                .map_err(|e| AppError::Db(format!("Failed to serialize hour limits: {}", e)))?;
+            sqlx::query(
+                r#"
+                INSERT INTO hour_limits (team_member_id, limits, updated_at)
+                VALUES ($1, $2::jsonb, now())
+                ON CONFLICT (team_member_id) DO UPDATE SET limits = EXCLUDED.limits, updated_at = now()
+                "#,
+            )
+            .bind(team_member_id)
+            .bind(json)
+            .execute(pool)
+            .await
+            .map_err(|e| AppError::Db(format!("Failed to save hour limits: {}", e)))?;
+        }
+        None => {
+            sqlx::query("DELETE FROM hour_limits WHERE team_member_id = $1")
+                .bind(team_member_id)
+                .execute(pool)
+                .await
+                .map_err(|e| AppError::Db(format!("Failed to remove hour limits: {}", e)))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 8 hours a day with a 10 hour hard limit, 40 hours a week
+    fn limits() -> HourLimits {
+        HourLimits {
+            daily_soft_minutes: Some(480),
+            daily_hard_minutes: Some(600),
+            weekly_hard_minutes: Some(2400),
+            weekly_contracted_minutes: Some(2400),
+            ..HourLimits::default()
+        }
+        .validated()
+        .unwrap()
+    }
+
+    // The 6th is a Monday
+    fn date(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 5, d).unwrap()
+    }
+
+    // Thursday of a week with 9 hours on Monday, 7 on Tuesday, none on Wednesday and 10 so far today
+    fn thursday() -> HourProgress {
+        let worked = [
+            (date(5), Duration::hours(3)), // Last week
+            (date(6), Duration::hours(9)),
+            (date(7), Duration::hours(5)),
+            (date(7), Duration::hours(2)),
+            (date(9), Duration::hours(10)),
+        ];
+        progress(&limits(), &worked, date(9))
+    }
+
+    #[test]
+    fn overtime_is_time_worked_beyond_the_contracted_day() {
+        let overtime: Vec<_> = thursday().days.iter().map(|day| day.overtime_minutes).collect();
+        assert_eq!(overtime, vec![60, -60, -480, 120]);
+    }
+
+    #[test]
+    fn the_week_counts_only_its_own_days() {
+        assert_eq!(thursday().week.worked_minutes, 26 * 60);
+    }
+
+    #[test]
+    fn weekly_overtime_counts_the_days_so_far() {
+        assert_eq!(thursday().week.overtime_minutes, -360);
+    }
+
+    #[test]
+    fn nothing_is_left_of_a_passed_soft_limit() {
+        assert_eq!(thursday().today.soft.as_ref().unwrap().remaining_minutes, 0);
+    }
+
+    #[test]
+    fn the_daily_hard_limit_is_reached() {
+        assert_eq!(thursday().hard_limit_reached(), Some(Period::Day));
+    }
+
+    #[test]
+    fn reached_thresholds_are_listed_in_order() {
+        let reached: Vec<_> = reached_thresholds(&limits(), &thursday())
+            .into_iter()
+            .map(|warning| (warning.period, warning.kind, warning.percent))
+            .collect();
+        assert_eq!(
+            reached,
+            vec![
+                (Period::Day, LimitKind::Soft, 80),
+                (Period::Day, LimitKind::Soft, 100),
+                (Period::Day, LimitKind::Hard, 80),
+                (Period::Day, LimitKind::Hard, 100),
+            ]
+        );
+    }
+
+    #[test]
+    fn all_of_a_day_outside_the_contract_is_overtime() {
+        // Saturday isn't a contracted day
+        let saturday = progress(&limits(), &[(date(11), Duration::hours(1))], date(11));
+        assert_eq!(saturday.today.overtime_minutes, 60);
+    }
+
+    #[test]
+    fn a_short_day_reaches_no_hard_limit() {
+        let saturday = progress(&limits(), &[(date(11), Duration::hours(1))], date(11));
+        assert_eq!(saturday.hard_limit_reached(), None);
+    }
+
+    #[test]
+    fn a_soft_limit_above_the_hard_limit_is_invalid() {
+        assert!(HourLimits { daily_soft_minutes: Some(700), ..limits() }.validated().is_err());
+    }
+}
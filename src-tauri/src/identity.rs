@@ -0,0 +1,73 @@
+use serde::Serialize;
+use sqlx::{Pool, Postgres, Row};
+
+/// Mirrors the `TeamMemberRole` enum of the web app's schema.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TeamMemberRole {
+    Employee,
+    Manager,
+    Admin,
+}
+
+impl TeamMemberRole {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "employee" => Some(TeamMemberRole::Employee),
+            "manager" => Some(TeamMemberRole::Manager),
+            "admin" => Some(TeamMemberRole::Admin),
+            _ => None,
+        }
+    }
+
+    /// Managers review their team's time; admins can do anything a manager can.
+    pub fn is_manager(self) -> bool {
+        matches!(self, TeamMemberRole::Manager | TeamMemberRole::Admin)
+    }
+}
+
+/// The team member using this install.
+#[derive(Serialize, Clone, Debug)]
+pub struct CurrentUser {
+    pub team_member_id: i32,
+    pub organization_id: i32,
+    pub email: String,
+    pub role: TeamMemberRole,
+}
+
+/// Email of the team member using this install, from `TEAM_MEMBER_EMAIL`. Whoever installs the
+/// app sets it, and the same `.env` holds `DATABASE_URL`, so it can't stop someone who edits
+/// that file: the role checks built on it are advisory, trusting the installer.
+pub fn team_member_email_from_env() -> Option<String> {
+    std::env::var("TEAM_MEMBER_EMAIL")
+        .ok()
+        .map(|email| email.trim().to_lowercase())
+        .filter(|email| !email.is_empty())
+}
+
+/// Looks the team member up by email. `None` if no email is configured or nobody matches.
+pub async fn load_current_user(pool: &Pool<Postgres>, email: Option<&str>) -> Result<Option<CurrentUser>, String> {
+    let Some(email) = email else {
+        return Ok(None);
+    };
+    let row = sqlx::query(
+        r#"SELECT id, "organizationId", role::text AS role, email FROM team_members WHERE lower(email) = $1"#,
+    )
+    .bind(email)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("Failed to load team member '{}': {}", email, e))?;
+
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let role: String = row.try_get("role").map_err(|e| format!("Failed to get role column: {}", e))?;
+    Ok(Some(CurrentUser {
+        team_member_id: row.try_get("id").map_err(|e| format!("Failed to get id column: {}", e))?,
+        organization_id: row
+            .try_get("organizationId")
+            .map_err(|e| format!("Failed to get organizationId column: {}", e))?,
+        email: row.try_get("email").map_err(|e| format!("Failed to get email column: {}", e))?,
+        role: TeamMemberRole::parse(&role).ok_or_else(|| format!("Unknown team member role '{}'", role))?,
+    }))
+}
@@ -2,61 +2,154 @@
 
 use base64::{engine::general_purpose::STANDARD as BASE64_STANDARD, Engine as _}; // For Base64 encoding
 use chrono::Utc;
+use chrono_tz::Tz;
 use image::{codecs::png::PngEncoder, ImageBuffer, Rgba}; // Added for PNG encoding, ImageBuffer, Rgba
 use image::ImageEncoder; // Added for PNG encoding
-use rand::Rng;
 use xcap::{Monitor, Window}; // Replaced screenshots::Screen with xcap types
 use sqlx::postgres::PgPoolOptions;
 use sqlx::{Pool, Postgres};
-use std::fs; // Added for file system operations
 use std::io::Cursor; // Added for writing PNG to buffer
-use std::path::PathBuf; // Added for path manipulation
 use std::sync::{atomic::{AtomicBool, Ordering}, Arc}; // Added AtomicBool and Ordering
 use std::time::Duration; // Removed SystemTime import
 use tauri::async_runtime::Mutex;
 use tauri::{AppHandle, Emitter, Manager, State}; // Added Manager back
-use tokio::sync::mpsc::{self, Sender};
-use tokio::time::sleep;
+use tokio::sync::mpsc::{self, error::TrySendError, Sender};
 use uuid::Uuid;
 
 mod activity_monitor; // Declare the new module
-use crate::activity_monitor::{ActivityCounters, ActivityData, listen as activity_listen, get_current_counts}; // Import items
-
-// Represents the possible states of the timer/screenshot task
-#[derive(Clone, serde::Serialize, Debug, PartialEq)]
-enum TimerCommand {
-    Pause,
-    Resume,
-    Stop,
-}
-
-// Represents the current status of the timer
-#[derive(Clone, serde::Serialize, Debug, PartialEq)]
-enum TimerStatus {
-    Stopped,
-    Running,
-    Paused,
-}
+mod authorization;
+mod errors;
+mod blur;
+mod breaks;
+mod browser;
+mod categories;
+mod clock;
+mod hour_limits;
+mod identity;
+mod manual_entries;
+mod object_store;
+mod pauses;
+mod perceptual_hash;
+mod policy;
+mod project_suggestions;
+mod redaction;
+mod rng;
+mod scheduler;
+mod screenshot_store;
+mod sealed_original;
+mod session_edits;
+mod sleep_monitor;
+mod task_segments;
+#[cfg(test)]
+mod test_db;
+mod timer;
+mod timer_state;
+mod timesheets;
+mod work_schedule;
+mod workday;
+use crate::activity_monitor::{ActivityCounters, ActivityData, UntrackedActivity, listen as activity_listen, get_current_counts}; // Import items
+use crate::authorization::Denial;
+use crate::blur::BlurLevel;
+use crate::breaks::{BreakTracker, PomodoroSettings, PomodoroState};
+use crate::browser::TitleDomains;
+use crate::categories::{CategoryRule, FocusScope, ProductivityReport};
+use crate::clock::{BoxFuture, Clock, ClockJump, SystemClock};
+use crate::errors::{AppError, CommandError};
+use crate::hour_limits::{HourLimits, HourProgress, Period};
+use crate::identity::{load_current_user, team_member_email_from_env, CurrentUser};
+use crate::manual_entries::{ManualEntryInput, ManualEntryStatus, ManualTimeEntry};
+use crate::redaction::{MonitorRect, WindowRect};
+use crate::pauses::{OpenPause, PauseReason, PausedTime};
+use crate::rng::{system_rng, SharedRng};
+use crate::scheduler::SlotScheduler;
+use crate::task_segments::{close_segment, open_segment, OpenSegment};
+use crate::timesheets::{Timesheet, TimesheetEvent, TimesheetStatus, TimesheetSummary};
+use crate::timer::{elapsed_seconds, timer_task, SessionLimits, TimerCommand, TimerHost};
+use crate::timer_state::{
+    persist_transition, recover_open_sessions, TimerStateMachine, TimerStatus, Transition, TransitionObserver,
+    TransitionReason,
+};
+use crate::object_store::{migrate_inline_screenshots, S3Config, S3ScreenshotStore};
+use crate::policy::{load_capture_policy, CapturePolicy, DuplicateScreenshotPolicy};
+use crate::project_suggestions::ProjectSuggestion;
+use crate::session_edits::{SessionEdit, SessionSnapshot};
+use crate::work_schedule::{OutsideHours, ScheduleEnd, WorkSchedule};
+use crate::sleep_monitor::SleepLog;
+use crate::screenshot_store::{LocalScreenshotStore, PurgeReport, ScreenshotStorage};
 
 // The application state shared across Tauri commands
 struct AppState {
     db_pool: Pool<Postgres>,
-    timer_status: Arc<Mutex<TimerStatus>>,
+    timer: Arc<TimerStateMachine>, // Owns the timer status; every change is validated and logged there
     // Channel to send commands (Pause, Resume, Stop) to the running timer task
     command_tx: Arc<Mutex<Option<Sender<TimerCommand>>>>,
     current_session_id: Arc<Mutex<Option<Uuid>>>, // Added
     session_start_time: Arc<Mutex<Option<chrono::DateTime<Utc>>>>, // Added to track start time for elapsed calculation
     activity_counters: Arc<ActivityCounters>, // Added for activity monitoring
+    current_segment: Arc<Mutex<Option<OpenSegment>>>, // Task being worked on; switch_task replaces it within the session
+    session_base_counts: Arc<Mutex<ActivityData>>, // Counters when the current session row began; they keep running across midnight splits
+    current_pause: Arc<Mutex<Option<OpenPause>>>, // The user's pause while the timer is paused
+    paused_time: Arc<Mutex<PausedTime>>, // Left out of the elapsed time: pauses, suspends, clock changes
+    sleep_log: Arc<SleepLog>, // logind's suspend/resume signals, where available
+    pomodoro_settings: Arc<Mutex<Option<PomodoroSettings>>>, // Pomodoro mode, if the user turned it on
+    pomodoro_state: Arc<std::sync::Mutex<Option<PomodoroState>>>, // Set by the timer task, which can't wait for an async lock there
     is_session_active: Arc<AtomicBool>, // Flag to control activity counting
+    untracked_activity: Arc<UntrackedActivity>, // Input while stopped, in memory only; drives the start reminder
+    screenshot_store: Arc<ScreenshotStorage>, // Local content-addressed files, plus the S3 bucket if configured
+    current_user: Option<CurrentUser>, // Team member using this install, from TEAM_MEMBER_EMAIL
+    capture_policy: Arc<Mutex<CapturePolicy>>, // Refreshed from the organization's policy on every start
+    title_domains: Arc<TitleDomains>, // Domains of browser page titles, learned from titles that showed a URL
+    work_schedule: Arc<Mutex<Option<WorkSchedule>>>, // The user's working hours, refreshed on every start; None if unrestricted
+    hour_limits: Arc<Mutex<Option<HourLimits>>>, // The user's daily and weekly limits, refreshed on every start
+    clock: Arc<dyn Clock>, // Replaced by a mock clock in tests
+    rng: SharedRng, // Randomness for screenshot scheduling
+    timezone: Tz, // Local timezone; sessions are split at its midnight
+}
+
+// The last capture that stored an image, used to detect an unchanged screen
+#[derive(Clone, Debug)]
+struct LastCapture {
+    image_id: Uuid, // Row holding the image (markers point here)
+    phash: u64,
+    unchanged_since: chrono::DateTime<Utc>, // Start of the current run of unchanged captures
+}
+
+// Screen-change score between a capture and the one before it
+#[derive(Clone, serde::Serialize, Debug)]
+struct ScreenChangeScore {
+    screenshot_id: String,
+    capture_time: chrono::DateTime<Utc>,
+    change_score: Option<f32>, // 0.0 = identical, 1.0 = completely different; None for the first capture
+    is_duplicate: bool,
 }
 
 
-// Function to capture a screenshot, gather system info, and save everything
+// Encodes a captured image as PNG
+fn encode_png(image: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> Result<Vec<u8>, String> {
+    let mut png_buffer = Cursor::new(Vec::new());
+    let encoder = PngEncoder::new(&mut png_buffer);
+    encoder
+        .write_image(
+            image.as_raw(), // Use as_raw() for the underlying buffer
+            image.width(),
+            image.height(),
+            image::ColorType::Rgba8.into() // Ensure .into() is present
+        )
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    Ok(png_buffer.into_inner()) // Get the Vec<u8>
+}
+
+// Function to capture a screenshot, gather system info, and save everything.
+// Returns the capture that later screenshots should be compared against.
 async fn capture_and_save(
     db_pool: &Pool<Postgres>,
+    store: &ScreenshotStorage,
+    policy: &CapturePolicy,
+    previous: Option<&LastCapture>,
     session_id: Uuid,
     app_handle: &AppHandle, // Added for emitting event
-) -> Result<(), String> {
+    clock: &dyn Clock,
+) -> Result<LastCapture, String> {
     // --- Gather System Info using xcap ---
     let monitors = Monitor::all().map_err(|e| format!("Failed to get monitors: {}", e))?;
     let monitor_count = monitors.len() as i32; // Cast usize to i32 for DB
@@ -78,44 +171,147 @@ async fn capture_and_save(
             }
         })
         .collect();
+    // Geometry of visible windows, used to redact sensitive ones from the image
+    let window_rects: Vec<WindowRect> = windows
+        .iter()
+        .filter(|w| !w.is_minimized())
+        .map(|w| WindowRect {
+            app_name: w.app_name().to_string(),
+            title: w.title().to_string(),
+            x: w.x(),
+            y: w.y(),
+            width: w.width(),
+            height: w.height(),
+        })
+        .collect();
+    // The window in front, which xcap lists first. Its title isn't kept if a redaction rule hides it.
+    let focused_window = window_rects.iter().find(|w| !w.title.is_empty());
+    let focused_app = focused_window.map(|w| w.app_name.clone());
+    let focused_title = focused_window
+        .filter(|w| !policy.redaction_rules.iter().any(|rule| rule.matches(&w.app_name, &w.title)))
+        .map(|w| w.title.clone());
+    // Browser and site of the window in front, so reports can go by domain
+    let focused_page = focused_window.and_then(|w| browser::parse_title(&w.app_name, &w.title));
+    let focused_domain = match (&focused_page, &focused_title) {
+        (Some(page), Some(_)) => app_handle.state::<AppState>().title_domains.resolve(db_pool, &page.page_title).await,
+        _ => None,
+    };
+    let focused_browser = focused_page.map(|page| page.browser);
     // --- End Gather System Info ---
 
     // Capture the primary monitor (or the first one found)
     if let Some(monitor) = monitors.first() {
         println!("Capturing monitor: {}", monitor.name());
-        let image: ImageBuffer<Rgba<u8>, Vec<u8>> = monitor // xcap returns ImageBuffer
+        let mut image: ImageBuffer<Rgba<u8>, Vec<u8>> = monitor // xcap returns ImageBuffer
             .capture_image()
             .map_err(|e| format!("Failed to capture screen using xcap: {}", e))?;
 
-        // Encode as PNG
-        let mut png_buffer = Cursor::new(Vec::new());
-        let encoder = PngEncoder::new(&mut png_buffer);
-        encoder
-            .write_image(
-                image.as_raw(), // Use as_raw() for the underlying buffer
-                image.width(),
-                image.height(),
-                image::ColorType::Rgba8.into() // Ensure .into() is present
-            )
-            .map_err(|e| format!("Failed to encode PNG: {}", e))?;
-        let buffer_data = png_buffer.into_inner(); // Get the Vec<u8>
+        // Hide password managers and other sensitive windows before anything else sees the pixels
+        let monitor_rect = MonitorRect { x: monitor.x(), y: monitor.y(), width: monitor.width() };
+        let redacted_regions = redaction::redact_windows(&mut image, monitor_rect, &window_rects, &policy.redaction_rules);
+        if !redacted_regions.is_empty() {
+            println!("Redacted {} sensitive window region(s).", redacted_regions.len());
+        }
+        let redacted_regions_json = serde_json::to_string(&redacted_regions)
+            .map_err(|e| format!("Failed to serialize redacted regions: {}", e))?;
 
-        let capture_time = Utc::now();
+        let capture_time = clock.now();
         let screenshot_id = Uuid::new_v4();
 
-        // Insert into DB including new fields, using query() function (runtime check)
+        // --- Compare with the previous capture using a perceptual hash ---
+        let phash = perceptual_hash::dhash(&image);
+        let change_score = previous.map(|p| perceptual_hash::change_score(p.phash, phash));
+        let unchanged = previous
+            .filter(|p| perceptual_hash::distance(p.phash, phash) <= policy.duplicate_threshold);
+
+        if let (Some(prev), DuplicateScreenshotPolicy::Marker) = (unchanged, policy.duplicate_screenshots) {
+            // Store a lightweight marker pointing at the earlier image instead of a new PNG
+            sqlx::query(
+                r#"
+                INSERT INTO screenshots (id, session_id, capture_time, monitor_count, open_windows, perceptual_hash, change_score, is_duplicate, duplicate_of, unchanged_since, blur_level, focused_app, focused_title, focused_browser, focused_domain)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, TRUE, $8, $9, $10, $11, $12, $13, $14)
+                "#
+            )
+            .bind(screenshot_id)
+            .bind(session_id)
+            .bind(capture_time)
+            .bind(monitor_count)
+            .bind(&open_windows)
+            .bind(phash as i64) // BIGINT, bit pattern preserved
+            .bind(change_score)
+            .bind(prev.image_id)
+            .bind(prev.unchanged_since)
+            .bind(policy.blur_level.as_str())
+            .bind(&focused_app)
+            .bind(&focused_title)
+            .bind(focused_browser)
+            .bind(&focused_domain)
+            .execute(db_pool)
+            .await
+            .map_err(|e| format!("Failed to insert unchanged-screen marker into DB: {}", e))?;
+
+            println!("Screen unchanged since {}, stored marker {} for session: {}", prev.unchanged_since, screenshot_id, session_id);
+            app_handle
+                .emit("new_screenshot", screenshot_id.to_string())
+                .map_err(|e| format!("Failed to emit new_screenshot event: {}", e))?;
+            return Ok(prev.clone()); // Keep comparing against the stored image
+        }
+        let unchanged_since = unchanged.map_or(capture_time, |p| p.unchanged_since);
+        // --- End comparison ---
+
+        // --- Apply the organization's blur level before encoding ---
+        // If configured, an unblurred copy is kept, encrypted so only admins can open it
+        let sealed_copy = match &policy.unblurred_copy_public_key {
+            Some(public_key) if policy.blur_level != BlurLevel::None => {
+                Some(sealed_original::seal(public_key, &encode_png(&image)?)?)
+            }
+            _ => None,
+        };
+        blur::apply_blur(&mut image, policy.blur_level);
+        // --- End blur ---
+
+        // Encode as PNG
+        let buffer_data = encode_png(&image)?;
+
+        // Save the PNG into the local content-addressed store (and the bucket, if configured)
+        let stored = store.save(&buffer_data).await?;
+        if stored.deduplicated {
+            println!("Screenshot identical to stored file {}, reusing it.", stored.hash);
+        } else {
+            println!("Screenshot saved locally as {} ({} bytes)", stored.hash, stored.size);
+        }
+        let original = match sealed_copy {
+            Some(bytes) => Some(store.save(&bytes).await?),
+            None => None,
+        };
+
+        // Insert into DB, referencing the image by hash and object key instead of duplicating the bytes
         sqlx::query( // Use query()
             r#"
-            INSERT INTO screenshots (id, session_id, capture_time, image_data, monitor_count, open_windows)
-            VALUES ($1, $2, $3, $4, $5, $6)
+            INSERT INTO screenshots (id, session_id, capture_time, image_hash, image_key, image_size, monitor_count, open_windows, perceptual_hash, change_score, is_duplicate, unchanged_since, blur_level, original_hash, original_key, redacted_regions, focused_app, focused_title, focused_browser, focused_domain)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16::jsonb, $17, $18, $19, $20)
             "#
         )
         .bind(screenshot_id)
         .bind(session_id)
         .bind(capture_time)
-        .bind(&buffer_data) // BYTEA
+        .bind(&stored.hash) // TEXT
+        .bind(&stored.key) // TEXT, NULL when no bucket is configured
+        .bind(stored.size as i64) // BIGINT
         .bind(monitor_count) // INTEGER
         .bind(&open_windows) // TEXT[]
+        .bind(phash as i64) // BIGINT
+        .bind(change_score) // REAL, NULL for the first capture
+        .bind(unchanged.is_some()) // BOOLEAN, flagged when the screen looks the same
+        .bind(unchanged.map(|p| p.unchanged_since)) // TIMESTAMPTZ
+        .bind(policy.blur_level.as_str()) // TEXT
+        .bind(original.as_ref().map(|o| &o.hash)) // TEXT, encrypted unblurred copy
+        .bind(original.as_ref().and_then(|o| o.key.as_ref())) // TEXT
+        .bind(&redacted_regions_json) // JSONB
+        .bind(&focused_app) // TEXT
+        .bind(&focused_title) // TEXT
+        .bind(focused_browser) // TEXT
+        .bind(&focused_domain) // TEXT
         .execute(db_pool)
         .await
         .map_err(|e| format!("Failed to insert screenshot into DB: {}", e))?;
@@ -125,172 +321,616 @@ async fn capture_and_save(
             screenshot_id, session_id, monitor_count, open_windows.len()
         );
 
-        // --- Save screenshot locally ---
-        let screenshots_dir = PathBuf::from("src-tauri/screenshots");
-        fs::create_dir_all(&screenshots_dir)
-            .map_err(|e| format!("Failed to create screenshots directory: {}", e))?;
-
-        let filename = format!("{}.png", screenshot_id);
-        let file_path = screenshots_dir.join(&filename); // Use reference to filename
-
-        fs::write(&file_path, &buffer_data)
-            .map_err(|e| format!("Failed to save screenshot file locally: {}", e))?;
-
-        println!("Screenshot saved locally to: {:?}", file_path);
-        // --- End save screenshot locally ---
-
-
         // Emit event to frontend with the screenshot ID
         app_handle
             .emit("new_screenshot", screenshot_id.to_string()) // Send the ID as string
             .map_err(|e| format!("Failed to emit new_screenshot event: {}", e))?;
 
-        Ok(())
+        Ok(LastCapture {
+            image_id: screenshot_id,
+            phash,
+            unchanged_since,
+        })
     } else {
         Err("No screens found to capture.".to_string())
     }
 }
 
-// The main async task for the timer and screenshot logic
-async fn timer_task(
+// Connects the timer task to the database, the screenshot store and the frontend
+struct AppTimerHost {
     db_pool: Pool<Postgres>,
-    timer_status: Arc<Mutex<TimerStatus>>,
-    mut command_rx: mpsc::Receiver<TimerCommand>,
+    store: Arc<ScreenshotStorage>,
+    policy: CapturePolicy,
+    session_id: Mutex<Uuid>, // Changes when the session is split at midnight
     app_handle: AppHandle,
-    session_id: Uuid, // Added
-) {
-    println!("Timer task started for session {}.", session_id);
-    let mut is_paused = false;
+    clock: Arc<dyn Clock>,
+    last_capture: Mutex<Option<LastCapture>>, // Compared against to detect an unchanged screen
+    last_capture_report: std::sync::Mutex<Option<(String, chrono::DateTime<Utc>)>>, // Cause and time last sent to Sentry
+}
 
-    loop {
-        // Check for commands (Pause, Resume, Stop) without blocking indefinitely
-        match command_rx.try_recv() {
-            Ok(TimerCommand::Pause) => {
-                println!("Timer task received PAUSE command.");
-                is_paused = true;
-                *timer_status.lock().await = TimerStatus::Paused;
-                // Notify frontend about the status change
-                app_handle.emit("timer_status_update", TimerStatus::Paused).unwrap();
-            }
-            Ok(TimerCommand::Resume) => {
-                println!("Timer task received RESUME command.");
-                is_paused = false;
-                *timer_status.lock().await = TimerStatus::Running;
-                 // Notify frontend about the status change
-                app_handle.emit("timer_status_update", TimerStatus::Running).unwrap();
-            }
-            Ok(TimerCommand::Stop) => {
-                println!("Timer task received STOP command.");
-                *timer_status.lock().await = TimerStatus::Stopped;
-                 // Notify frontend about the status change
-                app_handle.emit("timer_status_update", TimerStatus::Stopped).unwrap();
-                break; // Exit the loop
-            }
-            Err(mpsc::error::TryRecvError::Empty) => {
-                // No command received, continue normal operation
-            }
-            Err(mpsc::error::TryRecvError::Disconnected) => {
-                println!("Timer command channel disconnected. Stopping task.");
-                *timer_status.lock().await = TimerStatus::Stopped;
-                 // Notify frontend about the status change
-                app_handle.emit("timer_status_update", TimerStatus::Stopped).unwrap();
-                break; // Exit the loop
-            }
+// A failing capture usually fails the same way every slot (e.g. without screen recording
+// permission), so each cause is reported to Sentry at most this often
+const CAPTURE_REPORT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+impl TimerHost for AppTimerHost {
+    fn capture(&self) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            let mut last_capture = self.last_capture.lock().await;
+            let session_id = *self.session_id.lock().await;
+            let capture = capture_and_save(
+                &self.db_pool,
+                &self.store,
+                &self.policy,
+                last_capture.as_ref(),
+                session_id,
+                &self.app_handle,
+                self.clock.as_ref(),
+            )
+            .await?;
+            *last_capture = Some(capture);
+            Ok(())
+        })
+    }
+
+    fn capture_failed(&self, error: String) {
+        let error = AppError::Capture(error);
+        let now = self.clock.now();
+        let mut last_report = self.last_capture_report.lock().unwrap();
+        let reported_recently = last_report.as_ref().is_some_and(|(cause, at)| {
+            *cause == error.to_string() && (now - *at).num_seconds() < CAPTURE_REPORT_INTERVAL.as_secs() as i64
+        });
+        if !reported_recently {
+            error.report();
+            *last_report = Some((error.to_string(), now));
         }
+        drop(last_report);
+        self.app_handle
+            .emit("screenshot_error", error)
+            .unwrap_or_else(|err| eprintln!("Failed to emit error: {}", err));
+    }
 
-        if !is_paused {
-            // Generate random delay between 4 and 10 seconds
-            let delay_secs = rand::thread_rng().gen_range(4..=10);
-            println!("Next screenshot in {} seconds...", delay_secs);
-            sleep(Duration::from_secs(delay_secs)).await;
-
-             // Check again for commands received *during* sleep
-            match command_rx.try_recv() {
-                 Ok(TimerCommand::Pause) => { is_paused = true; *timer_status.lock().await = TimerStatus::Paused; app_handle.emit("timer_status_update", TimerStatus::Paused).unwrap(); continue; }
-                 Ok(TimerCommand::Resume) => { is_paused = false; *timer_status.lock().await = TimerStatus::Running; app_handle.emit("timer_status_update", TimerStatus::Running).unwrap(); /* Continue below */ }
-                 Ok(TimerCommand::Stop) => { *timer_status.lock().await = TimerStatus::Stopped; app_handle.emit("timer_status_update", TimerStatus::Stopped).unwrap(); break; }
-                 Err(_) => { /* Continue below */ }
-            }
+    fn session_length_warning(&self, stops_at: chrono::DateTime<Utc>) {
+        println!("Session reaches its maximum length at {}.", stops_at);
+        self.app_handle
+            .emit("session_length_warning", SessionLengthWarning { stops_at })
+            .unwrap_or_else(|err| eprintln!("Failed to emit session length warning: {}", err));
+    }
 
-            if !is_paused { // Check pause status *again* after sleep and potential command
-                println!("Taking screenshot for session {}...", session_id);
-                // Pass session_id and app_handle to capture_and_save
-                if let Err(e) = capture_and_save(&db_pool, session_id, &app_handle).await {
-                    eprintln!("Error capturing/saving screenshot: {}", e);
-                    app_handle.emit("screenshot_error", e).unwrap_or_else(|err| eprintln!("Failed to emit error: {}", err));
-                }
+    fn auto_stop(&self) -> BoxFuture<'_, Result<(), AppError>> {
+        Box::pin(async move {
+            let state = self.app_handle.state::<AppState>();
+            stop_session(&state, TransitionReason::MaxSessionLength).await
+        })
+    }
+
+    fn split_session(&self, at: chrono::DateTime<Utc>) -> BoxFuture<'_, Result<(), AppError>> {
+        Box::pin(async move {
+            let state = self.app_handle.state::<AppState>();
+            let session_id = split_session_at_day_start(&state, at).await?;
+            let previous_session_id = std::mem::replace(&mut *self.session_id.lock().await, session_id);
+            // The previous image belongs to the previous session, so don't mark against it
+            *self.last_capture.lock().await = None;
+            self.app_handle
+                .emit("session_split", SessionSplit { previous_session_id, session_id, at })
+                .unwrap_or_else(|err| eprintln!("Failed to emit session split: {}", err));
+            Ok(())
+        })
+    }
+
+    fn clock_jumped(&self, jump: ClockJump) -> BoxFuture<'_, Result<(), AppError>> {
+        Box::pin(async move {
+            let state = self.app_handle.state::<AppState>();
+            let pause_reason = record_clock_jump(&state, jump).await?;
+            let payload = ClockJumpNotice { from: jump.from, to: jump.to, pause_reason };
+            self.app_handle
+                .emit("clock_jump", payload)
+                .unwrap_or_else(|err| eprintln!("Failed to emit clock jump: {}", err));
+            Ok(())
+        })
+    }
+
+    fn break_started(&self) -> BoxFuture<'_, Result<(), AppError>> {
+        Box::pin(async move {
+            let state = self.app_handle.state::<AppState>();
+            pause_from_timer_task(&state, PauseReason::Break, TransitionReason::Break).await
+        })
+    }
+
+    fn break_ended(&self) -> BoxFuture<'_, Result<(), AppError>> {
+        Box::pin(async move { resume_after_break(&self.app_handle.state::<AppState>()).await })
+    }
+
+    fn break_reminder(&self, worked: chrono::Duration) {
+        self.app_handle
+            .emit("break_reminder", BreakReminder { worked_minutes: worked.num_minutes() })
+            .unwrap_or_else(|err| eprintln!("Failed to emit break reminder: {}", err));
+    }
+
+    fn pomodoro_changed(&self, pomodoro: Option<PomodoroState>) {
+        let state = self.app_handle.state::<AppState>();
+        *state.pomodoro_state.lock().unwrap() = pomodoro.clone();
+        self.app_handle
+            .emit("pomodoro_state", pomodoro)
+            .unwrap_or_else(|err| eprintln!("Failed to emit Pomodoro state: {}", err));
+    }
+
+    fn schedule_ended(&self, at_end: ScheduleEnd) -> BoxFuture<'_, Result<(), AppError>> {
+        Box::pin(async move {
+            let state = self.app_handle.state::<AppState>();
+            match at_end {
+                ScheduleEnd::Pause => pause_from_timer_task(&state, PauseReason::Schedule, TransitionReason::Schedule).await,
+                ScheduleEnd::Stop => stop_session(&state, TransitionReason::Schedule).await,
             }
-        } else {
-            // If paused, sleep for a short duration to avoid busy-waiting
-            sleep(Duration::from_millis(500)).await;
-        }
+        })
+    }
+}
+
+// Payload of the outside_work_schedule event, sent when tracking starts or resumes outside the
+// user's working hours under a schedule that only warns
+#[derive(serde::Serialize, Clone, Debug)]
+struct OutsideWorkSchedule {
+    message: String,
+    next_window_start: Option<chrono::DateTime<Utc>>,
+}
+
+// Checks tracking from `at` against the work schedule. Outside its windows this is refused, or
+// with a schedule that only warns, returns the warning to send once tracking is under way.
+fn check_work_schedule(
+    schedule: Option<&WorkSchedule>,
+    at: chrono::DateTime<Utc>,
+) -> Result<Option<OutsideWorkSchedule>, AppError> {
+    let Some(schedule) = schedule.filter(|schedule| schedule.window_at(at).is_none()) else {
+        return Ok(None);
+    };
+    let message = work_schedule::outside_hours_message(schedule, at);
+    match schedule.outside_hours {
+        OutsideHours::Block => Err(AppError::OutsideWorkHours(message)),
+        OutsideHours::Warn => Ok(Some(OutsideWorkSchedule {
+            message,
+            next_window_start: schedule.next_window_start(at),
+        })),
     }
+}
+
+fn warn_outside_work_schedule(app_handle: &AppHandle, warning: Option<OutsideWorkSchedule>) {
+    if let Some(warning) = warning {
+        println!("{}", warning.message);
+        app_handle
+            .emit("outside_work_schedule", warning)
+            .unwrap_or_else(|err| eprintln!("Failed to emit work schedule warning: {}", err));
+    }
+}
+
+// Payload of the break_reminder event
+#[derive(serde::Serialize, Clone, Debug)]
+struct BreakReminder {
+    worked_minutes: i64,
+}
+
+// Payload of the session_length_warning event
+#[derive(serde::Serialize, Clone, Debug)]
+struct SessionLengthWarning {
+    stops_at: chrono::DateTime<Utc>,
+}
+
+// Payload of the clock_jump event. `pause_reason` is set when the gap was recorded as a pause.
+#[derive(serde::Serialize, Clone, Debug)]
+struct ClockJumpNotice {
+    from: chrono::DateTime<Utc>,
+    to: chrono::DateTime<Utc>,
+    pause_reason: Option<PauseReason>,
+}
+
+// Payload of the session_split event
+#[derive(serde::Serialize, Clone, Debug)]
+struct SessionSplit {
+    previous_session_id: Uuid,
+    session_id: Uuid,
+    at: chrono::DateTime<Utc>,
+}
 
-    println!("Timer task finished.");
+// Logs timer transitions to the database and forwards the new status to the frontend
+struct AppTransitionObserver {
+    db_pool: Pool<Postgres>,
+    app_handle: AppHandle,
+}
+
+impl TransitionObserver for AppTransitionObserver {
+    fn persist(&self, transition: &Transition) -> BoxFuture<'_, Result<(), AppError>> {
+        let transition = transition.clone();
+        Box::pin(async move { persist_transition(&self.db_pool, &transition).await })
+    }
+
+    fn emit(&self, status: TimerStatus) {
+        if let Err(e) = self.app_handle.emit("timer_status_update", status) {
+            eprintln!("Failed to emit timer status update: {}", e);
+        }
+    }
 }
 
 // Tauri command to start the timer
 #[tauri::command]
-async fn start_timer(state: State<'_, AppState>, app_handle: AppHandle) -> Result<(), String> {
-    let mut status = state.timer_status.lock().await;
-    if *status != TimerStatus::Stopped {
-        return Err("Timer is already running or paused.".to_string());
+async fn start_timer(
+    task: Option<String>, // Optional first task of the session; switch_task changes it later
+    project: Option<String>,
+    state: State<'_, AppState>,
+    app_handle: AppHandle,
+) -> Result<(), CommandError> {
+    require_user(&state, "start the timer")?;
+    start_session(&state, app_handle, task, project, false).await?;
+    Ok(())
+}
+
+// Tauri command to start the timer from when the user began working without it, as offered by
+// the start_reminder event. Returns the backdated start time.
+#[tauri::command]
+async fn start_timer_from_activity(
+    task: Option<String>,
+    project: Option<String>,
+    state: State<'_, AppState>,
+    app_handle: AppHandle,
+) -> Result<chrono::DateTime<Utc>, CommandError> {
+    require_user(&state, "start the timer")?;
+    Ok(start_session(&state, app_handle, task, project, true).await?)
+}
+
+// Starts a session now, or with `backdate` from the start of the current untracked activity,
+// counting the input seen since then. Returns the session's start time.
+async fn start_session(
+    state: &AppState,
+    app_handle: AppHandle,
+    task: Option<String>,
+    project: Option<String>,
+    backdate: bool,
+) -> Result<chrono::DateTime<Utc>, AppError> {
+    let mut timer = state.timer.lock().await;
+    if timer.status() != TimerStatus::Stopped {
+        return Err(AppError::AlreadyRunning);
     }
 
-    println!("Starting timer...");
+    // Refresh the user's working hours, which may have changed since the last start
+    let schedule = match state.current_user.as_ref() {
+        Some(user) => match work_schedule::load_schedule(&state.db_pool, user.team_member_id).await {
+            Ok(schedule) => {
+                *state.work_schedule.lock().await = schedule.clone();
+                schedule
+            }
+            Err(e) => {
+                eprintln!("Warning: {}. Using the previously loaded work schedule.", e);
+                state.work_schedule.lock().await.clone()
+            }
+        },
+        None => None,
+    };
 
-    // --- Reset Activity Counters and Activate Listening ---
-    state.activity_counters.key_presses.store(0, Ordering::Relaxed);
-    state.activity_counters.mouse_clicks.store(0, Ordering::Relaxed);
-    state.is_session_active.store(true, Ordering::Relaxed); // Enable counting
-    println!("Activity counters reset and listening activated.");
-    // --- End Reset ---
+    let now = state.clock.now();
+    let outside_hours = check_work_schedule(schedule.as_ref(), now)?;
+    if let Some(period) = refresh_hour_limits(state).await?.and_then(|progress| progress.hard_limit_reached()) {
+        return Err(AppError::HourLimitReached(format!("You have reached your {} hour limit.", period_name(period))));
+    }
+    let (start_time, initial_counts) = if backdate {
+        let (active_since, counts) = state
+            .untracked_activity
+            .current(now)
+            .ok_or_else(|| AppError::Invalid("No recent activity to start the timer from.".to_string()))?;
+        // Never overlap the user's previous session
+        let (last_end,): (Option<chrono::DateTime<Utc>>,) =
+            sqlx::query_as("SELECT MAX(end_time) FROM sessions WHERE team_member_id IS NOT DISTINCT FROM $1")
+                .bind(state.current_user.as_ref().map(|user| user.team_member_id))
+                .fetch_one(&state.db_pool)
+                .await
+                .map_err(|e| AppError::Db(format!("Failed to look up the previous session: {}", e)))?;
+        let active_since = last_end.map_or(active_since, |last_end| active_since.max(last_end));
+        // Nor start before the current window of the work schedule
+        let window_start = schedule.as_ref().and_then(|schedule| schedule.window_at(now)).map(|(start, _)| start);
+        (window_start.map_or(active_since, |window_start| active_since.max(window_start)), counts)
+    } else {
+        (now, ActivityData::default())
+    };
+    if let Some(user) = state.current_user.as_ref() {
+        timesheets::ensure_unlocked(&state.db_pool, user.team_member_id, start_time, now).await?;
+    }
 
-    *status = TimerStatus::Running;
+    println!("Starting timer...");
 
     // --- Session Handling ---
     let session_id = Uuid::new_v4();
-    let start_time = Utc::now();
-    *state.current_session_id.lock().await = Some(session_id);
-    *state.session_start_time.lock().await = Some(start_time); // Store start time
 
-    // Insert new session into DB
-    // Use query() function
-    sqlx::query("INSERT INTO sessions (id, start_time) VALUES ($1, $2)") // Use query()
+    // Insert new session into DB, together with its first task segment
+    // The schedule in effect is kept with the session, since it may change later
+    let schedule_json = schedule
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(|e| AppError::Db(format!("Failed to serialize work schedule: {}", e)))?;
+    let mut tx = state.db_pool.begin().await.map_err(|e| AppError::Db(format!("Failed to begin transaction: {}", e)))?;
+    sqlx::query("INSERT INTO sessions (id, start_time, team_member_id, work_schedule) VALUES ($1, $2, $3, $4::jsonb)") // Use query()
         .bind(session_id)
         .bind(start_time)
-        .execute(&state.db_pool)
+        .bind(state.current_user.as_ref().map(|user| user.team_member_id))
+        .bind(schedule_json)
+        .execute(&mut *tx)
         .await
-        .map_err(|e| format!("Failed to insert session into DB: {}", e))?;
+        .map_err(|e| AppError::Db(format!("Failed to insert session into DB: {}", e)))?;
+    let segment = open_segment(
+        &mut tx,
+        session_id,
+        task,
+        project,
+        start_time,
+        ActivityData::default(), // Activity counted before the start belongs to the first task too
+    )
+    .await?;
+    tx.commit().await.map_err(|e| AppError::Db(format!("Failed to commit new session: {}", e)))?;
+
+    // Only now that the session is stored, so a failed start leaves counters and untracked input alone
+    state.activity_counters.key_presses.store(initial_counts.key_presses, Ordering::Relaxed);
+    state.activity_counters.mouse_clicks.store(initial_counts.mouse_clicks, Ordering::Relaxed);
+    state.is_session_active.store(true, Ordering::Relaxed); // Enable counting
+    state.untracked_activity.clear();
+    println!("Activity counters reset and listening activated.");
+    *state.current_session_id.lock().await = Some(session_id);
+    *state.session_start_time.lock().await = Some(start_time); // Store start time
+    *state.current_segment.lock().await = Some(segment);
+    *state.session_base_counts.lock().await = ActivityData::default();
+    *state.current_pause.lock().await = None;
+    *state.paused_time.lock().await = PausedTime::default();
     println!("Started session with ID: {}", session_id);
+    timer.transition(TimerStatus::Running, TransitionReason::User, Some(session_id)).await?;
     // --- End Session Handling ---
 
 
-    let db_pool = state.db_pool.clone();
-    let status_clone = Arc::clone(&state.timer_status);
-    let (tx, rx) = mpsc::channel(1);
+    // Refresh the user's organization's capture policy so changes apply from the next session on
+    let organization_id = state.current_user.as_ref().map(|user| user.organization_id);
+    let policy = match load_capture_policy(&state.db_pool, organization_id).await {
+        Ok(policy) => {
+            *state.capture_policy.lock().await = policy.clone();
+            policy
+        }
+        Err(e) => {
+            eprintln!("Warning: {}. Using the previously loaded capture policy.", e);
+            state.capture_policy.lock().await.clone()
+        }
+    };
+
+    let scheduler = SlotScheduler::new(
+        policy.screenshot_slot_minutes,
+        policy.min_screenshots_per_slot,
+        policy.max_screenshots_per_slot,
+    );
+    let limits = SessionLimits::from_policy(&policy, start_time, state.timezone, schedule);
+    let breaks = BreakTracker::new(
+        *state.pomodoro_settings.lock().await,
+        (policy.break_reminder_minutes > 0).then(|| chrono::Duration::minutes(i64::from(policy.break_reminder_minutes))),
+    );
+    let host = Arc::new(AppTimerHost {
+        db_pool: state.db_pool.clone(),
+        store: Arc::clone(&state.screenshot_store),
+        policy,
+        session_id: Mutex::new(session_id),
+        app_handle: app_handle.clone(),
+        clock: Arc::clone(&state.clock),
+        last_capture: Mutex::new(None),
+        last_capture_report: std::sync::Mutex::new(None),
+    });
+    // Commands are sent with try_send while holding the timer lock, which the task itself may be
+    // waiting for during an auto-stop or split, so leave room instead of blocking on a full channel
+    let (tx, rx) = mpsc::channel(8);
 
     *state.command_tx.lock().await = Some(tx);
 
     // Spawn the timer task with session_id
     tokio::spawn(timer_task(
-        db_pool,
-        status_clone,
+        host,
+        Arc::clone(&state.clock),
+        Arc::clone(&state.rng),
+        scheduler,
+        limits,
+        breaks,
         rx,
-        app_handle.clone(),
-        session_id, // Pass session_id
     ));
 
-    app_handle.emit("timer_status_update", TimerStatus::Running).unwrap();
+    warn_outside_work_schedule(&app_handle, outside_hours);
+    Ok(start_time)
+}
+
+// Reloads the user's hour limits and returns their progress, or None without limits
+async fn refresh_hour_limits(state: &AppState) -> Result<Option<HourProgress>, AppError> {
+    let Some(user) = state.current_user.as_ref() else {
+        return Ok(None);
+    };
+    let limits = match hour_limits::load_limits(&state.db_pool, user.team_member_id).await {
+        Ok(limits) => {
+            *state.hour_limits.lock().await = limits.clone();
+            limits
+        }
+        Err(e) => {
+            eprintln!("Warning: {}. Using the previously loaded hour limits.", e);
+            state.hour_limits.lock().await.clone()
+        }
+    };
+    match limits {
+        Some(limits) => hour_progress(state, user, &limits).await.map(Some),
+        None => Ok(None),
+    }
+}
+
+async fn hour_progress(state: &AppState, user: &CurrentUser, limits: &HourLimits) -> Result<HourProgress, AppError> {
+    let now = state.clock.now();
+    let worked = hour_limits::worked_this_week(&state.db_pool, user.team_member_id, now, state.timezone).await?;
+    Ok(hour_limits::progress(limits, &worked, now.with_timezone(&state.timezone).date_naive()))
+}
+
+fn period_name(period: Period) -> &'static str {
+    match period {
+        Period::Day => "daily",
+        Period::Week => "weekly",
+    }
+}
+
+// How often worked time is checked against the hour limits while the timer runs
+const HOUR_LIMIT_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+// Warns as worked time reaches each threshold of the user's hour limits (once per day or week)
+// and stops the timer at a hard limit. Only checks while the timer runs, since pauses don't count.
+async fn watch_hour_limits(app_handle: AppHandle) {
+    let state = app_handle.state::<AppState>();
+    let mut sent = std::collections::HashSet::new();
+    loop {
+        state.clock.sleep(HOUR_LIMIT_CHECK_INTERVAL).await;
+        let (Some(user), Some(limits)) = (state.current_user.as_ref(), state.hour_limits.lock().await.clone()) else {
+            continue;
+        };
+        if state.timer.status().await != TimerStatus::Running {
+            continue;
+        }
+        let progress = match hour_progress(&state, user, &limits).await {
+            Ok(progress) => progress,
+            Err(e) => {
+                eprintln!("Failed to check hour limits: {}", e);
+                continue;
+            }
+        };
+        for warning in hour_limits::reached_thresholds(&limits, &progress) {
+            if sent.insert(warning.key(&progress)) {
+                println!("Reached {}% of the {} hour limit.", warning.percent, period_name(warning.period));
+                app_handle
+                    .emit("hour_limit_warning", warning)
+                    .unwrap_or_else(|err| eprintln!("Failed to emit hour limit warning: {}", err));
+            }
+        }
+        if let Some(period) = progress.hard_limit_reached() {
+            println!("Reached the {} hard hour limit. Stopping the timer.", period_name(period));
+            if let Err(e) = stop_session(&state, TransitionReason::HourLimit).await {
+                eprintln!("Failed to stop the timer at the hour limit: {}", e);
+            }
+        }
+    }
+}
+
+// How often the local screenshot store is checked against its age and size limits
+const SCREENSHOT_RETENTION_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+// Marks the rows whose image (or unblurred copy) was only kept in the removed local files, so
+// opening them says the screenshot expired instead of failing to find the file.
+async fn expire_screenshot_files(pool: &Pool<Postgres>, hashes: &[String], now: chrono::DateTime<Utc>) -> Result<(), AppError> {
+    if hashes.is_empty() {
+        return Ok(());
+    }
+    sqlx::query(
+        "UPDATE screenshots SET image_expired_at = $2 WHERE image_hash = ANY($1) AND image_key IS NULL AND image_expired_at IS NULL",
+    )
+    .bind(hashes)
+    .bind(now)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to mark expired screenshots: {}", e)))?;
+    sqlx::query(
+        "UPDATE screenshots SET original_expired_at = $2 WHERE original_hash = ANY($1) AND original_key IS NULL AND original_expired_at IS NULL",
+    )
+    .bind(hashes)
+    .bind(now)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to mark expired unblurred copies: {}", e)))?;
     Ok(())
 }
 
+// Applies the local store's retention on startup and then periodically.
+async fn enforce_screenshot_retention(app_handle: AppHandle) {
+    let state = app_handle.state::<AppState>();
+    loop {
+        match state.screenshot_store.local.enforce_retention(std::time::SystemTime::now()) {
+            Ok(report) => {
+                if let Err(e) = expire_screenshot_files(&state.db_pool, &report.hashes, state.clock.now()).await {
+                    eprintln!("Warning: failed to mark screenshots removed by retention: {}", e);
+                }
+            }
+            Err(e) => eprintln!("Warning: screenshot retention failed: {}", e),
+        }
+        state.clock.sleep(SCREENSHOT_RETENTION_INTERVAL).await;
+    }
+}
+
+// How often to look for managers' reviews of the user's timesheets
+const TIMESHEET_NOTIFICATION_INTERVAL: Duration = Duration::from_secs(60);
+
+// Tells the user when a manager approves, rejects or reopens one of their timesheets, with a
+// timesheet_reviewed event. Reviews made while the app was closed arrive shortly after the next start.
+async fn notify_timesheet_reviews(app_handle: AppHandle) {
+    let state = app_handle.state::<AppState>();
+    let Some(user) = state.current_user.as_ref() else {
+        return;
+    };
+    loop {
+        // Checking after the wait gives the window time to load and start listening
+        state.clock.sleep(TIMESHEET_NOTIFICATION_INTERVAL).await;
+        if app_handle.get_webview_window("main").is_none() {
+            continue;
+        }
+        let events = match timesheets::pending_notifications(&state.db_pool, user.team_member_id).await {
+            Ok(events) => events,
+            Err(e) => {
+                eprintln!("Failed to check timesheet reviews: {}", e);
+                continue;
+            }
+        };
+        // A step is only marked as sent once it reached the window; if emitting fails it's retried next time
+        for event in events {
+            println!("Timesheet for the week of {} is now {}.", event.period_start, event.status.as_str());
+            if let Err(err) = app_handle.emit("timesheet_reviewed", &event) {
+                eprintln!("Failed to emit timesheet review: {}", err);
+                break;
+            }
+            if let Err(e) = timesheets::mark_notified(&state.db_pool, event.id, state.clock.now()).await {
+                eprintln!("{}", e);
+            }
+        }
+    }
+}
+
+// How often to check whether the user is working without the timer
+const START_REMINDER_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+// Payload of the start_reminder event
+#[derive(serde::Serialize, Clone, Debug)]
+struct StartReminder {
+    active_since: chrono::DateTime<Utc>,
+}
+
+// Reminds the user to start the timer once they have been active for a while without it.
+// The frontend can then offer start_timer_from_activity.
+async fn remind_to_start(app_handle: AppHandle) {
+    let state = app_handle.state::<AppState>();
+    loop {
+        state.clock.sleep(START_REMINDER_CHECK_INTERVAL).await;
+        let minutes = state.capture_policy.lock().await.start_reminder_minutes;
+        if minutes == 0 || state.timer.status().await != TimerStatus::Stopped {
+            continue;
+        }
+        let after = chrono::Duration::minutes(i64::from(minutes));
+        if let Some(active_since) = state.untracked_activity.reminder_due(state.clock.now(), after) {
+            println!("Active since {} without a timer. Reminding to start one.", active_since);
+            app_handle
+                .emit("start_reminder", StartReminder { active_since })
+                .unwrap_or_else(|err| eprintln!("Failed to emit start reminder: {}", err));
+        }
+    }
+}
+
 // Tauri command to stop the timer
 #[tauri::command]
-async fn stop_timer(state: State<'_, AppState>, app_handle: AppHandle) -> Result<(), String> {
-    let mut status = state.timer_status.lock().await;
-     if *status == TimerStatus::Stopped {
-         return Err("Timer is already stopped.".to_string());
+async fn stop_timer(state: State<'_, AppState>) -> Result<(), CommandError> {
+    require_user(&state, "stop the timer")?;
+    Ok(stop_session(&state, TransitionReason::User).await?)
+}
+
+// Ends the current run: saves the session and its open task segment, then stops the timer task.
+// Also used by the timer task itself when a run reaches its maximum length.
+async fn stop_session(state: &AppState, reason: TransitionReason) -> Result<(), AppError> {
+    let mut timer = state.timer.lock().await;
+     if timer.status() == TimerStatus::Stopped {
+         return Err(AppError::AlreadyStopped);
      }
      println!("Stopping timer...");
 
@@ -298,14 +938,18 @@ async fn stop_timer(state: State<'_, AppState>, app_handle: AppHandle) -> Result
      state.is_session_active.store(false, Ordering::Relaxed); // Disable counting FIRST
      println!("Activity listening deactivated.");
 
-     let final_key_presses = state.activity_counters.key_presses.load(Ordering::Relaxed) as i32; // Cast to i32 for DB
-     let final_mouse_clicks = state.activity_counters.mouse_clicks.load(Ordering::Relaxed) as i32; // Cast to i32 for DB
+     let final_counts = get_current_counts(&state.activity_counters);
+     // Only what happened since the last midnight split belongs to this session row
+     let session_counts = final_counts.since(&*state.session_base_counts.lock().await);
+     let final_key_presses = session_counts.key_presses as i32; // Cast to i32 for DB
+     let final_mouse_clicks = session_counts.mouse_clicks as i32; // Cast to i32 for DB
      println!("Final counts - Keys: {}, Clicks: {}", final_key_presses, final_mouse_clicks);
 
      // --- Session Handling (Update DB with counts) ---
      let session_id_opt = *state.current_session_id.lock().await;
      if let Some(session_id) = session_id_opt {
-         let end_time = Utc::now();
+         let end_time = state.clock.now();
+         let mut tx = state.db_pool.begin().await.map_err(|e| AppError::Db(format!("Failed to begin transaction: {}", e)))?;
          // Update session end time AND activity counts in DB
          sqlx::query(
              r#"
@@ -318,158 +962,862 @@ async fn stop_timer(state: State<'_, AppState>, app_handle: AppHandle) -> Result
          .bind(final_key_presses) // Bind key presses
          .bind(final_mouse_clicks) // Bind mouse clicks
          .bind(session_id)
-         .execute(&state.db_pool)
+         .execute(&mut *tx)
          .await
-         .map_err(|e| format!("Failed to update session end time and activity counts in DB: {}", e))?;
+         .map_err(|e| AppError::Db(format!("Failed to update session end time and activity counts in DB: {}", e)))?;
+         if let Some(segment) = state.current_segment.lock().await.take() {
+             close_segment(&mut tx, &segment, end_time, &final_counts).await?;
+         }
+         if let Some(pause) = state.current_pause.lock().await.take() {
+             pauses::close_pause(&mut tx, &pause, end_time).await?;
+         }
+         tx.commit().await.map_err(|e| AppError::Db(format!("Failed to commit session end: {}", e)))?;
          println!("Ended session with ID: {} and saved activity counts.", session_id);
      } else {
          eprintln!("Warning: Could not find current session ID when stopping timer to save activity counts.");
      }
      *state.current_session_id.lock().await = None; // Clear current session ID
      *state.session_start_time.lock().await = None; // Clear start time
+     *state.pomodoro_state.lock().unwrap() = None;
      // --- End Session Handling ---
 
+     timer.transition(TimerStatus::Stopped, reason, session_id_opt).await?;
 
      if let Some(tx) = state.command_tx.lock().await.take() { // Use take() to consume the sender
-         if tx.send(TimerCommand::Stop).await.is_err() {
-            // If sending fails, the task might have already stopped. Manually update status.
-            eprintln!("Failed to send stop command or channel closed. Forcing status update.");
-            *status = TimerStatus::Stopped;
-            app_handle.emit("timer_status_update", TimerStatus::Stopped).unwrap();
+         // Dropping the sender also ends the task, so a full or closed channel needs no retry
+         if tx.try_send(TimerCommand::Stop).is_err() {
+            eprintln!("Failed to send stop command or channel closed.");
          }
-         // Task will update status upon receiving command if send was successful
      } else {
          // Should not happen if timer is running/paused, but handle defensively
-         println!("Command channel not found while stopping. Forcing status update.");
-         *status = TimerStatus::Stopped;
-         app_handle.emit("timer_status_update", TimerStatus::Stopped).unwrap();
+         println!("Command channel not found while stopping.");
      }
 
      Ok(())
 }
 
+// Ends the current session at the start of a new local day and continues tracking in a new
+// session with the same task, so every session row belongs to exactly one workday. The run
+// itself (elapsed time, maximum length) carries on. Returns the new session's ID.
+async fn split_session_at_day_start(state: &AppState, at: chrono::DateTime<Utc>) -> Result<Uuid, AppError> {
+    let timer = state.timer.lock().await;
+    if timer.status() == TimerStatus::Stopped {
+        return Err(AppError::NotRunning);
+    }
+    let previous_session_id = state.current_session_id.lock().await.ok_or(AppError::NotRunning)?;
+    let mut current_segment = state.current_segment.lock().await;
+    let mut current_pause = state.current_pause.lock().await;
+    let mut base_counts = state.session_base_counts.lock().await;
+    let counts = get_current_counts(&state.activity_counters);
+    let session_counts = counts.since(&base_counts);
+    let session_id = Uuid::new_v4();
+
+    let mut tx = state.db_pool.begin().await.map_err(|e| AppError::Db(format!("Failed to begin transaction: {}", e)))?;
+    sqlx::query(
+        r#"
+        UPDATE sessions
+        SET end_time = $1, key_press_count = $2, mouse_click_count = $3
+        WHERE id = $4
+        "#,
+    )
+    .bind(at)
+    .bind(session_counts.key_presses as i32)
+    .bind(session_counts.mouse_clicks as i32)
+    .bind(previous_session_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to end session {} at day start: {}", previous_session_id, e)))?;
+    sqlx::query(
+        r#"
+        INSERT INTO sessions (id, start_time, team_member_id, work_schedule)
+        SELECT $1, $2, team_member_id, work_schedule FROM sessions WHERE id = $3
+        "#,
+    )
+    .bind(session_id)
+    .bind(at)
+    .bind(previous_session_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to insert session into DB: {}", e)))?;
+    let (task, project) = match current_segment.as_ref() {
+        Some(segment) => {
+            close_segment(&mut tx, segment, at, &counts).await?;
+            (segment.task.clone(), segment.project.clone())
+        }
+        None => (None, None),
+    };
+    let segment = open_segment(&mut tx, session_id, task, project, at, counts.clone()).await?;
+    pauses::split_pauses(&mut tx, previous_session_id, session_id, at).await?;
+    let pause = match current_pause.as_ref() {
+        Some(pause) => {
+            pauses::close_pause(&mut tx, pause, at).await?;
+            Some(pauses::open_pause(&mut tx, session_id, pause.reason, at).await?)
+        }
+        None => None,
+    };
+    tx.commit().await.map_err(|e| AppError::Db(format!("Failed to commit session split: {}", e)))?;
+
+    *current_segment = Some(segment);
+    *current_pause = pause;
+    *base_counts = counts;
+    *state.current_session_id.lock().await = Some(session_id);
+    println!("Split session {} at {}; continuing in session {}.", previous_session_id, at, session_id);
+    Ok(session_id)
+}
+
+// Pauses the session for a Pomodoro break or the end of the work schedule's window. The timer
+// task has already paused itself.
+async fn pause_from_timer_task(state: &AppState, pause_reason: PauseReason, reason: TransitionReason) -> Result<(), AppError> {
+    let mut timer = state.timer.lock().await;
+    if timer.status() != TimerStatus::Running {
+        return Err(AppError::NotRunning);
+    }
+    let session_id = *state.current_session_id.lock().await;
+    let now = state.clock.now();
+    if let Some(session_id) = session_id {
+        let mut conn = state.db_pool.acquire().await.map_err(|e| AppError::Db(format!("Failed to get a DB connection: {}", e)))?;
+        *state.current_pause.lock().await = Some(pauses::open_pause(&mut conn, session_id, pause_reason, now).await?);
+    }
+    state.paused_time.lock().await.begin(now);
+    timer.transition(TimerStatus::Paused, reason, session_id).await?;
+    Ok(())
+}
+
+// Resumes the session when a Pomodoro break is over, unless the user already did.
+async fn resume_after_break(state: &AppState) -> Result<(), AppError> {
+    let mut timer = state.timer.lock().await;
+    let mut current_pause = state.current_pause.lock().await;
+    let on_break = matches!(current_pause.as_ref(), Some(pause) if pause.reason == PauseReason::Break);
+    if timer.status() != TimerStatus::Paused || !on_break {
+        return Err(AppError::NotPaused);
+    }
+    let session_id = *state.current_session_id.lock().await;
+    let now = state.clock.now();
+    if let Some(pause) = current_pause.take() {
+        let mut conn = state.db_pool.acquire().await.map_err(|e| AppError::Db(format!("Failed to get a DB connection: {}", e)))?;
+        pauses::close_pause(&mut conn, &pause, now).await?;
+    }
+    state.paused_time.lock().await.end(now);
+    timer.transition(TimerStatus::Running, TransitionReason::Break, session_id).await?;
+    Ok(())
+}
+
+// Handles a suspend or clock change noticed by the timer task. A forward jump while running is
+// recorded as a pause, so it doesn't count as worked time; while paused it already doesn't.
+// A clock set back flags the session, whose times no longer add up. Returns the pause recorded.
+async fn record_clock_jump(state: &AppState, jump: ClockJump) -> Result<Option<PauseReason>, AppError> {
+    let timer = state.timer.lock().await;
+    let session_id = match *state.current_session_id.lock().await {
+        Some(session_id) if timer.status() != TimerStatus::Stopped => session_id,
+        _ => return Ok(None),
+    };
+
+    if jump.is_backwards() {
+        println!("System clock was set back from {} to {} during session {}.", jump.from, jump.to, session_id);
+        sqlx::query("UPDATE sessions SET clock_moved_backwards = TRUE WHERE id = $1")
+            .bind(session_id)
+            .execute(&state.db_pool)
+            .await
+            .map_err(|e| AppError::Db(format!("Failed to flag session {}: {}", session_id, e)))?;
+        state.paused_time.lock().await.set_back(jump.from, jump.to);
+        return Ok(None);
+    }
+    if timer.status() == TimerStatus::Paused {
+        return Ok(None);
+    }
+
+    // Without sleep signals, a forward jump is far more likely a suspend than a clock change
+    let reason = match state.sleep_log.slept_between(jump.from, jump.to) {
+        Some(false) => PauseReason::ClockChange,
+        _ => PauseReason::Suspend,
+    };
+    let mut conn = state.db_pool.acquire().await.map_err(|e| AppError::Db(format!("Failed to get a DB connection: {}", e)))?;
+    pauses::record_pause(&mut conn, session_id, reason, jump.from, jump.to).await?;
+    state.paused_time.lock().await.add(jump.from, jump.to);
+    println!("Recorded {} pause from {} to {} in session {}.", reason.as_str(), jump.from, jump.to, session_id);
+    Ok(Some(reason))
+}
+
 // Tauri command to pause the timer
-// No session changes needed on pause, but ensure status update happens
+// No session changes needed on pause; the state machine logs and announces the change
 #[tauri::command]
-async fn pause_timer(state: State<'_, AppState>, app_handle: AppHandle) -> Result<(), String> { // Added app_handle back
-    let status = state.timer_status.lock().await;
-    if *status != TimerStatus::Running {
-        return Err("Timer is not running.".to_string());
+async fn pause_timer(state: State<'_, AppState>) -> Result<(), CommandError> {
+    require_user(&state, "pause the timer")?;
+    let mut timer = state.timer.lock().await;
+    if timer.status() != TimerStatus::Running {
+        return Err(AppError::NotRunning.into());
     }
     println!("Pausing timer...");
+    let session_id = *state.current_session_id.lock().await;
+
+    // The pause is only kept on record if the schedule takes it; otherwise the transaction rolls back
+    let now = state.clock.now();
+    let mut tx = state.db_pool.begin().await.map_err(|e| AppError::Db(format!("Failed to start transaction: {}", e)))?;
+    let pause = match session_id {
+        Some(session_id) => Some(pauses::open_pause(&mut tx, session_id, PauseReason::User, now).await?),
+        None => None,
+    };
 
-    if let Some(tx) = state.command_tx.lock().await.as_ref() {
-        tx.send(TimerCommand::Pause)
-            .await
-            .map_err(|e| format!("Failed to send pause command: {}", e))?;
-        // Status update is handled by the task upon receiving command
+    if send_timer_command(&state, TimerCommand::Pause).await? {
+        tx.commit().await.map_err(|e| AppError::Db(format!("Failed to commit pause: {}", e)))?;
+        *state.current_pause.lock().await = pause;
+        state.paused_time.lock().await.begin(now);
+        timer.transition(TimerStatus::Paused, TransitionReason::User, session_id).await?;
         Ok(())
     } else {
-        // If channel is gone, task likely stopped unexpectedly. Update status.
+        // If channel is gone, task likely stopped unexpectedly
         println!("Command channel not found while pausing. Forcing status update.");
-        drop(status); // Release lock before acquiring again
-        *state.timer_status.lock().await = TimerStatus::Stopped; // Go to stopped if task died
-        app_handle.emit("timer_status_update", TimerStatus::Stopped).unwrap();
-        Err("Timer command channel not found, task may have stopped.".to_string())
+        timer.transition(TimerStatus::Stopped, TransitionReason::CrashRecovery, session_id).await?;
+        Err(AppError::TimerUnavailable.into())
     }
 }
 
 // Tauri command to resume the timer
-// No session changes needed on resume, but ensure status update happens
+// No session changes needed on resume; the state machine logs and announces the change
 #[tauri::command]
-async fn resume_timer(state: State<'_, AppState>, app_handle: AppHandle) -> Result<(), String> { // Added app_handle back
-    let status = state.timer_status.lock().await;
-    if *status != TimerStatus::Paused {
-        return Err("Timer is not paused.".to_string());
+async fn resume_timer(state: State<'_, AppState>, app_handle: AppHandle) -> Result<(), CommandError> {
+    require_user(&state, "resume the timer")?;
+    let mut timer = state.timer.lock().await;
+    if timer.status() != TimerStatus::Paused {
+        return Err(AppError::NotPaused.into());
+    }
+    let now = state.clock.now();
+    let outside_hours = check_work_schedule(state.work_schedule.lock().await.as_ref(), now)?;
+    println!("Resuming timer...");
+    let session_id = *state.current_session_id.lock().await;
+
+    // The pause only ends on record if the schedule resumes; otherwise the transaction rolls back
+    let mut current_pause = state.current_pause.lock().await;
+    let mut tx = state.db_pool.begin().await.map_err(|e| AppError::Db(format!("Failed to start transaction: {}", e)))?;
+    if let Some(pause) = current_pause.as_ref() {
+        pauses::close_pause(&mut tx, pause, now).await?;
     }
-     println!("Resuming timer...");
 
-    if let Some(tx) = state.command_tx.lock().await.as_ref() {
-        tx.send(TimerCommand::Resume)
-            .await
-            .map_err(|e| format!("Failed to send resume command: {}", e))?;
-        // Status update is handled by the task upon receiving command
+    if send_timer_command(&state, TimerCommand::Resume).await? {
+        tx.commit().await.map_err(|e| AppError::Db(format!("Failed to commit end of pause: {}", e)))?;
+        *current_pause = None;
+        state.paused_time.lock().await.end(now);
+        timer.transition(TimerStatus::Running, TransitionReason::User, session_id).await?;
+        warn_outside_work_schedule(&app_handle, outside_hours);
         Ok(())
     } else {
-         // If channel is gone, task likely stopped unexpectedly. Update status.
-         println!("Command channel not found while resuming. Forcing status update.");
-         drop(status); // Release lock before acquiring again
-         *state.timer_status.lock().await = TimerStatus::Stopped; // Go to stopped if task died
-         app_handle.emit("timer_status_update", TimerStatus::Stopped).unwrap();
-         Err("Timer command channel not found, task may have stopped.".to_string())
+        // If channel is gone, task likely stopped unexpectedly
+        println!("Command channel not found while resuming. Forcing status update.");
+        timer.transition(TimerStatus::Stopped, TransitionReason::CrashRecovery, session_id).await?;
+        Err(AppError::TimerUnavailable.into())
+    }
+}
+
+// Sends a command to the running timer task. Returns false if the task is gone.
+// Doesn't wait: the task may itself be waiting for the timer lock the caller holds.
+async fn send_timer_command(state: &AppState, command: TimerCommand) -> Result<bool, AppError> {
+    match state.command_tx.lock().await.as_ref() {
+        Some(tx) => match tx.try_send(command) {
+            Ok(()) => Ok(true),
+            Err(TrySendError::Full(_)) => Err(AppError::Busy),
+            Err(TrySendError::Closed(_)) => Ok(false),
+        },
+        None => Ok(false),
+    }
+}
+
+// Tauri command to turn Pomodoro mode on, with its lengths, or off (`None`). A running timer
+// switches right away and starts a fresh cycle.
+#[tauri::command]
+async fn set_pomodoro(settings: Option<PomodoroSettings>, state: State<'_, AppState>) -> Result<(), CommandError> {
+    require_user(&state, "change Pomodoro settings")?;
+    let settings = settings.map(PomodoroSettings::validated).transpose()?;
+    let timer = state.timer.lock().await;
+    *state.pomodoro_settings.lock().await = settings;
+    if timer.status() != TimerStatus::Stopped && !send_timer_command(&state, TimerCommand::SetPomodoro(settings)).await? {
+        return Err(AppError::TimerUnavailable.into());
+    }
+    Ok(())
+}
+
+// Tauri command to get the current Pomodoro phase, or None outside Pomodoro mode
+#[tauri::command]
+fn get_pomodoro_state(state: State<'_, AppState>) -> Result<Option<PomodoroState>, CommandError> {
+    require_user(&state, "view the Pomodoro state")?;
+    Ok(state.pomodoro_state.lock().unwrap().clone())
+}
+
+// Tauri command to get a team member's work schedule (the user's own by default), or None if
+// their hours aren't restricted
+#[tauri::command]
+async fn get_work_schedule(
+    team_member_id: Option<i32>,
+    state: State<'_, AppState>,
+) -> Result<Option<WorkSchedule>, CommandError> {
+    let user = require_user(&state, "view a work schedule")?;
+    let team_member_id = team_member_id.unwrap_or(user.team_member_id);
+    authorization::authorize_team_member(&state.db_pool, user, "view a work schedule", team_member_id).await?;
+    Ok(work_schedule::load_schedule(&state.db_pool, team_member_id).await?)
+}
+
+// Tauri command to get the user's progress this week towards their hour limits and contracted
+// hours, or None if they have no limits
+#[tauri::command]
+async fn get_hour_progress(state: State<'_, AppState>) -> Result<Option<HourProgress>, CommandError> {
+    require_user(&state, "view hour progress")?;
+    Ok(refresh_hour_limits(&state).await?)
+}
+
+// Tauri command to get a team member's hour limits (the user's own by default)
+#[tauri::command]
+async fn get_hour_limits(team_member_id: Option<i32>, state: State<'_, AppState>) -> Result<Option<HourLimits>, CommandError> {
+    let user = require_user(&state, "view hour limits")?;
+    let team_member_id = team_member_id.unwrap_or(user.team_member_id);
+    authorization::authorize_team_member(&state.db_pool, user, "view hour limits", team_member_id).await?;
+    Ok(hour_limits::load_limits(&state.db_pool, team_member_id).await?)
+}
+
+// Tauri command for managers to set a team member's hour limits, or remove them with None
+#[tauri::command]
+async fn set_hour_limits(
+    team_member_id: i32,
+    limits: Option<HourLimits>,
+    state: State<'_, AppState>,
+) -> Result<(), CommandError> {
+    let user = require_user(&state, "set hour limits")?;
+    Ok(hour_limits::save_limits(&state.db_pool, user, team_member_id, limits).await?)
+}
+
+// Tauri command for managers to set a team member's work schedule, or remove it with None.
+// It applies from their next start on.
+#[tauri::command]
+async fn set_work_schedule(
+    team_member_id: i32,
+    schedule: Option<WorkSchedule>,
+    state: State<'_, AppState>,
+) -> Result<(), CommandError> {
+    let user = require_user(&state, "set a work schedule")?;
+    Ok(work_schedule::save_schedule(&state.db_pool, user, team_member_id, schedule).await?)
+}
+
+// Tauri command to change the current task without stopping tracking. The open task segment
+// is closed and a new one opened at the same instant, in the same session, and the activity
+// counters (which keep running) are split at that point.
+#[tauri::command]
+async fn switch_task(
+    task: String,
+    project: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<(), CommandError> {
+    require_user(&state, "switch tasks")?;
+    let task = task.trim().to_string();
+    if task.is_empty() {
+        return Err(AppError::Invalid("Task name cannot be empty.".to_string()).into());
+    }
+
+    // Holding the timer keeps a concurrent stop from closing the session mid-switch
+    let timer = state.timer.lock().await;
+    if timer.status() == TimerStatus::Stopped {
+        return Err(AppError::NotRunning.into());
+    }
+    let mut current_segment = state.current_segment.lock().await;
+    let Some(previous) = current_segment.as_ref() else {
+        return Err(AppError::Invalid("No task is being tracked.".to_string()).into());
+    };
+
+    let now = state.clock.now();
+    let counts = get_current_counts(&state.activity_counters);
+    let mut tx = state.db_pool.begin().await.map_err(|e| AppError::Db(format!("Failed to begin transaction: {}", e)))?;
+    let activity = close_segment(&mut tx, previous, now, &counts).await?;
+    let next = open_segment(&mut tx, previous.session_id, Some(task), project, now, counts).await?;
+    tx.commit().await.map_err(|e| AppError::Db(format!("Failed to commit task switch: {}", e)))?;
+
+    println!(
+        "Switched from {:?} to {:?} (previous task: {} keys, {} clicks).",
+        previous.task, next.task, activity.key_presses, activity.mouse_clicks
+    );
+    *current_segment = Some(next);
+    Ok(())
+}
+
+// Tauri command to get the current timer status
+#[tauri::command]
+async fn get_timer_status(state: State<'_, AppState>) -> Result<TimerStatus, CommandError> {
+    require_user(&state, "view the timer status")?;
+    Ok(state.timer.status().await)
+}
+
+
+// --- NEW COMMAND: get_screenshot_data ---
+#[tauri::command]
+async fn get_screenshot_data(
+    id: String, // Receive UUID as String from JS
+    state: State<'_, AppState>,
+) -> Result<String, CommandError> {
+    let user = require_user(&state, "view a screenshot")?;
+    let screenshot_uuid = Uuid::parse_str(&id)
+        .map_err(|_| AppError::Invalid("Invalid UUID format".to_string()))?;
+    // Screenshots are only reachable through the session they were taken in
+    authorization::authorize_screenshot(&state.db_pool, user, "view a screenshot", screenshot_uuid).await?;
+
+    println!("Fetching screenshot data for ID: {}", screenshot_uuid);
+
+    // Use query() function (runtime check) for fetching screenshot data
+    // Unchanged-screen markers have no image of their own and resolve to the row they point at
+    let record = sqlx::query(
+        r#"
+        SELECT COALESCE(s.image_data, o.image_data) AS image_data,
+               COALESCE(s.image_hash, o.image_hash) AS image_hash,
+               COALESCE(s.image_key, o.image_key) AS image_key,
+               COALESCE(s.image_expired_at, o.image_expired_at) AS image_expired_at
+        FROM screenshots s
+        LEFT JOIN screenshots o ON o.id = s.duplicate_of
+        WHERE s.id = $1
+        "#
+    )
+        .bind(screenshot_uuid)
+        .fetch_optional(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Db(format!("Database error fetching screenshot: {}", e)))?;
+
+    if let Some(rec) = record {
+        // Need to get the column data using column name or index with query()
+        use sqlx::Row;
+        let inline_data: Option<Vec<u8>> = rec.try_get("image_data")
+            .map_err(|e| AppError::Db(format!("Failed to get image_data column: {}", e)))?;
+        let image_hash: Option<String> = rec.try_get("image_hash")
+            .map_err(|e| AppError::Db(format!("Failed to get image_hash column: {}", e)))?;
+        let image_key: Option<String> = rec.try_get("image_key")
+            .map_err(|e| AppError::Db(format!("Failed to get image_key column: {}", e)))?;
+        let expired_at: Option<chrono::DateTime<Utc>> = rec.try_get("image_expired_at")
+            .map_err(|e| AppError::Db(format!("Failed to get image_expired_at column: {}", e)))?;
+        // Older rows carry the bytes inline; newer ones are resolved through the screenshot store
+        let image_data = match inline_data {
+            Some(data) => data,
+            None => state
+                .screenshot_store
+                .load(image_hash.as_deref(), image_key.as_deref())
+                .await
+                .map_err(|e| expired_or(expired_at, e))?,
+        };
+        // Encode bytea data as Base64
+        let base64_image = BASE64_STANDARD.encode(&image_data);
+        Ok(format!("data:image/png;base64,{}", base64_image)) // Return data URI
+    } else {
+        Err(AppError::NotFound(format!("Screenshot with ID {} not found", screenshot_uuid)).into())
     }
 }
 
-// Tauri command to get the current timer status
+// A screenshot that can't be loaded because retention deleted its only copy is gone for good;
+// anything else is a storage fault.
+fn expired_or(expired_at: Option<chrono::DateTime<Utc>>, error: String) -> AppError {
+    match expired_at {
+        Some(at) => AppError::NotFound(format!(
+            "This screenshot was deleted from this computer on {} to keep the screenshot store within its limits.",
+            at.format("%Y-%m-%d")
+        )),
+        None => AppError::Storage(error),
+    }
+}
+
+// Tauri command to open the encrypted unblurred copy of a screenshot. Admins only, and only
+// works where the admin secret key is configured.
+#[tauri::command]
+async fn get_unblurred_screenshot(
+    id: String,
+    state: State<'_, AppState>,
+) -> Result<String, CommandError> {
+    let user = require_user(&state, "open an unblurred screenshot")?;
+    authorization::require_admin(user, "open an unblurred screenshot")?;
+    let screenshot_uuid = Uuid::parse_str(&id)
+        .map_err(|_| AppError::Invalid("Invalid UUID format".to_string()))?;
+    authorization::authorize_screenshot(&state.db_pool, user, "open an unblurred screenshot", screenshot_uuid).await?;
+
+    // Unchanged-screen markers resolve to the row they point at, as in get_screenshot_data
+    let record = sqlx::query(
+        r#"
+        SELECT COALESCE(s.original_hash, o.original_hash) AS original_hash,
+               COALESCE(s.original_key, o.original_key) AS original_key,
+               COALESCE(s.original_expired_at, o.original_expired_at) AS original_expired_at
+        FROM screenshots s
+        LEFT JOIN screenshots o ON o.id = s.duplicate_of
+        WHERE s.id = $1
+        "#
+    )
+        .bind(screenshot_uuid)
+        .fetch_optional(&state.db_pool)
+        .await
+        .map_err(|e| AppError::Db(format!("Database error fetching screenshot: {}", e)))?
+        .ok_or_else(|| AppError::NotFound(format!("Screenshot with ID {} not found", screenshot_uuid)))?;
+
+    use sqlx::Row;
+    let original_hash: Option<String> = record.try_get("original_hash")
+        .map_err(|e| AppError::Db(format!("Failed to get original_hash column: {}", e)))?;
+    let original_key: Option<String> = record.try_get("original_key")
+        .map_err(|e| AppError::Db(format!("Failed to get original_key column: {}", e)))?;
+    let expired_at: Option<chrono::DateTime<Utc>> = record.try_get("original_expired_at")
+        .map_err(|e| AppError::Db(format!("Failed to get original_expired_at column: {}", e)))?;
+    if original_hash.is_none() && original_key.is_none() {
+        return Err(AppError::NotFound(format!("Screenshot {} has no unblurred copy", screenshot_uuid)).into());
+    }
+
+    let sealed = state
+        .screenshot_store
+        .load(original_hash.as_deref(), original_key.as_deref())
+        .await
+        .map_err(|e| expired_or(expired_at, e))?;
+    let png = sealed_original::unseal_with_env_key(&sealed).map_err(AppError::Storage)?;
+    Ok(format!("data:image/png;base64,{}", BASE64_STANDARD.encode(&png)))
+}
+
+// Tauri command to get the screen-change score of every capture interval in a session
+#[tauri::command]
+async fn get_screen_change_scores(
+    session_id: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<ScreenChangeScore>, CommandError> {
+    let user = require_user(&state, "view screen-change scores")?;
+    let session_uuid = Uuid::parse_str(&session_id)
+        .map_err(|_| AppError::Invalid("Invalid UUID format".to_string()))?;
+    authorization::authorize_session(&state.db_pool, user, "view screen-change scores", session_uuid).await?;
+
+    let rows = sqlx::query(
+        "SELECT id, capture_time, change_score, is_duplicate FROM screenshots WHERE session_id = $1 AND removed_by_edit IS NULL ORDER BY capture_time",
+    )
+    .bind(session_uuid)
+    .fetch_all(&state.db_pool)
+    .await
+    .map_err(|e| AppError::Db(format!("Database error fetching screen-change scores: {}", e)))?;
+
+    use sqlx::Row;
+    rows.iter()
+        .map(|row| {
+            let id: Uuid = row.try_get("id").map_err(|e| AppError::Db(format!("Failed to get id column: {}", e)))?;
+            Ok(ScreenChangeScore {
+                screenshot_id: id.to_string(),
+                capture_time: row.try_get("capture_time").map_err(|e| AppError::Db(format!("Failed to get capture_time column: {}", e)))?,
+                change_score: row.try_get("change_score").map_err(|e| AppError::Db(format!("Failed to get change_score column: {}", e)))?,
+                is_duplicate: row.try_get("is_duplicate").map_err(|e| AppError::Db(format!("Failed to get is_duplicate column: {}", e)))?,
+            })
+        })
+        .collect()
+}
+
+// Tauri command to get the capture policy currently in effect
+#[tauri::command]
+async fn get_capture_policy(state: State<'_, AppState>) -> Result<CapturePolicy, CommandError> {
+    require_user(&state, "view the capture policy")?;
+    Ok(state.capture_policy.lock().await.clone())
+}
+
+// Tauri command for managers to replace their organization's category rules. They apply to
+// every report from then on, including for earlier sessions.
+#[tauri::command]
+async fn set_category_rules(rules: Vec<CategoryRule>, state: State<'_, AppState>) -> Result<(), CommandError> {
+    let user = require_user(&state, "change category rules")?;
+    categories::save_rules(&state.db_pool, user, &rules).await?;
+    state.capture_policy.lock().await.category_rules = rules;
+    Ok(())
+}
+
+// Builds a productivity report from the focused windows of the sessions in `scope`
+async fn productivity_report(state: &AppState, scope: FocusScope) -> Result<ProductivityReport, AppError> {
+    let policy = state.capture_policy.lock().await.clone();
+    // A screenshot stands for at most one slot of focus time
+    let max_sample = chrono::Duration::minutes(i64::from(policy.screenshot_slot_minutes));
+    let samples = categories::focus_samples(&state.db_pool, &scope, max_sample, state.clock.now()).await?;
+    Ok(categories::productivity_report(&policy.category_rules, &samples))
+}
+
+// Tauri command to get a session's focus time by category and its productivity score
+#[tauri::command]
+async fn get_session_productivity(session_id: String, state: State<'_, AppState>) -> Result<ProductivityReport, CommandError> {
+    let session_id = Uuid::parse_str(&session_id).map_err(|_| AppError::Invalid("Invalid session ID format".to_string()))?;
+    let user = require_user(&state, "view session productivity")?;
+    authorization::authorize_session(&state.db_pool, user, "view session productivity", session_id).await?;
+    Ok(productivity_report(&state, FocusScope::Session(session_id)).await?)
+}
+
+// Tauri command to get the user's focus time by category and productivity score for a local day
+// (today by default)
+#[tauri::command]
+async fn get_daily_productivity(
+    date: Option<chrono::NaiveDate>,
+    state: State<'_, AppState>,
+) -> Result<ProductivityReport, CommandError> {
+    let user = require_user(&state, "view daily productivity")?;
+    let date = date.unwrap_or_else(|| state.clock.now().with_timezone(&state.timezone).date_naive());
+    let scope = FocusScope::Day { team_member_id: user.team_member_id, date, timezone: state.timezone };
+    Ok(productivity_report(&state, scope).await?)
+}
+
+// Tauri command to propose projects for the user's finished time without one, learned locally
+// from their time with a project (unassigned time of the last week by default)
+#[tauri::command]
+async fn get_project_suggestions(
+    since: Option<chrono::DateTime<Utc>>,
+    state: State<'_, AppState>,
+) -> Result<Vec<ProjectSuggestion>, CommandError> {
+    let user = require_user(&state, "view project suggestions")?;
+    let now = state.clock.now();
+    let since = since.unwrap_or(now - chrono::Duration::days(7));
+    // A screenshot stands for at most one slot of focus time, as in the productivity reports
+    let slot_minutes = state.capture_policy.lock().await.screenshot_slot_minutes;
+    let max_sample = chrono::Duration::minutes(i64::from(slot_minutes));
+    Ok(project_suggestions::suggestions(&state.db_pool, user, since, now, max_sample).await?)
+}
+
+// Tauri command to assign a suggested (or corrected) project to a task segment without one
+#[tauri::command]
+async fn accept_project_suggestion(segment_id: String, project: String, state: State<'_, AppState>) -> Result<(), CommandError> {
+    let segment_id = Uuid::parse_str(&segment_id).map_err(|_| AppError::Invalid("Invalid segment ID format".to_string()))?;
+    let user = require_user(&state, "accept a project suggestion")?;
+    Ok(project_suggestions::accept(&state.db_pool, user, segment_id, &project).await?)
+}
+
+// Tauri command to turn down a suggested project, so it isn't proposed for the segment again
+#[tauri::command]
+async fn reject_project_suggestion(segment_id: String, project: String, state: State<'_, AppState>) -> Result<(), CommandError> {
+    let segment_id = Uuid::parse_str(&segment_id).map_err(|_| AppError::Invalid("Invalid segment ID format".to_string()))?;
+    let user = require_user(&state, "reject a project suggestion")?;
+    Ok(project_suggestions::reject(&state.db_pool, user, segment_id, &project).await?)
+}
+
+// Tauri command to get a week's timesheet (the current week by default) of the user, or of a team
+// member for managers
+#[tauri::command]
+async fn get_timesheet(
+    team_member_id: Option<i32>,
+    date: Option<chrono::NaiveDate>,
+    state: State<'_, AppState>,
+) -> Result<Timesheet, CommandError> {
+    let user = require_user(&state, "view a timesheet")?;
+    let date = date.unwrap_or_else(|| state.clock.now().with_timezone(&state.timezone).date_naive());
+    let rounding = state.capture_policy.lock().await.timesheet_rounding();
+    let team_member_id = team_member_id.unwrap_or(user.team_member_id);
+    Ok(timesheets::timesheet(&state.db_pool, user, team_member_id, date, state.timezone, rounding).await?)
+}
+
+// Tauri command to submit the user's timesheet for the week `date` falls in, locking that week
+#[tauri::command]
+async fn submit_timesheet(date: chrono::NaiveDate, state: State<'_, AppState>) -> Result<Timesheet, CommandError> {
+    let user = require_user(&state, "submit a timesheet")?;
+    let rounding = state.capture_policy.lock().await.timesheet_rounding();
+    Ok(timesheets::submit(&state.db_pool, user, date, state.timezone, rounding, state.clock.now()).await?)
+}
+
+// Tauri command for managers to unlock a submitted or approved week of a team member
+#[tauri::command]
+async fn reopen_timesheet(
+    team_member_id: i32,
+    date: chrono::NaiveDate,
+    comment: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<(), CommandError> {
+    let user = require_user(&state, "reopen a timesheet")?;
+    Ok(timesheets::reopen(&state.db_pool, user, team_member_id, date, comment, state.clock.now()).await?)
+}
+
+// Tauri command to list stored timesheets: the user's own, or their organization's for managers
+#[tauri::command]
+async fn list_timesheets(
+    status: Option<TimesheetStatus>,
+    state: State<'_, AppState>,
+) -> Result<Vec<TimesheetSummary>, CommandError> {
+    let user = require_user(&state, "list timesheets")?;
+    Ok(timesheets::list(&state.db_pool, user, status).await?)
+}
+
+// Tauri command for managers to approve a team member's submitted timesheet
+#[tauri::command]
+async fn approve_timesheet(
+    team_member_id: i32,
+    date: chrono::NaiveDate,
+    comment: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<(), CommandError> {
+    let user = require_user(&state, "approve a timesheet")?;
+    let now = state.clock.now();
+    Ok(timesheets::review(&state.db_pool, user, team_member_id, date, TimesheetStatus::Approved, comment, now).await?)
+}
+
+// Tauri command for managers to send a submitted timesheet back, saying what to correct
+#[tauri::command]
+async fn reject_timesheet(
+    team_member_id: i32,
+    date: chrono::NaiveDate,
+    comment: String,
+    state: State<'_, AppState>,
+) -> Result<(), CommandError> {
+    let user = require_user(&state, "reject a timesheet")?;
+    let now = state.clock.now();
+    Ok(timesheets::review(&state.db_pool, user, team_member_id, date, TimesheetStatus::Rejected, Some(comment), now).await?)
+}
+
+// Tauri command to get the submission and review history of a week's timesheet
+#[tauri::command]
+async fn get_timesheet_history(
+    team_member_id: Option<i32>,
+    date: chrono::NaiveDate,
+    state: State<'_, AppState>,
+) -> Result<Vec<TimesheetEvent>, CommandError> {
+    let user = require_user(&state, "view timesheet history")?;
+    Ok(timesheets::history(&state.db_pool, user, team_member_id.unwrap_or(user.team_member_id), date).await?)
+}
+
+// --- NEW COMMAND: get_elapsed_time ---
+#[tauri::command]
+async fn get_elapsed_time(state: State<'_, AppState>) -> Result<u64, CommandError> {
+    require_user(&state, "view the elapsed time")?;
+    let status = state.timer.status().await;
+    let start_time_opt = *state.session_start_time.lock().await;
+    let now = state.clock.now();
+    let paused = state.paused_time.lock().await.total(now);
+    Ok(elapsed_seconds(&status, start_time_opt, paused, now))
+}
+
+// The team member using this install, checked by every command before it does anything.
+// `action` says what they tried, for the log if nobody is signed in.
+fn require_user<'a>(state: &'a AppState, action: &str) -> Result<&'a CurrentUser, Denial> {
+    authorization::signed_in(state.current_user.as_ref(), action)
+}
+
+// Tauri command to get the team member using this install. The one command open without a
+// signed-in team member, so the UI can explain how to configure one.
+#[tauri::command]
+fn get_current_user(state: State<'_, AppState>) -> Result<Option<CurrentUser>, CommandError> {
+    Ok(state.current_user.clone())
+}
+
+// Tauri command to log time worked away from the computer; it starts out pending approval
+#[tauri::command]
+async fn create_manual_entry(entry: ManualEntryInput, state: State<'_, AppState>) -> Result<ManualTimeEntry, CommandError> {
+    let user = require_user(&state, "create a manual entry")?;
+    Ok(manual_entries::create_entry(&state.db_pool, user, entry, state.clock.now()).await?)
+}
+
+// Tauri command to edit one of the user's manual entries; it goes back to pending approval
+#[tauri::command]
+async fn update_manual_entry(
+    id: String,
+    entry: ManualEntryInput,
+    state: State<'_, AppState>,
+) -> Result<ManualTimeEntry, CommandError> {
+    let user = require_user(&state, "change a manual entry")?;
+    let entry_id = Uuid::parse_str(&id).map_err(|_| AppError::Invalid("Invalid UUID format".to_string()))?;
+    Ok(manual_entries::update_entry(&state.db_pool, user, entry_id, entry, state.clock.now()).await?)
+}
+
+// Tauri command to delete one of the user's manual entries that hasn't been approved
+#[tauri::command]
+async fn delete_manual_entry(id: String, state: State<'_, AppState>) -> Result<(), CommandError> {
+    let user = require_user(&state, "delete a manual entry")?;
+    let entry_id = Uuid::parse_str(&id).map_err(|_| AppError::Invalid("Invalid UUID format".to_string()))?;
+    Ok(manual_entries::delete_entry(&state.db_pool, user, entry_id).await?)
+}
+
+// Tauri command to list manual entries: the user's own, or the organization's for managers
+#[tauri::command]
+async fn list_manual_entries(
+    status: Option<ManualEntryStatus>,
+    state: State<'_, AppState>,
+) -> Result<Vec<ManualTimeEntry>, CommandError> {
+    let user = require_user(&state, "list manual entries")?;
+    Ok(manual_entries::list_entries(&state.db_pool, user, status).await?)
+}
+
+// Tauri command for managers to approve a pending manual entry
+#[tauri::command]
+async fn approve_manual_entry(id: String, state: State<'_, AppState>) -> Result<ManualTimeEntry, CommandError> {
+    let user = require_user(&state, "approve a manual entry")?;
+    let entry_id = Uuid::parse_str(&id).map_err(|_| AppError::Invalid("Invalid UUID format".to_string()))?;
+    Ok(manual_entries::review_entry(&state.db_pool, user, entry_id, ManualEntryStatus::Approved, state.clock.now()).await?)
+}
+
+// Tauri command for managers to reject a pending manual entry
 #[tauri::command]
-async fn get_timer_status(state: State<'_, AppState>) -> Result<TimerStatus, String> {
-    Ok(state.timer_status.lock().await.clone())
+async fn reject_manual_entry(id: String, state: State<'_, AppState>) -> Result<ManualTimeEntry, CommandError> {
+    let user = require_user(&state, "reject a manual entry")?;
+    let entry_id = Uuid::parse_str(&id).map_err(|_| AppError::Invalid("Invalid UUID format".to_string()))?;
+    Ok(manual_entries::review_entry(&state.db_pool, user, entry_id, ManualEntryStatus::Rejected, state.clock.now()).await?)
 }
 
+// Applies a correction to a finished session on behalf of the signed-in team member
+async fn edit_session(
+    state: &AppState,
+    session_id: &str,
+    edit: SessionEdit,
+    reason: &str,
+) -> Result<Vec<SessionSnapshot>, AppError> {
+    let user = require_user(state, "edit a session")?;
+    let session_uuid = Uuid::parse_str(session_id).map_err(|_| AppError::Invalid("Invalid UUID format".to_string()))?;
+    session_edits::apply_edit(&state.db_pool, user, session_uuid, edit, reason, state.clock.now()).await
+}
 
-// --- NEW COMMAND: get_screenshot_data ---
+// Tauri command to move a finished session's start later and/or its end earlier
 #[tauri::command]
-async fn get_screenshot_data(
-    id: String, // Receive UUID as String from JS
+async fn trim_session(
+    session_id: String,
+    start_time: Option<chrono::DateTime<Utc>>,
+    end_time: Option<chrono::DateTime<Utc>>,
+    reason: String,
     state: State<'_, AppState>,
-) -> Result<String, String> {
-    let screenshot_uuid = Uuid::parse_str(&id)
-        .map_err(|_| "Invalid UUID format".to_string())?;
-
-    println!("Fetching screenshot data for ID: {}", screenshot_uuid);
-
-    // Use query() function (runtime check) for fetching screenshot data
-    let record = sqlx::query("SELECT image_data FROM screenshots WHERE id = $1") // Use query()
-        .bind(screenshot_uuid)
-        .fetch_optional(&state.db_pool)
-        .await
-        .map_err(|e| format!("Database error fetching screenshot: {}", e))?;
+) -> Result<Vec<SessionSnapshot>, CommandError> {
+    Ok(edit_session(&state, &session_id, SessionEdit::Trim { start_time, end_time }, &reason).await?)
+}
 
-    if let Some(rec) = record {
-        // Need to get the column data using column name or index with query()
-        use sqlx::Row;
-        let image_data: Vec<u8> = rec.try_get("image_data")
-            .map_err(|e| format!("Failed to get image_data column: {}", e))?;
-        // Encode bytea data as Base64
-        let base64_image = BASE64_STANDARD.encode(&image_data);
-        Ok(format!("data:image/png;base64,{}", base64_image)) // Return data URI
-    } else {
-        Err(format!("Screenshot with ID {} not found", screenshot_uuid))
-    }
+// Tauri command to remove a time range from the middle of a finished session
+#[tauri::command]
+async fn delete_session_range(
+    session_id: String,
+    from: chrono::DateTime<Utc>,
+    to: chrono::DateTime<Utc>,
+    reason: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<SessionSnapshot>, CommandError> {
+    Ok(edit_session(&state, &session_id, SessionEdit::DeleteRange { from, to }, &reason).await?)
 }
 
-// --- NEW COMMAND: get_elapsed_time ---
+// Tauri command to split a finished session in two
 #[tauri::command]
-async fn get_elapsed_time(state: State<'_, AppState>) -> Result<u64, String> {
-    let status = state.timer_status.lock().await.clone();
-    let start_time_opt = *state.session_start_time.lock().await;
+async fn split_session(
+    session_id: String,
+    at: chrono::DateTime<Utc>,
+    reason: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<SessionSnapshot>, CommandError> {
+    Ok(edit_session(&state, &session_id, SessionEdit::Split { at }, &reason).await?)
+}
 
-    match status {
-        TimerStatus::Running | TimerStatus::Paused => {
-            if let Some(start_time) = start_time_opt {
-                let now = Utc::now();
-                let duration = now.signed_duration_since(start_time);
-                // Ensure duration is non-negative before converting
-                Ok(duration.num_seconds().max(0) as u64)
-            } else {
-                // Should not happen if running/paused, but return 0 defensively
-                println!("Warning: Timer is running/paused but session start time is missing.");
-                Ok(0)
-            }
-        }
-        TimerStatus::Stopped => Ok(0), // Return 0 if stopped
-    }
+// Tauri command to delete local screenshot files, either all of them or those older than the given number of days.
+// Screenshots without a copy in the bucket are marked as expired. Admins only, as the organization may still need them.
+#[tauri::command]
+async fn purge_screenshots(
+    older_than_days: Option<u64>,
+    state: State<'_, AppState>,
+) -> Result<PurgeReport, CommandError> {
+    let user = require_user(&state, "purge screenshots")?;
+    authorization::require_admin(user, "purge screenshots")?;
+    let cutoff = match older_than_days {
+        Some(days) => Some(
+            days.checked_mul(24 * 60 * 60)
+                .and_then(|secs| std::time::SystemTime::now().checked_sub(Duration::from_secs(secs)))
+                .ok_or_else(|| AppError::Invalid(format!("Cannot purge screenshots older than {} days", days)))?,
+        ),
+        None => None,
+    };
+    let report = state.screenshot_store.local.purge(cutoff).map_err(AppError::Storage)?;
+    expire_screenshot_files(&state.db_pool, &report.hashes, state.clock.now()).await?;
+    Ok(report)
 }
 
 // Tauri command to intentionally cause a panic for Sentry testing
 #[tauri::command]
-fn test_sentry_panic() {
+fn test_sentry_panic(state: State<'_, AppState>) -> Result<(), CommandError> {
+    require_user(&state, "test error reporting")?;
     sentry::capture_message("test", sentry::Level::Info);
+    Ok(())
 }
 
 // --- NEW COMMAND: get_activity_data ---
 #[tauri::command]
-fn get_activity_data(state: State<'_, AppState>) -> Result<ActivityData, String> {
+fn get_activity_data(state: State<'_, AppState>) -> Result<ActivityData, CommandError> {
+    require_user(&state, "view activity data")?;
     // Directly use the helper function from the module
     Ok(get_current_counts(&state.activity_counters))
 }
@@ -576,6 +1924,392 @@ async fn setup_database(pool: &Pool<Postgres>) -> Result<(), sqlx::Error> {
     ).execute(pool).await?;
     println!("Column 'open_windows' ensured in 'screenshots'.");
 
+    // Screenshots now live in the local store and are referenced by content hash,
+    // so image_data is only populated on rows written before the store existed.
+    sqlx::query(
+        r#"
+        DO $$
+        BEGIN
+            IF NOT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name='screenshots' AND column_name='image_hash') THEN
+                ALTER TABLE screenshots ADD COLUMN image_hash TEXT NULL;
+            END IF;
+        END $$;
+        "#
+    ).execute(pool).await?;
+    sqlx::query("ALTER TABLE screenshots ALTER COLUMN image_data DROP NOT NULL;")
+        .execute(pool)
+        .await?;
+    println!("Column 'image_hash' ensured in 'screenshots'.");
+
+    // With object storage the row keeps only the key and size of the uploaded image
+    sqlx::query(
+        r#"
+        DO $$
+        BEGIN
+            IF NOT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name='screenshots' AND column_name='image_key') THEN
+                ALTER TABLE screenshots ADD COLUMN image_key TEXT NULL;
+            END IF;
+            IF NOT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name='screenshots' AND column_name='image_size') THEN
+                ALTER TABLE screenshots ADD COLUMN image_size BIGINT NULL;
+            END IF;
+        END $$;
+        "#
+    ).execute(pool).await?;
+    println!("Columns 'image_key' and 'image_size' ensured in 'screenshots'.");
+
+    // Perceptual hash and change detection columns
+    sqlx::query(
+        r#"
+        DO $$
+        BEGIN
+            IF NOT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name='screenshots' AND column_name='perceptual_hash') THEN
+                ALTER TABLE screenshots ADD COLUMN perceptual_hash BIGINT NULL;
+            END IF;
+            IF NOT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name='screenshots' AND column_name='change_score') THEN
+                ALTER TABLE screenshots ADD COLUMN change_score REAL NULL;
+            END IF;
+            IF NOT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name='screenshots' AND column_name='is_duplicate') THEN
+                ALTER TABLE screenshots ADD COLUMN is_duplicate BOOLEAN NOT NULL DEFAULT FALSE;
+            END IF;
+            IF NOT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name='screenshots' AND column_name='duplicate_of') THEN
+                ALTER TABLE screenshots ADD COLUMN duplicate_of UUID NULL; -- Row holding the image for unchanged-screen markers
+            END IF;
+            IF NOT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name='screenshots' AND column_name='unchanged_since') THEN
+                ALTER TABLE screenshots ADD COLUMN unchanged_since TIMESTAMPTZ NULL;
+            END IF;
+        END $$;
+        "#
+    ).execute(pool).await?;
+    println!("Screen-change columns ensured in 'screenshots'.");
+
+    // Blur level applied to each capture, and the encrypted unblurred copy if one was kept
+    sqlx::query(
+        r#"
+        DO $$
+        BEGIN
+            IF NOT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name='screenshots' AND column_name='blur_level') THEN
+                ALTER TABLE screenshots ADD COLUMN blur_level TEXT NOT NULL DEFAULT 'none';
+            END IF;
+            IF NOT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name='screenshots' AND column_name='original_hash') THEN
+                ALTER TABLE screenshots ADD COLUMN original_hash TEXT NULL;
+            END IF;
+            IF NOT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name='screenshots' AND column_name='original_key') THEN
+                ALTER TABLE screenshots ADD COLUMN original_key TEXT NULL;
+            END IF;
+        END $$;
+        "#
+    ).execute(pool).await?;
+    println!("Blur columns ensured in 'screenshots'.");
+
+    // Set when retention deleted the only copy of the image or of the unblurred copy
+    sqlx::query(
+        r#"
+        DO $$
+        BEGIN
+            IF NOT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name='screenshots' AND column_name='image_expired_at') THEN
+                ALTER TABLE screenshots ADD COLUMN image_expired_at TIMESTAMPTZ NULL;
+            END IF;
+            IF NOT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name='screenshots' AND column_name='original_expired_at') THEN
+                ALTER TABLE screenshots ADD COLUMN original_expired_at TIMESTAMPTZ NULL;
+            END IF;
+        END $$;
+        "#
+    ).execute(pool).await?;
+    println!("Expiry columns ensured in 'screenshots'.");
+
+    sqlx::query(
+        r#"
+        DO $$
+        BEGIN
+            IF NOT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name='screenshots' AND column_name='redacted_regions') THEN
+                ALTER TABLE screenshots ADD COLUMN redacted_regions JSONB NULL; -- Rectangles hidden by redaction rules
+            END IF;
+        END $$;
+        "#
+    ).execute(pool).await?;
+    println!("Column 'redacted_regions' ensured in 'screenshots'.");
+
+    sqlx::query(
+        r#"
+        DO $$
+        BEGIN
+            IF NOT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name='screenshots' AND column_name='focused_app') THEN
+                ALTER TABLE screenshots ADD COLUMN focused_app TEXT NULL; -- Window in front at capture time
+                ALTER TABLE screenshots ADD COLUMN focused_title TEXT NULL; -- NULL when a redaction rule hides it
+            END IF;
+        END $$;
+        "#
+    ).execute(pool).await?;
+    sqlx::query(
+        r#"
+        DO $$
+        BEGIN
+            IF NOT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name='screenshots' AND column_name='focused_browser') THEN
+                ALTER TABLE screenshots ADD COLUMN focused_browser TEXT NULL; -- Set when the window in front is a browser
+                ALTER TABLE screenshots ADD COLUMN focused_domain TEXT NULL; -- Site it showed, if known
+            END IF;
+        END $$;
+        "#
+    ).execute(pool).await?;
+    println!("Focused window columns ensured in 'screenshots'.");
+
+    // Page titles whose site is known from a URL seen in them, so they resolve without one.
+    // Titles can name customers or documents, so each organization learns its own.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS learned_title_domains (
+            organization_id INTEGER NOT NULL,
+            title_key TEXT NOT NULL,
+            domain TEXT NOT NULL,
+            learned_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+            PRIMARY KEY (organization_id, title_key)
+        );
+        "#
+    ).execute(pool).await?;
+    println!("Table 'learned_title_domains' ensured.");
+
+    sqlx::query(
+        r#"
+        DO $$
+        BEGIN
+            IF NOT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name='screenshots' AND column_name='removed_by_edit') THEN
+                ALTER TABLE screenshots ADD COLUMN removed_by_edit UUID NULL; -- Session edit that cut this capture's time out
+            END IF;
+            IF NOT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name='sessions' AND column_name='team_member_id') THEN
+                ALTER TABLE sessions ADD COLUMN team_member_id INTEGER NULL; -- Who tracked the session; NULL for older sessions
+            END IF;
+        END $$;
+        "#
+    ).execute(pool).await?;
+    println!("Session edit columns ensured.");
+
+    // Per-organization policies, stored as JSON so new settings don't need a migration
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS org_policies (
+            organization_id INTEGER PRIMARY KEY,
+            capture_policy JSONB NOT NULL DEFAULT '{}'::jsonb,
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+        );
+        "#
+    ).execute(pool).await?;
+    println!("Table 'org_policies' ensured.");
+
+    // Append-only log of timer status changes and why they happened
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS timer_transitions (
+            id BIGSERIAL PRIMARY KEY,
+            session_id UUID,
+            from_status TEXT NOT NULL,
+            to_status TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            occurred_at TIMESTAMPTZ NOT NULL
+        );
+        "#
+    ).execute(pool).await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_timer_transitions_session ON timer_transitions (session_id);")
+        .execute(pool).await?;
+    println!("Table 'timer_transitions' ensured.");
+
+    // A session is split into task segments, one per task worked on
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS task_segments (
+            id UUID PRIMARY KEY,
+            session_id UUID NOT NULL REFERENCES sessions(id),
+            task TEXT,
+            project TEXT,
+            start_time TIMESTAMPTZ NOT NULL,
+            end_time TIMESTAMPTZ,
+            key_press_count INTEGER,
+            mouse_click_count INTEGER
+        );
+        "#
+    ).execute(pool).await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_task_segments_session ON task_segments (session_id);")
+        .execute(pool).await?;
+    println!("Table 'task_segments' ensured.");
+
+    // Projects the user turned down for a task segment, so they aren't suggested for it again
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS project_suggestion_rejections (
+            segment_id UUID NOT NULL REFERENCES task_segments(id) ON DELETE CASCADE,
+            project TEXT NOT NULL,
+            rejected_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+            PRIMARY KEY (segment_id, project)
+        );
+        "#
+    ).execute(pool).await?;
+    println!("Table 'project_suggestion_rejections' ensured.");
+
+    // Submitted weeks, with their totals as submitted. A submitted week is locked against edits
+    // until a manager reopens it.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS timesheets (
+            id UUID PRIMARY KEY,
+            team_member_id INTEGER NOT NULL,
+            period_start DATE NOT NULL, -- Monday of the week
+            starts_at TIMESTAMPTZ NOT NULL, -- The week's local start and end, for lock checks
+            ends_at TIMESTAMPTZ NOT NULL,
+            status TEXT NOT NULL, -- 'submitted', 'approved', 'rejected' or 'reopened'
+            totals JSONB NOT NULL,
+            submitted_at TIMESTAMPTZ NOT NULL,
+            reviewed_by INTEGER NULL,
+            reviewed_at TIMESTAMPTZ NULL,
+            review_comment TEXT NULL,
+            UNIQUE (team_member_id, period_start)
+        );
+        "#
+    ).execute(pool).await?;
+    println!("Table 'timesheets' ensured.");
+
+    // Every submission, review and reopening of a timesheet. Managers' steps are sent to the
+    // team member, which sets notified_at.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS timesheet_events (
+            id UUID PRIMARY KEY,
+            timesheet_id UUID NOT NULL REFERENCES timesheets(id),
+            status TEXT NOT NULL, -- The status the timesheet moved to
+            actor INTEGER NOT NULL,
+            comment TEXT NULL,
+            occurred_at TIMESTAMPTZ NOT NULL,
+            notified_at TIMESTAMPTZ NULL
+        );
+        "#
+    ).execute(pool).await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_timesheet_events_timesheet ON timesheet_events (timesheet_id);")
+        .execute(pool).await?;
+    println!("Table 'timesheet_events' ensured.");
+
+    // Time within a session that isn't worked: user pauses, suspends and clock changes
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS pause_segments (
+            id UUID PRIMARY KEY,
+            session_id UUID NOT NULL REFERENCES sessions(id),
+            start_time TIMESTAMPTZ NOT NULL,
+            end_time TIMESTAMPTZ,
+            reason TEXT NOT NULL
+        );
+        "#
+    ).execute(pool).await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_pause_segments_session ON pause_segments (session_id);")
+        .execute(pool).await?;
+    sqlx::query(
+        r#"
+        DO $$
+        BEGIN
+            IF NOT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name='sessions' AND column_name='clock_moved_backwards') THEN
+                ALTER TABLE sessions ADD COLUMN clock_moved_backwards BOOLEAN NOT NULL DEFAULT FALSE; -- Its times can't be trusted; review by hand
+            END IF;
+        END $$;
+        "#
+    ).execute(pool).await?;
+    println!("Table 'pause_segments' ensured.");
+
+    // Working hours per team member; sessions keep a copy of the one they were tracked under
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS work_schedules (
+            team_member_id INTEGER PRIMARY KEY,
+            schedule JSONB NOT NULL,
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+        );
+        "#
+    ).execute(pool).await?;
+    sqlx::query(
+        r#"
+        DO $$
+        BEGIN
+            IF NOT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name='sessions' AND column_name='work_schedule') THEN
+                ALTER TABLE sessions ADD COLUMN work_schedule JSONB; -- NULL when tracked without a schedule
+            END IF;
+        END $$;
+        "#
+    ).execute(pool).await?;
+    println!("Table 'work_schedules' ensured.");
+
+    // Daily and weekly hour limits and contracted hours per team member
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS hour_limits (
+            team_member_id INTEGER PRIMARY KEY,
+            limits JSONB NOT NULL,
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+        );
+        "#
+    ).execute(pool).await?;
+    println!("Table 'hour_limits' ensured.");
+
+    // Time logged by hand (meetings, calls), kept apart from tracked sessions until a manager approves it
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS manual_time_entries (
+            id UUID PRIMARY KEY,
+            team_member_id INTEGER NOT NULL,
+            organization_id INTEGER NOT NULL,
+            task TEXT NOT NULL,
+            project TEXT,
+            start_time TIMESTAMPTZ NOT NULL,
+            end_time TIMESTAMPTZ NOT NULL,
+            reason TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'approved', 'rejected')),
+            reviewed_by INTEGER,
+            reviewed_at TIMESTAMPTZ,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+            CHECK (end_time > start_time)
+        );
+        "#
+    ).execute(pool).await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_manual_time_entries_member ON manual_time_entries (team_member_id, start_time);")
+        .execute(pool).await?;
+    println!("Table 'manual_time_entries' ensured.");
+
+    // Audit trail of session corrections. Rows can only be added, never changed or removed.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS session_edits (
+            id UUID PRIMARY KEY,
+            session_id UUID NOT NULL,
+            edit_type TEXT NOT NULL,
+            before_state JSONB NOT NULL,
+            after_state JSONB NOT NULL,
+            removed_screenshots INTEGER NOT NULL DEFAULT 0,
+            edited_by INTEGER NOT NULL,
+            reason TEXT NOT NULL,
+            edited_at TIMESTAMPTZ NOT NULL
+        );
+        "#
+    ).execute(pool).await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_session_edits_session ON session_edits (session_id);")
+        .execute(pool).await?;
+    sqlx::query(
+        r#"
+        CREATE OR REPLACE FUNCTION reject_session_edit_changes() RETURNS trigger AS $$
+        BEGIN
+            RAISE EXCEPTION 'session_edits is append-only';
+        END;
+        $$ LANGUAGE plpgsql;
+        "#
+    ).execute(pool).await?;
+    sqlx::query(
+        r#"
+        DO $$
+        BEGIN
+            IF NOT EXISTS (SELECT 1 FROM pg_trigger WHERE tgname = 'session_edits_append_only') THEN
+                CREATE TRIGGER session_edits_append_only BEFORE UPDATE OR DELETE ON session_edits
+                FOR EACH ROW EXECUTE FUNCTION reject_session_edit_changes();
+            END IF;
+        END $$;
+        "#
+    ).execute(pool).await?;
+    println!("Table 'session_edits' ensured.");
+
 
      // Add FK constraint separately to handle potential timing issues or existing data
      // This might fail if there are existing screenshots without a valid session_id.
@@ -623,7 +2357,8 @@ fn main() {
     // We need to run the async database setup within a tokio runtime
     // Tauri's main thread isn't async by default before run()
     let rt = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
-    let db_pool = rt.block_on(async {
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+    let (db_pool, capture_policy, current_user, title_domains) = rt.block_on(async {
         let pool = pool_options
             .connect(&database_url)
             .await
@@ -631,28 +2366,48 @@ fn main() {
         setup_database(&pool)
             .await
             .expect("Failed to setup database");
-        pool
+        let user = load_current_user(&pool, team_member_email_from_env().as_deref())
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("Warning: {}", e);
+                None
+            });
+        let policy = load_capture_policy(&pool, user.as_ref().map(|user| user.organization_id))
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("Warning: {}. Using the default capture policy.", e);
+                CapturePolicy::default()
+            });
+        match &user {
+            Some(user) => println!("Signed in as {} ({:?}).", user.email, user.role),
+            None => println!("No team member configured; every command but get_current_user will be refused."),
+        }
+        // Sessions still open from the last run were interrupted by a crash or forced quit
+        match recover_open_sessions(&pool, clock.as_ref(), user.as_ref().map(|user| user.team_member_id)).await {
+            Ok(0) => {}
+            Ok(count) => println!("Recovered {} session(s) left open by the previous run.", count),
+            Err(e) => eprintln!("Warning: {}", e),
+        }
+        let title_domains = TitleDomains::load(&pool, user.as_ref().map(|user| user.organization_id)).await.unwrap_or_else(|e| {
+            eprintln!("Warning: {}", e);
+            TitleDomains::default()
+        });
+        (pool, policy, user, title_domains)
     });
 
-    // Initialize the application state
-    let app_state = AppState {
-        db_pool,
-        timer_status: Arc::new(Mutex::new(TimerStatus::Stopped)),
-        command_tx: Arc::new(Mutex::new(None)),
-        current_session_id: Arc::new(Mutex::new(None)), // Initialize new state field
-        session_start_time: Arc::new(Mutex::new(None)), // Initialize new state field
-        activity_counters: Arc::new(ActivityCounters::default()), // Initialize activity counters
-        is_session_active: Arc::new(AtomicBool::new(false)), // Initialize session active flag
-    };
-
-    // --- Spawn Activity Monitor Thread ---
-    // rdev::listen is blocking, so it needs its own dedicated thread, not a tokio task.
-    let activity_counters_clone = Arc::clone(&app_state.activity_counters);
-    let is_session_active_clone = Arc::clone(&app_state.is_session_active); // Clone the flag
-    std::thread::spawn(move || {
-        activity_listen(activity_counters_clone, is_session_active_clone); // Pass the flag
-    });
-    // --- End Spawn Activity Monitor Thread ---
+    // `--migrate-screenshots` moves inline BYTEA screenshots into the bucket and exits
+    if std::env::args().any(|arg| arg == "--migrate-screenshots") {
+        let config = S3Config::from_env().expect("S3_BUCKET must be set to migrate screenshots");
+        let store = S3ScreenshotStore::new(config);
+        match rt.block_on(migrate_inline_screenshots(&db_pool, &store)) {
+            Ok(count) => println!("Migrated {} screenshots to object storage.", count),
+            Err(e) => {
+                eprintln!("Screenshot migration failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
     tauri::Builder::default()
         .plugin(tauri_plugin_single_instance::init(|app, _argv, _cwd| {
@@ -664,15 +2419,125 @@ fn main() {
         }))
         // Removed tauri_plugin_shell as it's not used and wasn't added as a dependency
         .plugin(tauri_plugin_opener::init())
-        .manage(app_state) // Add the state to Tauri
+        .setup(move |app| {
+            // The screenshot store lives under the app data dir, which is only known once the app exists
+            let app_data_dir = app.path().app_data_dir()?;
+            let local_store = LocalScreenshotStore::from_env(&app_data_dir);
+            let remote_store = S3Config::from_env().map(|config| {
+                println!("Uploading screenshots to bucket '{}'.", config.bucket);
+                S3ScreenshotStore::new(config)
+            });
+            let screenshot_store = ScreenshotStorage {
+                local: local_store,
+                remote: remote_store,
+            };
+
+            // Timer status changes are logged to the database and announced to the frontend
+            let transition_observer = AppTransitionObserver {
+                db_pool: db_pool.clone(),
+                app_handle: app.handle().clone(),
+            };
+            let timer = Arc::new(TimerStateMachine::new(Arc::new(transition_observer), Arc::clone(&clock)));
+
+            // logind tells a suspend apart from the clock being set forwards
+            let sleep_log = Arc::new(SleepLog::default());
+            let watched_sleep_log = Arc::clone(&sleep_log);
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) = sleep_monitor::watch_logind(watched_sleep_log).await {
+                    eprintln!("Warning: {}. Suspends are detected from clock drift alone.", e);
+                }
+            });
+
+            // Initialize the application state
+            let app_state = AppState {
+                db_pool,
+                timer,
+                command_tx: Arc::new(Mutex::new(None)),
+                current_session_id: Arc::new(Mutex::new(None)), // Initialize new state field
+                session_start_time: Arc::new(Mutex::new(None)), // Initialize new state field
+                activity_counters: Arc::new(ActivityCounters::default()), // Initialize activity counters
+                current_segment: Arc::new(Mutex::new(None)),
+                session_base_counts: Arc::new(Mutex::new(ActivityData::default())),
+                current_pause: Arc::new(Mutex::new(None)),
+                paused_time: Arc::new(Mutex::new(PausedTime::default())),
+                sleep_log,
+                pomodoro_settings: Arc::new(Mutex::new(None)),
+                pomodoro_state: Arc::new(std::sync::Mutex::new(None)),
+                is_session_active: Arc::new(AtomicBool::new(false)), // Initialize session active flag
+                untracked_activity: Arc::new(UntrackedActivity::default()),
+                screenshot_store: Arc::new(screenshot_store),
+                current_user,
+                capture_policy: Arc::new(Mutex::new(capture_policy)),
+                title_domains: Arc::new(title_domains),
+                work_schedule: Arc::new(Mutex::new(None)),
+                hour_limits: Arc::new(Mutex::new(None)),
+                clock,
+                rng: system_rng(),
+                timezone: workday::user_timezone(),
+            };
+
+            // --- Spawn Activity Monitor Thread ---
+            // rdev::listen is blocking, so it needs its own dedicated thread, not a tokio task.
+            let activity_counters_clone = Arc::clone(&app_state.activity_counters);
+            let is_session_active_clone = Arc::clone(&app_state.is_session_active); // Clone the flag
+            let untracked_activity_clone = Arc::clone(&app_state.untracked_activity);
+            let clock_clone = Arc::clone(&app_state.clock);
+            std::thread::spawn(move || {
+                activity_listen(activity_counters_clone, is_session_active_clone, untracked_activity_clone, clock_clone); // Pass the flag
+            });
+            // --- End Spawn Activity Monitor Thread ---
+
+            app.manage(app_state); // Add the state to Tauri
+            tauri::async_runtime::spawn(remind_to_start(app.handle().clone()));
+            tauri::async_runtime::spawn(watch_hour_limits(app.handle().clone()));
+            tauri::async_runtime::spawn(notify_timesheet_reviews(app.handle().clone()));
+            tauri::async_runtime::spawn(enforce_screenshot_retention(app.handle().clone()));
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             start_timer,
+            start_timer_from_activity,
             stop_timer,
+            set_pomodoro,
+            get_pomodoro_state,
+            get_work_schedule,
+            set_work_schedule,
+            get_hour_progress,
+            get_hour_limits,
+            set_hour_limits,
             pause_timer,
             resume_timer,
+            switch_task,
             get_timer_status,
             get_elapsed_time, // Added
             get_screenshot_data, // Added
+            purge_screenshots,
+            get_screen_change_scores,
+            get_unblurred_screenshot,
+            get_capture_policy,
+            set_category_rules,
+            get_session_productivity,
+            get_daily_productivity,
+            get_project_suggestions,
+            accept_project_suggestion,
+            reject_project_suggestion,
+            get_timesheet,
+            submit_timesheet,
+            reopen_timesheet,
+            list_timesheets,
+            approve_timesheet,
+            reject_timesheet,
+            get_timesheet_history,
+            get_current_user,
+            create_manual_entry,
+            update_manual_entry,
+            delete_manual_entry,
+            list_manual_entries,
+            approve_manual_entry,
+            reject_manual_entry,
+            trim_session,
+            delete_session_range,
+            split_session,
             test_sentry_panic,
             get_activity_data // Added activity data command
         ])
@@ -722,4 +2587,35 @@ mod tests {
         assert_eq!(result, 4);
 
     }
+
+    // Needs Postgres, see `test_db`
+    #[tokio::test]
+    #[ignore]
+    async fn only_screenshots_without_a_bucket_copy_expire() {
+        let pool = test_db::pool().await;
+        let hash = Uuid::new_v4().simple().to_string();
+        let now = Utc::now();
+        let mut ids = Vec::new();
+        for key in [None, Some("screenshots/uploaded.png")] {
+            let id = Uuid::new_v4();
+            sqlx::query("INSERT INTO screenshots (id, capture_time, image_hash, image_key) VALUES ($1, $2, $3, $4)")
+                .bind(id)
+                .bind(now)
+                .bind(&hash)
+                .bind(key)
+                .execute(&pool)
+                .await
+                .unwrap();
+            ids.push(id);
+        }
+
+        expire_screenshot_files(&pool, &[hash], now).await.unwrap();
+
+        let expired: Vec<bool> = sqlx::query_scalar("SELECT image_expired_at IS NOT NULL FROM screenshots WHERE id = ANY($1) ORDER BY image_key NULLS FIRST")
+            .bind(&ids)
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(expired, vec![true, false]);
+    }
 }
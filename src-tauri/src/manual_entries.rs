@@ -0,0 +1,384 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{Pool, Postgres, Row};
+use uuid::Uuid;
+
+use crate::authorization::{self, Owner};
+use crate::errors::AppError;
+use crate::identity::{CurrentUser, TeamMemberRole};
+use crate::timesheets;
+
+/// Review state of a manual entry. New and edited entries are pending until a manager reviews them.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ManualEntryStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl ManualEntryStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ManualEntryStatus::Pending => "pending",
+            ManualEntryStatus::Approved => "approved",
+            ManualEntryStatus::Rejected => "rejected",
+        }
+    }
+
+    fn parse(value: &str) -> Result<Self, AppError> {
+        match value {
+            "pending" => Ok(ManualEntryStatus::Pending),
+            "approved" => Ok(ManualEntryStatus::Approved),
+            "rejected" => Ok(ManualEntryStatus::Rejected),
+            other => Err(AppError::Db(format!("Unknown manual entry status '{}'", other))),
+        }
+    }
+}
+
+/// Time worked away from the computer (a meeting, a phone call), logged by hand.
+/// Kept in `manual_time_entries`, apart from tracked sessions.
+#[derive(Serialize, Clone, Debug)]
+pub struct ManualTimeEntry {
+    pub id: Uuid,
+    pub team_member_id: i32,
+    pub organization_id: i32,
+    pub task: String,
+    pub project: Option<String>,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub reason: String,
+    pub status: ManualEntryStatus,
+    pub reviewed_by: Option<i32>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What the user fills in when creating or editing an entry.
+#[derive(Deserialize, Clone, Debug)]
+pub struct ManualEntryInput {
+    pub task: String,
+    pub project: Option<String>,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub reason: String,
+}
+
+const MAX_ENTRY_LENGTH_HOURS: i64 = 24;
+
+const ENTRY_COLUMNS: &str = "id, team_member_id, organization_id, task, project, start_time, end_time, reason, \
+                             status, reviewed_by, reviewed_at, created_at";
+
+impl ManualEntryInput {
+    /// Trims the text fields and rejects entries that are empty, inverted, in the future or longer than a day.
+    fn validated(self, now: DateTime<Utc>) -> Result<Self, AppError> {
+        let task = self.task.trim().to_string();
+        let reason = self.reason.trim().to_string();
+        let project = self.project.map(|p| p.trim().to_string()).filter(|p| !p.is_empty());
+        if task.is_empty() {
+            return Err(AppError::Invalid("A manual entry needs a task.".to_string()));
+        }
+        if reason.is_empty() {
+            return Err(AppError::Invalid("A manual entry needs a reason.".to_string()));
+        }
+        if self.end_time <= self.start_time {
+            return Err(AppError::Invalid("A manual entry must end after it starts.".to_string()));
+        }
+        if self.end_time > now {
+            return Err(AppError::Invalid("Manual entries can only be added for time that has already passed.".to_string()));
+        }
+        if self.end_time - self.start_time > Duration::hours(MAX_ENTRY_LENGTH_HOURS) {
+            return Err(AppError::Invalid(format!("A manual entry can be at most {} hours long.", MAX_ENTRY_LENGTH_HOURS)));
+        }
+        Ok(ManualEntryInput { task, project, reason, ..self })
+    }
+}
+
+/// Only the owner may change an entry, and only until it has been approved.
+fn check_can_edit(user: &CurrentUser, entry: &ManualTimeEntry) -> Result<(), AppError> {
+    authorization::require_own(user, "change a manual entry", entry.team_member_id)?;
+    if entry.status == ManualEntryStatus::Approved {
+        return Err(AppError::Invalid("Approved manual entries can no longer be changed.".to_string()));
+    }
+    Ok(())
+}
+
+/// Managers review pending entries of their own organization, admins of any, but never their own.
+fn check_can_review(reviewer: &CurrentUser, entry: &ManualTimeEntry) -> Result<(), AppError> {
+    let owner = Owner { team_member_id: Some(entry.team_member_id), organization_id: Some(entry.organization_id) };
+    authorization::check_manager_of(reviewer, "review a manual entry", owner)?;
+    if entry.team_member_id == reviewer.team_member_id {
+        return Err(AppError::Invalid("You cannot review your own manual entries.".to_string()));
+    }
+    if entry.status != ManualEntryStatus::Pending {
+        return Err(AppError::Invalid(format!("This manual entry is already {}.", entry.status.as_str())));
+    }
+    Ok(())
+}
+
+fn entry_from_row(row: &PgRow) -> Result<ManualTimeEntry, AppError> {
+    let column_error = |name: &str, e: sqlx::Error| AppError::Db(format!("Failed to get {} column: {}", name, e));
+    let status: String = row.try_get("status").map_err(|e| column_error("status", e))?;
+    Ok(ManualTimeEntry {
+        id: row.try_get("id").map_err(|e| column_error("id", e))?,
+        team_member_id: row.try_get("team_member_id").map_err(|e| column_error("team_member_id", e))?,
+        organization_id: row.try_get("organization_id").map_err(|e| column_error("organization_id", e))?,
+        task: row.try_get("task").map_err(|e| column_error("task", e))?,
+        project: row.try_get("project").map_err(|e| column_error("project", e))?,
+        start_time: row.try_get("start_time").map_err(|e| column_error("start_time", e))?,
+        end_time: row.try_get("end_time").map_err(|e| column_error("end_time", e))?,
+        reason: row.try_get("reason").map_err(|e| column_error("reason", e))?,
+        status: ManualEntryStatus::parse(&status)?,
+        reviewed_by: row.try_get("reviewed_by").map_err(|e| column_error("reviewed_by", e))?,
+        reviewed_at: row.try_get("reviewed_at").map_err(|e| column_error("reviewed_at", e))?,
+        created_at: row.try_get("created_at").map_err(|e| column_error("created_at", e))?,
+    })
+}
+
+pub async fn get_entry(pool: &Pool<Postgres>, id: Uuid) -> Result<ManualTimeEntry, AppError> {
+    let row = sqlx::query(&format!("SELECT {} FROM manual_time_entries WHERE id = $1", ENTRY_COLUMNS))
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::Db(format!("Database error fetching manual entry: {}", e)))?
+        .ok_or_else(|| AppError::NotFound(format!("Manual entry {} not found.", id)))?;
+    entry_from_row(&row)
+}
+
+pub async fn create_entry(
+    pool: &Pool<Postgres>,
+    user: &CurrentUser,
+    input: ManualEntryInput,
+    now: DateTime<Utc>,
+) -> Result<ManualTimeEntry, AppError> {
+    let input = input.validated(now)?;
+    timesheets::ensure_unlocked(pool, user.team_member_id, input.start_time, input.end_time).await?;
+    let row = sqlx::query(&format!(
+        r#"
+        INSERT INTO manual_time_entries (id, team_member_id, organization_id, task, project, start_time, end_time, reason, status, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, 'pending', $9, $9)
+        RETURNING {}
+        "#,
+        ENTRY_COLUMNS
+    ))
+    .bind(Uuid::new_v4())
+    .bind(user.team_member_id)
+    .bind(user.organization_id)
+    .bind(&input.task)
+    .bind(&input.project)
+    .bind(input.start_time)
+    .bind(input.end_time)
+    .bind(&input.reason)
+    .bind(now)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to insert manual entry: {}", e)))?;
+    entry_from_row(&row)
+}
+
+/// Edits an entry. Edited entries go back to pending, so a rejected entry can be fixed and resubmitted.
+pub async fn update_entry(
+    pool: &Pool<Postgres>,
+    user: &CurrentUser,
+    id: Uuid,
+    input: ManualEntryInput,
+    now: DateTime<Utc>,
+) -> Result<ManualTimeEntry, AppError> {
+    let entry = get_entry(pool, id).await?;
+    check_can_edit(user, &entry)?;
+    let input = input.validated(now)?;
+    timesheets::ensure_unlocked(pool, user.team_member_id, entry.start_time, entry.end_time).await?;
+    timesheets::ensure_unlocked(pool, user.team_member_id, input.start_time, input.end_time).await?;
+    let row = sqlx::query(&format!(
+        r#"
+        UPDATE manual_time_entries
+        SET task = $1, project = $2, start_time = $3, end_time = $4, reason = $5,
+            status = 'pending', reviewed_by = NULL, reviewed_at = NULL, updated_at = $6
+        WHERE id = $7 AND team_member_id = $8 AND status <> 'approved'
+        RETURNING {}
+        "#,
+        ENTRY_COLUMNS
+    ))
+    .bind(&input.task)
+    .bind(&input.project)
+    .bind(input.start_time)
+    .bind(input.end_time)
+    .bind(&input.reason)
+    .bind(now)
+    .bind(id)
+    .bind(user.team_member_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to update manual entry: {}", e)))?
+    .ok_or_else(|| AppError::Invalid("The manual entry was approved while you were editing it.".to_string()))?;
+    entry_from_row(&row)
+}
+
+pub async fn delete_entry(pool: &Pool<Postgres>, user: &CurrentUser, id: Uuid) -> Result<(), AppError> {
+    let entry = get_entry(pool, id).await?;
+    check_can_edit(user, &entry)?;
+    timesheets::ensure_unlocked(pool, entry.team_member_id, entry.start_time, entry.end_time).await?;
+    let result = sqlx::query(
+        "DELETE FROM manual_time_entries WHERE id = $1 AND team_member_id = $2 AND status <> 'approved'",
+    )
+    .bind(id)
+    .bind(user.team_member_id)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to delete manual entry: {}", e)))?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::Invalid("The manual entry was approved while you were deleting it.".to_string()));
+    }
+    Ok(())
+}
+
+/// Entries visible to the user, newest first: their own, the whole organization's for managers,
+/// or everyone's for admins.
+pub async fn list_entries(
+    pool: &Pool<Postgres>,
+    user: &CurrentUser,
+    status: Option<ManualEntryStatus>,
+) -> Result<Vec<ManualTimeEntry>, AppError> {
+    let rows = sqlx::query(&format!(
+        r#"
+        SELECT {}
+        FROM manual_time_entries
+        WHERE ($5 OR organization_id = $1)
+          AND ($2 OR team_member_id = $3)
+          AND ($4::text IS NULL OR status = $4)
+        ORDER BY start_time DESC
+        "#,
+        ENTRY_COLUMNS
+    ))
+    .bind(user.organization_id)
+    .bind(user.role.is_manager())
+    .bind(user.team_member_id)
+    .bind(status.map(ManualEntryStatus::as_str))
+    .bind(user.role == TeamMemberRole::Admin)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Db(format!("Database error fetching manual entries: {}", e)))?;
+    rows.iter().map(entry_from_row).collect()
+}
+
+/// Approves or rejects a pending entry.
+pub async fn review_entry(
+    pool: &Pool<Postgres>,
+    reviewer: &CurrentUser,
+    id: Uuid,
+    decision: ManualEntryStatus,
+    now: DateTime<Utc>,
+) -> Result<ManualTimeEntry, AppError> {
+    if decision == ManualEntryStatus::Pending {
+        return Err(AppError::Invalid("A review must approve or reject the entry.".to_string()));
+    }
+    let entry = get_entry(pool, id).await?;
+    check_can_review(reviewer, &entry)?;
+    timesheets::ensure_unlocked(pool, entry.team_member_id, entry.start_time, entry.end_time).await?;
+    let row = sqlx::query(&format!(
+        r#"
+        UPDATE manual_time_entries
+        SET status = $1, reviewed_by = $2, reviewed_at = $3, updated_at = $3
+        WHERE id = $4 AND status = 'pending'
+        RETURNING {}
+        "#,
+        ENTRY_COLUMNS
+    ))
+    .bind(decision.as_str())
+    .bind(reviewer.team_member_id)
+    .bind(now)
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to review manual entry: {}", e)))?
+    .ok_or_else(|| AppError::Invalid("The manual entry changed while you were reviewing it.".to_string()))?;
+    println!("Manual entry {} {} by team member {}.", id, decision.as_str(), reviewer.team_member_id);
+    entry_from_row(&row)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authorization::Denial;
+    use chrono::TimeZone;
+
+    fn user(team_member_id: i32, role: TeamMemberRole) -> CurrentUser {
+        CurrentUser { team_member_id, organization_id: 1, email: format!("{}@example.com", team_member_id), role }
+    }
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 6, 17, 0, 0).unwrap()
+    }
+
+    fn input() -> ManualEntryInput {
+        ManualEntryInput {
+            task: "  Sprint planning ".into(),
+            project: Some(" ".into()),
+            start_time: now() - Duration::hours(2),
+            end_time: now() - Duration::hours(1),
+            reason: "Meeting room, laptop closed".into(),
+        }
+    }
+
+    // An entry of team member 7, in the same organization as `user`
+    fn entry(status: ManualEntryStatus) -> ManualTimeEntry {
+        let input = input();
+        ManualTimeEntry {
+            id: Uuid::new_v4(),
+            team_member_id: 7,
+            organization_id: 1,
+            task: input.task,
+            project: None,
+            start_time: input.start_time,
+            end_time: input.end_time,
+            reason: input.reason,
+            status,
+            reviewed_by: None,
+            reviewed_at: None,
+            created_at: now(),
+        }
+    }
+
+    #[test]
+    fn validation_trims_the_task_and_drops_a_blank_project() {
+        let valid = input().validated(now()).unwrap();
+        assert_eq!((valid.task.as_str(), valid.project), ("Sprint planning", None));
+    }
+
+    #[test]
+    fn entries_for_time_still_to_come_are_refused() {
+        let future = ManualEntryInput { end_time: now() + Duration::hours(1), ..input() };
+        assert!(future.validated(now()).is_err());
+    }
+
+    #[test]
+    fn entries_need_a_reason() {
+        let blank = ManualEntryInput { reason: " ".into(), ..input() };
+        assert!(blank.validated(now()).is_err());
+    }
+
+    #[test]
+    fn managers_review_their_team_members_entries() {
+        assert_eq!(check_can_review(&user(8, TeamMemberRole::Manager), &entry(ManualEntryStatus::Pending)), Ok(()));
+    }
+
+    #[test]
+    fn employees_cannot_review_entries() {
+        let result = check_can_review(&user(8, TeamMemberRole::Employee), &entry(ManualEntryStatus::Pending));
+        assert_eq!(result, Err(AppError::Unauthorized(Denial::ManagersOnly)));
+    }
+
+    #[test]
+    fn managers_cannot_review_their_own_entries() {
+        let result = check_can_review(&user(7, TeamMemberRole::Manager), &entry(ManualEntryStatus::Pending));
+        assert!(matches!(result, Err(AppError::Invalid(_))));
+    }
+
+    #[test]
+    fn owners_change_entries_until_they_are_approved() {
+        let owner = user(7, TeamMemberRole::Employee);
+        assert_eq!(check_can_edit(&owner, &entry(ManualEntryStatus::Pending)), Ok(()));
+        assert!(matches!(check_can_edit(&owner, &entry(ManualEntryStatus::Approved)), Err(AppError::Invalid(_))));
+    }
+}
@@ -0,0 +1,197 @@
+use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+use aws_sdk_s3::error::DisplayErrorContext;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client;
+use sqlx::{Pool, Postgres, Row};
+use uuid::Uuid;
+
+use crate::screenshot_store::LocalScreenshotStore;
+
+/// Connection settings for an S3-compatible bucket (AWS S3, MinIO, ...).
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    pub bucket: String,
+    pub region: String,
+    pub endpoint: Option<String>, // e.g. http://localhost:9000 for a local MinIO
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub prefix: String,
+    pub force_path_style: bool,
+}
+
+impl S3Config {
+    /// Reads the S3 settings from the environment. Returns None when `S3_BUCKET` is not set,
+    /// in which case screenshots are only kept in the local store.
+    pub fn from_env() -> Option<Self> {
+        let bucket = std::env::var("S3_BUCKET").ok().filter(|b| !b.is_empty())?;
+        let endpoint = std::env::var("S3_ENDPOINT").ok().filter(|e| !e.is_empty());
+        Some(S3Config {
+            bucket,
+            region: std::env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+            // MinIO and most self-hosted endpoints only support path-style addressing
+            force_path_style: std::env::var("S3_FORCE_PATH_STYLE")
+                .map(|v| v == "true" || v == "1")
+                .unwrap_or(endpoint.is_some()),
+            endpoint,
+            access_key_id: std::env::var("S3_ACCESS_KEY_ID").unwrap_or_default(),
+            secret_access_key: std::env::var("S3_SECRET_ACCESS_KEY").unwrap_or_default(),
+            prefix: std::env::var("S3_PREFIX").unwrap_or_else(|_| "screenshots".to_string()),
+        })
+    }
+}
+
+/// Screenshot blobs in an S3-compatible bucket, keyed by content hash.
+pub struct S3ScreenshotStore {
+    client: Client,
+    bucket: String,
+    prefix: String,
+}
+
+impl S3ScreenshotStore {
+    pub fn new(config: S3Config) -> Self {
+        let credentials = Credentials::new(
+            config.access_key_id,
+            config.secret_access_key,
+            None,
+            None,
+            "avoda-env",
+        );
+        let mut builder = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new(config.region))
+            .credentials_provider(credentials)
+            .force_path_style(config.force_path_style);
+        if let Some(endpoint) = config.endpoint {
+            builder = builder.endpoint_url(endpoint);
+        }
+        S3ScreenshotStore {
+            client: Client::from_conf(builder.build()),
+            bucket: config.bucket,
+            prefix: config.prefix,
+        }
+    }
+
+    /// Object key for a screenshot hash, sharded like the local store.
+    pub fn key_for(&self, hash: &str) -> String {
+        format!("{}/{}/{}.png", self.prefix.trim_end_matches('/'), &hash[..2], hash)
+    }
+
+    /// Uploads the bytes under their content-addressed key and returns the key.
+    /// Objects that already exist are not uploaded again.
+    pub async fn put(&self, hash: &str, bytes: &[u8]) -> Result<String, String> {
+        let key = self.key_for(hash);
+        let exists = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+            .is_ok();
+        if exists {
+            return Ok(key);
+        }
+
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .content_type("image/png")
+            .body(ByteStream::from(bytes.to_vec()))
+            .send()
+            .await
+            .map_err(|e| format!("Failed to upload screenshot to object storage: {}", DisplayErrorContext(&e)))?;
+        Ok(key)
+    }
+
+    /// Downloads an object by key.
+    pub async fn get(&self, key: &str) -> Result<Vec<u8>, String> {
+        let output = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to fetch screenshot from object storage: {}", DisplayErrorContext(&e)))?;
+        let data = output
+            .body
+            .collect()
+            .await
+            .map_err(|e| format!("Failed to read screenshot from object storage: {}", e))?;
+        Ok(data.into_bytes().to_vec())
+    }
+}
+
+/// Moves screenshots still stored inline as BYTEA into the bucket, replacing `image_data`
+/// with the object key, size and hash. Runs in batches and returns the number of rows moved.
+pub async fn migrate_inline_screenshots(
+    pool: &Pool<Postgres>,
+    store: &S3ScreenshotStore,
+) -> Result<u64, String> {
+    const BATCH_SIZE: i64 = 50;
+    let mut migrated: u64 = 0;
+
+    loop {
+        let rows = sqlx::query(
+            "SELECT id, image_data FROM screenshots WHERE image_data IS NOT NULL ORDER BY capture_time LIMIT $1",
+        )
+        .bind(BATCH_SIZE)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("Failed to load inline screenshots: {}", e))?;
+
+        if rows.is_empty() {
+            break;
+        }
+
+        for row in rows {
+            let id: Uuid = row.try_get("id").map_err(|e| format!("Failed to get id column: {}", e))?;
+            let data: Vec<u8> = row
+                .try_get("image_data")
+                .map_err(|e| format!("Failed to get image_data column: {}", e))?;
+            let hash = LocalScreenshotStore::hash(&data);
+            let key = store.put(&hash, &data).await?;
+
+            sqlx::query(
+                r#"
+                UPDATE screenshots
+                SET image_key = $1, image_hash = $2, image_size = $3, image_data = NULL
+                WHERE id = $4
+                "#,
+            )
+            .bind(&key)
+            .bind(&hash)
+            .bind(data.len() as i64)
+            .bind(id)
+            .execute(pool)
+            .await
+            .map_err(|e| format!("Failed to update migrated screenshot {}: {}", id, e))?;
+
+            migrated += 1;
+        }
+        println!("Migrated {} screenshots to object storage so far...", migrated);
+    }
+
+    Ok(migrated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Round-trips an object through a real bucket. Run against a local MinIO with
+    // S3_BUCKET/S3_ENDPOINT/S3_ACCESS_KEY_ID/S3_SECRET_ACCESS_KEY set and `--ignored`.
+    #[tokio::test]
+    #[ignore]
+    async fn round_trips_through_bucket() {
+        let config = S3Config::from_env().expect("S3_BUCKET must be set for this test");
+        let store = S3ScreenshotStore::new(config);
+        let bytes = Uuid::new_v4().as_bytes().to_vec();
+        let hash = LocalScreenshotStore::hash(&bytes);
+
+        let key = store.put(&hash, &bytes).await.unwrap();
+        assert_eq!(key, store.key_for(&hash));
+        assert_eq!(store.get(&key).await.unwrap(), bytes);
+    }
+}
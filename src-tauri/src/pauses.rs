@@ -0,0 +1,267 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::{PgConnection, Row};
+use uuid::Uuid;
+
+use crate::errors::AppError;
+
+/// Why a session wasn't being worked on for a while.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PauseReason {
+    /// Paused with the pause button
+    User,
+    /// The system was asleep
+    Suspend,
+    /// The system clock was set forwards
+    ClockChange,
+    /// A Pomodoro break
+    Break,
+    /// The work schedule's window ended
+    Schedule,
+}
+
+impl PauseReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PauseReason::User => "user",
+            PauseReason::Suspend => "suspend",
+            PauseReason::ClockChange => "clock_change",
+            PauseReason::Break => "break",
+            PauseReason::Schedule => "schedule",
+        }
+    }
+}
+
+/// A pause that hasn't ended yet.
+#[derive(Clone, Debug)]
+pub struct OpenPause {
+    pub id: Uuid,
+    pub session_id: Uuid,
+    pub reason: PauseReason,
+    pub start_time: DateTime<Utc>,
+}
+
+/// Inserts a pause starting at `at` that stays open until `close_pause`.
+pub async fn open_pause(
+    conn: &mut PgConnection,
+    session_id: Uuid,
+    reason: PauseReason,
+    at: DateTime<Utc>,
+) -> Result<OpenPause, AppError> {
+    let pause = OpenPause { id: Uuid::new_v4(), session_id, reason, start_time: at };
+    sqlx::query("INSERT INTO pause_segments (id, session_id, start_time, reason) VALUES ($1, $2, $3, $4)")
+        .bind(pause.id)
+        .bind(pause.session_id)
+        .bind(pause.start_time)
+        .bind(reason.as_str())
+        .execute(conn)
+        .await
+        .map_err(|e| AppError::Db(format!("Failed to insert pause: {}", e)))?;
+    Ok(pause)
+}
+
+/// Ends the pause at `at`.
+pub async fn close_pause(conn: &mut PgConnection, pause: &OpenPause, at: DateTime<Utc>) -> Result<(), AppError> {
+    sqlx::query("UPDATE pause_segments SET end_time = $1 WHERE id = $2")
+        .bind(at)
+        .bind(pause.id)
+        .execute(conn)
+        .await
+        .map_err(|e| AppError::Db(format!("Failed to close pause: {}", e)))?;
+    Ok(())
+}
+
+/// Records a pause that is already over, such as a suspend noticed after resuming.
+pub async fn record_pause(
+    conn: &mut PgConnection,
+    session_id: Uuid,
+    reason: PauseReason,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<(), AppError> {
+    sqlx::query(
+        "INSERT INTO pause_segments (id, session_id, start_time, end_time, reason) VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(Uuid::new_v4())
+    .bind(session_id)
+    .bind(from)
+    .bind(to)
+    .bind(reason.as_str())
+    .execute(conn)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to record {} pause: {}", reason.as_str(), e)))?;
+    Ok(())
+}
+
+/// Moves the part of `session_id`'s finished pauses after `at` to `new_session_id`, for a
+/// session that ends at `at` and continues in a new one. Open pauses are handled by the caller.
+pub async fn split_pauses(
+    conn: &mut PgConnection,
+    session_id: Uuid,
+    new_session_id: Uuid,
+    at: DateTime<Utc>,
+) -> Result<(), AppError> {
+    // Pauses that start at or after the cut move as a whole
+    sqlx::query("UPDATE pause_segments SET session_id = $3 WHERE session_id = $1 AND start_time >= $2 AND end_time IS NOT NULL")
+        .bind(session_id)
+        .bind(at)
+        .bind(new_session_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| AppError::Db(format!("Failed to move pauses to session {}: {}", new_session_id, e)))?;
+
+    // Pauses spanning the cut end there and continue in the new session
+    let rows = sqlx::query(
+        r#"
+        UPDATE pause_segments p
+        SET end_time = $2
+        FROM (SELECT id, end_time FROM pause_segments WHERE session_id = $1 AND end_time > $2 FOR UPDATE) old
+        WHERE p.id = old.id
+        RETURNING old.end_time, p.reason
+        "#,
+    )
+    .bind(session_id)
+    .bind(at)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to end pauses at {}: {}", at, e)))?;
+
+    for row in rows {
+        let end_time: DateTime<Utc> = row.try_get("end_time").map_err(|e| AppError::Db(format!("Failed to get end_time column: {}", e)))?;
+        let reason: String = row.try_get("reason").map_err(|e| AppError::Db(format!("Failed to get reason column: {}", e)))?;
+        sqlx::query(
+            "INSERT INTO pause_segments (id, session_id, start_time, end_time, reason) VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(Uuid::new_v4())
+        .bind(new_session_id)
+        .bind(at)
+        .bind(end_time)
+        .bind(reason)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| AppError::Db(format!("Failed to move pause to session {}: {}", new_session_id, e)))?;
+    }
+    Ok(())
+}
+
+/// Trims `session_id`'s pauses to `range` after the session was shortened, deleting those
+/// that lie entirely outside it. A pause still open ends with the range.
+pub async fn clip_pauses(
+    conn: &mut PgConnection,
+    session_id: Uuid,
+    range: (DateTime<Utc>, DateTime<Utc>),
+) -> Result<(), AppError> {
+    sqlx::query("DELETE FROM pause_segments WHERE session_id = $1 AND (start_time >= $3 OR end_time <= $2)")
+        .bind(session_id)
+        .bind(range.0)
+        .bind(range.1)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| AppError::Db(format!("Failed to delete pauses outside the session: {}", e)))?;
+    sqlx::query(
+        "UPDATE pause_segments SET start_time = GREATEST(start_time, $2), end_time = LEAST(end_time, $3) WHERE session_id = $1",
+    )
+    .bind(session_id)
+    .bind(range.0)
+    .bind(range.1)
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to clip pauses to the session: {}", e)))?;
+    Ok(())
+}
+
+/// Time left out of the current run's elapsed time: finished pauses plus the open one. Time
+/// the clock was set back by is counted back in, since the wall clock lost it.
+#[derive(Debug, Clone, Default)]
+pub struct PausedTime {
+    finished: Duration,
+    since: Option<DateTime<Utc>>,
+    set_back: Duration,
+}
+
+impl PausedTime {
+    pub fn begin(&mut self, at: DateTime<Utc>) {
+        self.since.get_or_insert(at);
+    }
+
+    pub fn end(&mut self, at: DateTime<Utc>) {
+        if let Some(since) = self.since.take() {
+            self.finished += (at - since).max(Duration::zero());
+        }
+    }
+
+    /// Adds a pause that is already over.
+    pub fn add(&mut self, from: DateTime<Utc>, to: DateTime<Utc>) {
+        self.finished += (to - from).max(Duration::zero());
+    }
+
+    /// The clock was set back from `from` to `to`.
+    pub fn set_back(&mut self, from: DateTime<Utc>, to: DateTime<Utc>) {
+        self.set_back += (from - to).max(Duration::zero());
+    }
+
+    pub fn total(&self, now: DateTime<Utc>) -> Duration {
+        let open = self.since.map_or(Duration::zero(), |since| (now - since).max(Duration::zero()));
+        self.finished + open - self.set_back
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::TeamMemberRole;
+    use crate::test_db;
+    use chrono::TimeZone;
+    use sqlx::{Pool, Postgres};
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 6, 9, 0, 0).unwrap() + Duration::minutes(minutes)
+    }
+
+    async fn pauses_of(pool: &Pool<Postgres>, session_id: Uuid) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+        sqlx::query_as("SELECT start_time, end_time FROM pause_segments WHERE session_id = $1 ORDER BY start_time")
+            .bind(session_id)
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    // Needs Postgres, see `test_db`
+    #[tokio::test]
+    #[ignore]
+    async fn splitting_moves_pauses_after_the_cut_and_divides_the_one_across_it() {
+        let pool = test_db::pool().await;
+        let organization = test_db::organization(&pool).await;
+        let user = test_db::team_member(&pool, organization, TeamMemberRole::Employee).await;
+        let session = test_db::session(&pool, user.team_member_id, at(0), Some(at(180))).await;
+        let continued = test_db::session(&pool, user.team_member_id, at(60), Some(at(180))).await;
+        let mut conn = pool.acquire().await.unwrap();
+        record_pause(&mut conn, session, PauseReason::User, at(10), at(20)).await.unwrap();
+        record_pause(&mut conn, session, PauseReason::Suspend, at(50), at(70)).await.unwrap();
+        record_pause(&mut conn, session, PauseReason::User, at(100), at(110)).await.unwrap();
+
+        split_pauses(&mut conn, session, continued, at(60)).await.unwrap();
+
+        assert_eq!(pauses_of(&pool, session).await, vec![(at(10), at(20)), (at(50), at(60))]);
+        assert_eq!(pauses_of(&pool, continued).await, vec![(at(60), at(70)), (at(100), at(110))]);
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn clipping_drops_pauses_outside_the_range_and_shortens_those_across_its_ends() {
+        let pool = test_db::pool().await;
+        let organization = test_db::organization(&pool).await;
+        let user = test_db::team_member(&pool, organization, TeamMemberRole::Employee).await;
+        let session = test_db::session(&pool, user.team_member_id, at(0), Some(at(180))).await;
+        let mut conn = pool.acquire().await.unwrap();
+        record_pause(&mut conn, session, PauseReason::User, at(0), at(20)).await.unwrap();
+        record_pause(&mut conn, session, PauseReason::User, at(40), at(50)).await.unwrap();
+        record_pause(&mut conn, session, PauseReason::User, at(110), at(130)).await.unwrap();
+        record_pause(&mut conn, session, PauseReason::User, at(150), at(160)).await.unwrap();
+
+        clip_pauses(&mut conn, session, (at(10), at(120))).await.unwrap();
+
+        assert_eq!(pauses_of(&pool, session).await, vec![(at(10), at(20)), (at(40), at(50)), (at(110), at(120))]);
+    }
+}
@@ -0,0 +1,50 @@
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+
+/// Number of bits in a perceptual hash.
+pub const HASH_BITS: u32 = 64;
+
+/// Computes a 64-bit difference hash (dHash) of a capture: the image is shrunk to 9x8
+/// grayscale and each bit records whether a pixel is brighter than its right neighbour.
+/// Visually similar frames produce hashes that differ in only a few bits.
+pub fn dhash(image: &RgbaImage) -> u64 {
+    let gray = imageops::grayscale(image);
+    let small = imageops::resize(&gray, 9, 8, FilterType::Triangle);
+
+    let mut hash: u64 = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let left = small.get_pixel(x, y)[0];
+            let right = small.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | u64::from(left > right);
+        }
+    }
+    hash
+}
+
+/// Number of differing bits between two hashes.
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// How much the screen changed between two captures, from 0.0 (identical) to 1.0.
+pub fn change_score(a: u64, b: u64) -> f32 {
+    distance(a, b) as f32 / HASH_BITS as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn similar_frames_hash_close_and_different_frames_far() {
+        let gradient = RgbaImage::from_fn(320, 200, |x, _| Rgba([(x * 255 / 319) as u8, 0, 0, 255]));
+        let mut tweaked = gradient.clone();
+        tweaked.put_pixel(10, 10, Rgba([255, 255, 255, 255]));
+        let flipped = imageops::flip_horizontal(&gradient);
+
+        assert!(distance(dhash(&gradient), dhash(&tweaked)) <= 2);
+        assert!(change_score(dhash(&gradient), dhash(&flipped)) > 0.5);
+    }
+}
@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, Row};
+
+use crate::blur::BlurLevel;
+use crate::categories::{self, CategoryRule};
+use crate::redaction::{default_rules, RedactionRule};
+use crate::timesheets::{Rounding, RoundingMode};
+
+/// What to do with a capture that looks the same as the previous stored one.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateScreenshotPolicy {
+    /// Store the image anyway and mark the row as a duplicate.
+    #[default]
+    Flag,
+    /// Store only an "unchanged since" marker that points at the earlier image.
+    Marker,
+}
+
+/// Screenshot capture settings. Defaults apply unless the organization has a row in
+/// `org_policies`; missing fields in the stored JSON also fall back to the defaults.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct CapturePolicy {
+    pub duplicate_screenshots: DuplicateScreenshotPolicy,
+    /// Maximum number of differing perceptual hash bits (out of 64) for two captures to count as unchanged.
+    pub duplicate_threshold: u32,
+    /// Pixelation applied to every capture before it is encoded.
+    pub blur_level: BlurLevel,
+    /// Base64 X25519 public key of the organization's admins. When set and captures are
+    /// blurred, an unblurred copy is kept, encrypted to this key.
+    pub unblurred_copy_public_key: Option<String>,
+    /// Windows whose on-screen area is blacked out or blurred in every capture.
+    pub redaction_rules: Vec<RedactionRule>,
+    /// Length of the wall-clock aligned slots screenshots are scheduled in.
+    pub screenshot_slot_minutes: u32,
+    /// Number of screenshots per slot is drawn between these bounds.
+    pub min_screenshots_per_slot: u32,
+    pub max_screenshots_per_slot: u32,
+    /// A tracking run is stopped automatically after this long; 0 disables the limit.
+    pub max_session_minutes: u32,
+    /// How long before the automatic stop the user is warned.
+    pub session_warning_minutes: u32,
+    /// The user is reminded to start the timer after being active this long without it; 0 disables the reminder.
+    pub start_reminder_minutes: u32,
+    /// The user is nudged to take a break after this much active time without one; 0 disables the nudges.
+    pub break_reminder_minutes: u32,
+    /// Map focused windows to categories for the productivity score; the first matching rule wins.
+    pub category_rules: Vec<CategoryRule>,
+    /// Timesheet totals are rounded to this many minutes; 0 keeps whole minutes.
+    pub timesheet_rounding_minutes: u32,
+    pub timesheet_rounding: RoundingMode,
+}
+
+impl CapturePolicy {
+    pub fn timesheet_rounding(&self) -> Rounding {
+        Rounding { minutes: self.timesheet_rounding_minutes, mode: self.timesheet_rounding }
+    }
+}
+
+impl Default for CapturePolicy {
+    fn default() -> Self {
+        CapturePolicy {
+            duplicate_screenshots: DuplicateScreenshotPolicy::default(),
+            duplicate_threshold: 4,
+            blur_level: BlurLevel::default(),
+            unblurred_copy_public_key: None,
+            redaction_rules: default_rules(),
+            screenshot_slot_minutes: 10,
+            min_screenshots_per_slot: 1,
+            max_screenshots_per_slot: 3,
+            max_session_minutes: 12 * 60,
+            session_warning_minutes: 30,
+            start_reminder_minutes: 10,
+            break_reminder_minutes: 90,
+            category_rules: categories::default_rules(),
+            timesheet_rounding_minutes: 0,
+            timesheet_rounding: RoundingMode::default(),
+        }
+    }
+}
+
+/// Loads the capture policy for the organization, falling back to the defaults
+/// when no team member is signed in or their organization has no stored policy.
+pub async fn load_capture_policy(
+    pool: &Pool<Postgres>,
+    organization_id: Option<i32>,
+) -> Result<CapturePolicy, String> {
+    let Some(organization_id) = organization_id else {
+        return Ok(CapturePolicy::default());
+    };
+
+    let row = sqlx::query("SELECT capture_policy::text AS capture_policy FROM org_policies WHERE organization_id = $1")
+        .bind(organization_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("Failed to load capture policy: {}", e))?;
+
+    match row {
+        Some(row) => {
+            let json: String = row
+                .try_get("capture_policy")
+                .map_err(|e| format!("Failed to get capture_policy column: {}", e))?;
+            serde_json::from_str(&json).map_err(|e| format!("Invalid capture policy JSON: {}", e))
+        }
+        None => Ok(CapturePolicy::default()),
+    }
+}
@@ -0,0 +1,476 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{Pool, Postgres, Row};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use uuid::Uuid;
+
+use crate::browser;
+use crate::categories::FocusSample;
+use crate::errors::AppError;
+use crate::identity::CurrentUser;
+use crate::timesheets;
+
+/// How far back assigned time is learned from, and unassigned time is looked for.
+pub const LEARN_DAYS: i64 = 90;
+
+/// Suggestions less certain than this (in percent) aren't offered.
+const MIN_CONFIDENCE: u32 = 30;
+
+/// Common title words that say nothing about a project.
+const STOP_WORDS: &[&str] = &[
+    "the", "and", "for", "with", "from", "new", "tab", "untitled", "home", "inbox", "page", "window", "edit", "view",
+];
+
+/// Directories that appear in most paths, so they don't name a repository.
+const GENERIC_DIRS: &[&str] = &[
+    "~", ".", "..", "home", "users", "root", "src", "lib", "code", "projects", "repos", "git", "dev", "workspace",
+    "workspaces", "documents", "desktop", "downloads", "tmp", "var", "opt", "usr", "mnt", "volumes",
+];
+
+/// Something about a focused window that can point at a project. Features that say more about
+/// the project count more towards a suggestion.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Feature {
+    /// A ticket key like `ABC-123`
+    Ticket(String),
+    /// The project part of a ticket key, `ABC`
+    TicketProject(String),
+    /// A directory in a path shown in the title
+    Repository(String),
+    Domain(String),
+    Word(String),
+    App(String),
+}
+
+impl Feature {
+    fn weight(&self) -> f64 {
+        match self {
+            Feature::Ticket(_) => 4.0,
+            Feature::TicketProject(_) | Feature::Repository(_) => 3.0,
+            Feature::Domain(_) => 1.5,
+            Feature::Word(_) => 1.0,
+            Feature::App(_) => 0.5,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Feature::Ticket(key) => format!("ticket {}", key),
+            Feature::TicketProject(key) => format!("tickets in {}", key),
+            Feature::Repository(name) => format!("repository {}", name),
+            Feature::Domain(domain) => format!("site {}", domain),
+            Feature::Word(word) => format!("title mentions \"{}\"", word),
+            Feature::App(app) => format!("app {}", app),
+        }
+    }
+}
+
+/// Ticket keys in a title: an upper-case project key of 2–10 letters or digits, a dash and a number.
+pub fn ticket_keys(title: &str) -> Vec<String> {
+    title
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+        .flat_map(|token| token.split('-').collect::<Vec<_>>().windows(2).map(|pair| (pair[0], pair[1])).collect::<Vec<_>>())
+        .filter(|(key, number)| {
+            (2..=10).contains(&key.len())
+                && key.starts_with(|c: char| c.is_ascii_uppercase())
+                && key.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+                && !number.is_empty()
+                && number.chars().all(|c| c.is_ascii_digit())
+        })
+        .map(|(key, number)| format!("{}-{}", key, number))
+        .collect()
+}
+
+// Directories of paths in a title, leaving out ones most paths share and the file name
+fn repositories(title: &str) -> Vec<String> {
+    title
+        .split_whitespace()
+        .map(|word| word.trim_matches(|c: char| matches!(c, '(' | ')' | '[' | ']' | '"' | '\'' | ',' | ':')))
+        .filter(|word| (word.contains('/') || word.contains('\\')) && !word.contains("://") && browser::url_domain(word).is_none())
+        .flat_map(|path| {
+            let parts: Vec<&str> = path.split(['/', '\\']).filter(|part| !part.is_empty()).collect();
+            let directories = match parts.last() {
+                Some(last) if last.contains('.') => &parts[..parts.len() - 1],
+                _ => &parts[..],
+            };
+            directories
+                .iter()
+                .map(|part| part.to_lowercase())
+                .filter(|part| !GENERIC_DIRS.contains(&part.as_str()) && !part.ends_with(':'))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+// Everything about a focused window that is used to tell projects apart
+fn features(sample: &FocusSample) -> BTreeSet<Feature> {
+    // A browser's name in the title is the same for every project
+    let title = browser::parse_title(&sample.app_name, &sample.title).map_or(sample.title.clone(), |page| page.page_title);
+    let app = sample.app_name.to_lowercase();
+    let mut features = BTreeSet::from([Feature::App(app.clone())]);
+    let mut ticket_projects = Vec::new();
+    for key in ticket_keys(&title) {
+        if let Some((project, _)) = key.split_once('-') {
+            ticket_projects.push(project.to_lowercase());
+            features.insert(Feature::TicketProject(project.to_string()));
+        }
+        features.insert(Feature::Ticket(key));
+    }
+    features.extend(repositories(&title).into_iter().map(Feature::Repository));
+    features.extend(sample.domain.clone().or_else(|| browser::url_domain(&title)).map(Feature::Domain));
+    features.extend(
+        title
+            .split(|c: char| !c.is_alphanumeric())
+            .map(str::to_lowercase)
+            .filter(|word| {
+                word.chars().count() >= 3
+                    && !word.chars().all(|c| c.is_ascii_digit())
+                    && !STOP_WORDS.contains(&word.as_str())
+                    && !app.contains(word.as_str())
+                    && !ticket_projects.contains(word)
+            })
+            .map(Feature::Word),
+    );
+    features
+}
+
+/// A task segment with the focused windows captured during it.
+#[derive(Clone, Debug)]
+pub struct ActivitySpan {
+    pub segment_id: Uuid,
+    pub session_id: Uuid,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub project: Option<String>,
+    pub samples: Vec<FocusSample>,
+}
+
+/// A project proposed for time that has none.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ProjectSuggestion {
+    pub segment_id: Uuid,
+    pub session_id: Uuid,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub project: String,
+    /// 0–100: how much more of the span's focus time points at the project than at any other
+    pub confidence: u32,
+    /// What the suggestion is based on, most telling first
+    pub evidence: Vec<String>,
+}
+
+/// Focus time per feature and project, learned from time already assigned to projects. Built on
+/// this computer from the user's own sessions; nothing is sent anywhere.
+#[derive(Default)]
+pub struct ProjectModel {
+    seconds: HashMap<Feature, HashMap<String, f64>>,
+}
+
+impl ProjectModel {
+    pub fn train(spans: &[ActivitySpan]) -> Self {
+        let mut model = ProjectModel::default();
+        for span in spans {
+            let Some(project) = &span.project else {
+                continue;
+            };
+            for sample in &span.samples {
+                for feature in features(sample) {
+                    *model.seconds.entry(feature).or_default().entry(project.clone()).or_default() += sample.seconds;
+                }
+            }
+        }
+        model
+    }
+
+    // How much each project is backed by a feature, as shares of the feature's time
+    fn shares(&self, feature: &Feature) -> Option<HashMap<&str, f64>> {
+        let by_project = self.seconds.get(feature)?;
+        let total: f64 = by_project.values().sum();
+        (total > 0.0).then(|| by_project.iter().map(|(project, seconds)| (project.as_str(), seconds / total)).collect())
+    }
+
+    /// The project the span's focus time points at most, leaving out `rejected` ones. Each
+    /// window's time is split between projects by the weighted shares of its known features.
+    /// The confidence is the lead over the next project (rejected ones included), so windows
+    /// every project shares, and time with no known features, count against it.
+    pub fn suggest(&self, span: &ActivitySpan, rejected: &HashSet<String>) -> Option<ProjectSuggestion> {
+        let mut scores: HashMap<&str, f64> = HashMap::new();
+        let mut evidence: HashMap<(&str, Feature), f64> = HashMap::new();
+        let total_seconds: f64 = span.samples.iter().map(|sample| sample.seconds).sum();
+        for sample in &span.samples {
+            let known: Vec<(Feature, HashMap<&str, f64>)> =
+                features(sample).into_iter().filter_map(|feature| self.shares(&feature).map(|shares| (feature, shares))).collect();
+            let weights: f64 = known.iter().map(|(feature, _)| feature.weight()).sum();
+            for (feature, shares) in known {
+                for (project, share) in shares {
+                    let points = sample.seconds * feature.weight() * share / weights;
+                    *scores.entry(project).or_default() += points;
+                    *evidence.entry((project, feature.clone())).or_default() += points;
+                }
+            }
+        }
+        let (project, score) = scores
+            .iter()
+            .filter(|(project, _)| !rejected.contains(**project))
+            .max_by(|a, b| a.1.total_cmp(b.1).then_with(|| b.0.cmp(a.0)))
+            .map(|(project, score)| (*project, *score))?;
+        let runner_up = scores.iter().filter(|(other, _)| **other != project).map(|(_, score)| *score).fold(0.0, f64::max);
+        let confidence = ((score - runner_up).max(0.0) / total_seconds * 100.0).round() as u32;
+        if confidence < MIN_CONFIDENCE {
+            return None;
+        }
+        let mut reasons: Vec<(Feature, f64)> =
+            evidence.into_iter().filter(|((p, _), _)| *p == project).map(|((_, feature), points)| (feature, points)).collect();
+        reasons.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Some(ProjectSuggestion {
+            segment_id: span.segment_id,
+            session_id: span.session_id,
+            start_time: span.start_time,
+            end_time: span.end_time,
+            project: project.to_string(),
+            confidence,
+            evidence: reasons.iter().take(3).map(|(feature, _)| feature.describe()).collect(),
+        })
+    }
+}
+
+/// The user's finished task segments since `from`, with their focus samples. A screenshot's window
+/// counts until the next screenshot or the end of the segment, for at most `max_sample`.
+pub async fn activity_spans(
+    pool: &Pool<Postgres>,
+    team_member_id: i32,
+    from: DateTime<Utc>,
+    max_sample: chrono::Duration,
+) -> Result<Vec<ActivitySpan>, AppError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT ts.id, ts.session_id, ts.start_time, ts.end_time, NULLIF(btrim(ts.project), '') AS project,
+               sc.focused_app, COALESCE(sc.focused_title, '') AS focused_title, sc.focused_domain,
+               EXTRACT(EPOCH FROM LEAST(
+                   COALESCE(sc.next_capture, ts.end_time), ts.end_time, sc.capture_time + make_interval(secs => $3)
+               ) - sc.capture_time)::float8 AS seconds
+        FROM task_segments ts
+        JOIN sessions s ON s.id = ts.session_id
+        LEFT JOIN (
+            SELECT session_id, capture_time, focused_app, focused_title, focused_domain,
+                   LEAD(capture_time) OVER (PARTITION BY session_id ORDER BY capture_time) AS next_capture
+            FROM screenshots
+            WHERE removed_by_edit IS NULL
+              AND session_id IN (SELECT id FROM sessions WHERE team_member_id = $1 AND start_time >= $2)
+        ) sc ON sc.session_id = ts.session_id AND sc.capture_time >= ts.start_time AND sc.capture_time < ts.end_time
+            AND sc.focused_app IS NOT NULL
+        WHERE s.team_member_id = $1 AND s.start_time >= $2 AND ts.end_time IS NOT NULL
+        ORDER BY ts.start_time, sc.capture_time
+        "#,
+    )
+    .bind(team_member_id)
+    .bind(from)
+    .bind(max_sample.num_seconds() as f64)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to load activity for project suggestions: {}", e)))?;
+
+    let column_error = |column: &str, e: sqlx::Error| AppError::Db(format!("Failed to get {} column: {}", column, e));
+    let mut spans: Vec<ActivitySpan> = Vec::new();
+    for row in &rows {
+        let segment_id: Uuid = row.try_get("id").map_err(|e| column_error("id", e))?;
+        if spans.last().is_none_or(|span| span.segment_id != segment_id) {
+            spans.push(ActivitySpan {
+                segment_id,
+                session_id: row.try_get("session_id").map_err(|e| column_error("session_id", e))?,
+                start_time: row.try_get("start_time").map_err(|e| column_error("start_time", e))?,
+                end_time: row.try_get("end_time").map_err(|e| column_error("end_time", e))?,
+                project: row.try_get("project").map_err(|e| column_error("project", e))?,
+                samples: Vec::new(),
+            });
+        }
+        let app_name: Option<String> = row.try_get("focused_app").map_err(|e| column_error("focused_app", e))?;
+        if let (Some(app_name), Some(span)) = (app_name, spans.last_mut()) {
+            span.samples.push(FocusSample {
+                app_name,
+                title: row.try_get("focused_title").map_err(|e| column_error("focused_title", e))?,
+                domain: row.try_get("focused_domain").map_err(|e| column_error("focused_domain", e))?,
+                seconds: row.try_get::<f64, _>("seconds").map_err(|e| column_error("seconds", e))?.max(0.0),
+            });
+        }
+    }
+    Ok(spans)
+}
+
+// Projects the user turned down, per task segment
+async fn rejected_projects(pool: &Pool<Postgres>, team_member_id: i32) -> Result<HashMap<Uuid, HashSet<String>>, AppError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT r.segment_id, r.project
+        FROM project_suggestion_rejections r
+        JOIN task_segments ts ON ts.id = r.segment_id
+        JOIN sessions s ON s.id = ts.session_id
+        WHERE s.team_member_id = $1
+        "#,
+    )
+    .bind(team_member_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to load rejected project suggestions: {}", e)))?;
+    let mut rejected: HashMap<Uuid, HashSet<String>> = HashMap::new();
+    for row in &rows {
+        let segment_id: Uuid = row.try_get("segment_id").map_err(|e| AppError::Db(format!("Failed to get segment_id column: {}", e)))?;
+        let project: String = row.try_get("project").map_err(|e| AppError::Db(format!("Failed to get project column: {}", e)))?;
+        rejected.entry(segment_id).or_default().insert(project);
+    }
+    Ok(rejected)
+}
+
+/// Suggestions for the user's time without a project that started at or after `since`, learned
+/// from their time with one over the last `LEARN_DAYS` days.
+pub async fn suggestions(
+    pool: &Pool<Postgres>,
+    user: &CurrentUser,
+    since: DateTime<Utc>,
+    now: DateTime<Utc>,
+    max_sample: chrono::Duration,
+) -> Result<Vec<ProjectSuggestion>, AppError> {
+    let from = since.min(now - chrono::Duration::days(LEARN_DAYS));
+    let spans = activity_spans(pool, user.team_member_id, from, max_sample).await?;
+    let model = ProjectModel::train(&spans);
+    let rejected = rejected_projects(pool, user.team_member_id).await?;
+    let none = HashSet::new();
+    Ok(spans
+        .iter()
+        .filter(|span| span.project.is_none() && span.start_time >= since)
+        .filter_map(|span| model.suggest(span, rejected.get(&span.segment_id).unwrap_or(&none)))
+        .collect())
+}
+
+/// Assigns `project` to one of the user's finished task segments that has none yet.
+pub async fn accept(pool: &Pool<Postgres>, user: &CurrentUser, segment_id: Uuid, project: &str) -> Result<(), AppError> {
+    let project = project.trim();
+    if project.is_empty() {
+        return Err(AppError::Invalid("A project is required.".to_string()));
+    }
+    let not_found = || AppError::NotFound(format!("Task segment {} is not your finished time without a project.", segment_id));
+    let row = sqlx::query(
+        r#"
+        SELECT ts.start_time, ts.end_time
+        FROM task_segments ts
+        JOIN sessions s ON s.id = ts.session_id
+        WHERE ts.id = $1 AND s.team_member_id = $2 AND ts.end_time IS NOT NULL
+        "#,
+    )
+    .bind(segment_id)
+    .bind(user.team_member_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::Db(format!("Database error fetching task segment: {}", e)))?
+    .ok_or_else(not_found)?;
+    let start_time: DateTime<Utc> = row.try_get("start_time").map_err(|e| AppError::Db(format!("Failed to get start_time column: {}", e)))?;
+    let end_time: DateTime<Utc> = row.try_get("end_time").map_err(|e| AppError::Db(format!("Failed to get end_time column: {}", e)))?;
+    timesheets::ensure_unlocked(pool, user.team_member_id, start_time, end_time).await?;
+
+    let updated = sqlx::query("UPDATE task_segments SET project = $1 WHERE id = $2 AND NULLIF(btrim(project), '') IS NULL")
+        .bind(project)
+        .bind(segment_id)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Db(format!("Failed to assign project: {}", e)))?;
+    if updated.rows_affected() == 0 {
+        return Err(not_found());
+    }
+    Ok(())
+}
+
+/// Records that `project` is wrong for the segment, so it isn't suggested for it again.
+pub async fn reject(pool: &Pool<Postgres>, user: &CurrentUser, segment_id: Uuid, project: &str) -> Result<(), AppError> {
+    let inserted = sqlx::query(
+        r#"
+        INSERT INTO project_suggestion_rejections (segment_id, project, rejected_at)
+        SELECT ts.id, $2, now()
+        FROM task_segments ts
+        JOIN sessions s ON s.id = ts.session_id
+        WHERE ts.id = $1 AND s.team_member_id = $3
+        ON CONFLICT (segment_id, project) DO UPDATE SET rejected_at = now()
+        "#,
+    )
+    .bind(segment_id)
+    .bind(project.trim())
+    .bind(user.team_member_id)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to reject project suggestion: {}", e)))?;
+    if inserted.rows_affected() == 0 {
+        return Err(AppError::NotFound(format!("Task segment {} not found among your sessions.", segment_id)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn sample(app: &str, title: &str, minutes: f64) -> FocusSample {
+        FocusSample { app_name: app.to_string(), title: title.to_string(), domain: None, seconds: minutes * 60.0 }
+    }
+
+    fn span(project: Option<&str>, samples: Vec<FocusSample>) -> ActivitySpan {
+        let at = Utc.with_ymd_and_hms(2024, 5, 6, 9, 0, 0).unwrap();
+        ActivitySpan {
+            segment_id: Uuid::new_v4(),
+            session_id: Uuid::new_v4(),
+            start_time: at,
+            end_time: at,
+            project: project.map(str::to_string),
+            samples,
+        }
+    }
+
+    // Learned from an hour each on Billing and Website; time without a project teaches nothing
+    fn model() -> ProjectModel {
+        ProjectModel::train(&[
+            span(Some("Billing"), vec![sample("Code", "invoice.rs - billing - Visual Studio Code", 50.0), sample("Firefox", "BIL-12 Invoices - Mozilla Firefox", 10.0)]),
+            span(Some("Website"), vec![sample("Code", "index.html - website - Visual Studio Code", 40.0), sample("Firefox", "WEB-3 Landing page - Mozilla Firefox", 20.0)]),
+            span(None, vec![sample("Code", "ignored.rs - billing", 60.0)]),
+        ])
+    }
+
+    fn refund_work() -> ActivitySpan {
+        span(None, vec![sample("Firefox", "BIL-40 Refunds - Mozilla Firefox", 20.0), sample("Code", "refund.rs - billing - Visual Studio Code", 20.0)])
+    }
+
+    #[test]
+    fn finds_ticket_keys_in_titles() {
+        assert_eq!(ticket_keys("ABC-123: Fix login (see WEB-7, not-a-key, X-1)"), vec!["ABC-123", "WEB-7"]);
+    }
+
+    #[test]
+    fn finds_repositories_in_paths() {
+        assert_eq!(repositories("~/code/billing/src/main.rs - Vim"), vec!["billing"]);
+    }
+
+    #[test]
+    fn suggests_the_project_of_the_same_tickets_and_words() {
+        let suggestion = model().suggest(&refund_work(), &HashSet::new()).unwrap();
+        assert_eq!(suggestion.project, "Billing");
+        assert!(suggestion.confidence >= 50, "{:?}", suggestion);
+        assert_eq!(suggestion.evidence[..2], ["tickets in BIL", "title mentions \"billing\""]);
+    }
+
+    #[test]
+    fn an_editor_used_for_every_project_is_not_enough() {
+        let notes = span(None, vec![sample("Code", "notes.txt - Visual Studio Code", 10.0)]);
+        assert!(model().suggest(&notes, &HashSet::new()).is_none());
+    }
+
+    #[test]
+    fn unknown_activity_suggests_nothing() {
+        let chat = span(None, vec![sample("Slack", "general", 10.0)]);
+        assert!(model().suggest(&chat, &HashSet::new()).is_none());
+    }
+
+    #[test]
+    fn a_rejected_project_is_not_suggested_again() {
+        // The rest of the evidence is too weak for another project
+        let rejected = HashSet::from(["Billing".to_string()]);
+        assert!(model().suggest(&refund_work(), &rejected).is_none());
+    }
+}
@@ -0,0 +1,163 @@
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::blur::{pixelate_region, BlurLevel};
+
+/// How a matching window is hidden in the screenshot.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RedactionMode {
+    #[default]
+    Blackout,
+    Blur,
+}
+
+/// A window matches a rule when every condition that is set matches (case-insensitive).
+/// A rule with no conditions matches nothing.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(default)]
+pub struct RedactionRule {
+    pub app_contains: Option<String>,
+    pub title_contains: Option<String>,
+    pub mode: RedactionMode,
+}
+
+impl RedactionRule {
+    fn app(app: &str) -> Self {
+        RedactionRule {
+            app_contains: Some(app.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn matches(&self, app_name: &str, title: &str) -> bool {
+        if self.app_contains.is_none() && self.title_contains.is_none() {
+            return false;
+        }
+        let contains = |haystack: &str, needle: &Option<String>| {
+            needle
+                .as_ref()
+                .is_none_or(|n| haystack.to_lowercase().contains(&n.to_lowercase()))
+        };
+        contains(app_name, &self.app_contains) && contains(title, &self.title_contains)
+    }
+}
+
+/// Rules applied when the organization does not configure its own: common password managers.
+pub fn default_rules() -> Vec<RedactionRule> {
+    ["1Password", "Bitwarden", "KeePass", "LastPass", "Dashlane", "Keychain Access"]
+        .into_iter()
+        .map(RedactionRule::app)
+        .collect()
+}
+
+/// Position of a visible window in desktop coordinates, as reported by xcap.
+#[derive(Debug, Clone)]
+pub struct WindowRect {
+    pub app_name: String,
+    pub title: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A rectangle that was hidden, in pixels of the stored screenshot. Titles are
+/// deliberately not recorded since they are what the rule is protecting.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RedactedRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub app_name: String,
+    pub mode: RedactionMode,
+}
+
+/// The captured monitor's position and size in desktop coordinates.
+#[derive(Debug, Clone, Copy)]
+pub struct MonitorRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+}
+
+/// Hides every window that matches a rule and returns the regions that were changed.
+/// Window geometry is translated to the monitor and scaled to the image, so HiDPI
+/// captures (where the image is larger than the logical monitor size) line up.
+pub fn redact_windows(
+    image: &mut RgbaImage,
+    monitor: MonitorRect,
+    windows: &[WindowRect],
+    rules: &[RedactionRule],
+) -> Vec<RedactedRegion> {
+    let scale = if monitor.width > 0 {
+        image.width() as f64 / monitor.width as f64
+    } else {
+        1.0
+    };
+    let (image_width, image_height) = (image.width() as i64, image.height() as i64);
+    let mut regions = Vec::new();
+
+    for window in windows {
+        let Some(rule) = rules.iter().find(|r| r.matches(&window.app_name, &window.title)) else {
+            continue;
+        };
+
+        let to_px = |v: f64| (v * scale).round() as i64;
+        let left = to_px((window.x - monitor.x) as f64).clamp(0, image_width);
+        let top = to_px((window.y - monitor.y) as f64).clamp(0, image_height);
+        let right = to_px((window.x - monitor.x) as f64 + window.width as f64).clamp(0, image_width);
+        let bottom = to_px((window.y - monitor.y) as f64 + window.height as f64).clamp(0, image_height);
+        if right <= left || bottom <= top {
+            continue; // Window is on another monitor
+        }
+
+        let region = RedactedRegion {
+            x: left as u32,
+            y: top as u32,
+            width: (right - left) as u32,
+            height: (bottom - top) as u32,
+            app_name: window.app_name.clone(),
+            mode: rule.mode,
+        };
+        match rule.mode {
+            RedactionMode::Blackout => {
+                for py in region.y..region.y + region.height {
+                    for px in region.x..region.x + region.width {
+                        image.put_pixel(px, py, Rgba([0, 0, 0, 255]));
+                    }
+                }
+            }
+            RedactionMode::Blur => {
+                let block = BlurLevel::Heavy.block_size(image.width()).unwrap_or(12);
+                pixelate_region(image, region.x, region.y, region.width, region.height, block);
+            }
+        }
+        regions.push(region);
+    }
+
+    regions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blacks_out_matching_window_clipped_to_monitor() {
+        let mut image = RgbaImage::from_pixel(100, 50, Rgba([255, 255, 255, 255]));
+        let monitor = MonitorRect { x: 1000, y: 0, width: 100 };
+        let windows = vec![
+            WindowRect { app_name: "Bitwarden".into(), title: "Vault".into(), x: 1080, y: 10, width: 40, height: 10 },
+            WindowRect { app_name: "Code".into(), title: "main.rs".into(), x: 1000, y: 0, width: 50, height: 50 },
+        ];
+
+        let regions = redact_windows(&mut image, monitor, &windows, &default_rules());
+
+        assert_eq!(regions.len(), 1);
+        assert_eq!((regions[0].x, regions[0].y, regions[0].width, regions[0].height), (80, 10, 20, 10));
+        assert_eq!(*image.get_pixel(90, 15), Rgba([0, 0, 0, 255]));
+        assert_eq!(*image.get_pixel(10, 15), Rgba([255, 255, 255, 255]));
+    }
+}
@@ -0,0 +1,23 @@
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use std::sync::{Arc, Mutex};
+
+/// Randomness used for screenshot scheduling. Any `rand` generator qualifies,
+/// and `dyn Rng` can be passed wherever a `rand::Rng` is expected.
+pub trait Rng: RngCore + Send {}
+
+impl<T: RngCore + Send> Rng for T {}
+
+/// Generator shared between `AppState` and the timer task.
+pub type SharedRng = Arc<Mutex<dyn Rng>>;
+
+/// Generator seeded from the OS, used by the app.
+pub fn system_rng() -> SharedRng {
+    Arc::new(Mutex::new(StdRng::from_entropy()))
+}
+
+/// Deterministic generator for tests: the same seed always yields the same schedule.
+#[cfg(test)]
+pub fn seeded_rng(seed: u64) -> SharedRng {
+    Arc::new(Mutex::new(StdRng::seed_from_u64(seed)))
+}
@@ -0,0 +1,152 @@
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use rand::Rng;
+
+/// Plans screenshot times: each wall-clock aligned slot (e.g. 10:00–10:10) gets between
+/// `min_per_slot` and `max_per_slot` captures at random offsets inside it.
+#[derive(Debug, Clone)]
+pub struct SlotScheduler {
+    slot_length: Duration,
+    min_per_slot: u32,
+    max_per_slot: u32,
+    current: Option<SlotPlan>,
+}
+
+#[derive(Debug, Clone)]
+struct SlotPlan {
+    slot_start: DateTime<Utc>,
+    pending: Vec<DateTime<Utc>>, // Sorted capture times not taken yet
+}
+
+impl SlotScheduler {
+    pub fn new(slot_minutes: u32, min_per_slot: u32, max_per_slot: u32) -> Self {
+        let min_per_slot = min_per_slot.max(1);
+        SlotScheduler {
+            slot_length: Duration::minutes(i64::from(slot_minutes.max(1))),
+            min_per_slot,
+            max_per_slot: max_per_slot.max(min_per_slot),
+            current: None,
+        }
+    }
+
+    /// Start of the slot containing `now`, aligned to the wall clock.
+    pub fn slot_start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        now.duration_trunc(self.slot_length).unwrap_or(now)
+    }
+
+    /// The next planned capture time. Captures whose time has already passed are skipped,
+    /// and the following slot is planned once the current one is used up. When the timer
+    /// starts mid-slot, only the planned offsets still ahead are kept.
+    pub fn next_capture<R: Rng + ?Sized>(&mut self, now: DateTime<Utc>, rng: &mut R) -> DateTime<Utc> {
+        let mut slot_start = self.slot_start(now);
+        // Once a slot is used up the next one is already planned; don't plan the finished one again
+        if let Some(plan) = self.current.as_ref().filter(|plan| plan.slot_start > slot_start) {
+            slot_start = plan.slot_start;
+        }
+        loop {
+            if self.current.as_ref().is_none_or(|plan| plan.slot_start != slot_start) {
+                self.current = Some(self.plan_slot(slot_start, rng));
+            }
+            let plan = self.current.as_mut().expect("slot was just planned");
+            plan.pending.retain(|t| *t >= now);
+            if let Some(next) = plan.pending.first() {
+                return *next;
+            }
+            slot_start += self.slot_length;
+        }
+    }
+
+    /// Marks the capture returned by `next_capture` as taken.
+    pub fn mark_taken(&mut self) {
+        if let Some(plan) = self.current.as_mut() {
+            if !plan.pending.is_empty() {
+                plan.pending.remove(0);
+            }
+        }
+    }
+
+    /// Forgets the current plan, for when the clock was set back and the planned slot is
+    /// now in the future.
+    pub fn reset(&mut self) {
+        self.current = None;
+    }
+
+    /// Called when the timer resumes. If still inside the slot that was running when it was
+    /// paused, captures that fell into the pause are re-drawn over the rest of the slot,
+    /// so the slot keeps its planned count instead of starting over.
+    pub fn resume<R: Rng + ?Sized>(&mut self, now: DateTime<Utc>, rng: &mut R) {
+        let slot_start = self.slot_start(now);
+        let slot_end = slot_start + self.slot_length;
+        let Some(plan) = self.current.as_mut().filter(|plan| plan.slot_start == slot_start) else {
+            return;
+        };
+
+        let missed = plan.pending.iter().filter(|t| **t < now).count();
+        plan.pending.retain(|t| *t >= now);
+        let remaining_ms = (slot_end - now).num_milliseconds().max(1);
+        for _ in 0..missed {
+            plan.pending.push(now + Duration::milliseconds(rng.gen_range(0..remaining_ms)));
+        }
+        plan.pending.sort();
+        if missed > 0 {
+            println!("Re-planned {} screenshot(s) missed while paused in the current slot.", missed);
+        }
+    }
+
+    fn plan_slot<R: Rng + ?Sized>(&self, slot_start: DateTime<Utc>, rng: &mut R) -> SlotPlan {
+        let count = rng.gen_range(self.min_per_slot..=self.max_per_slot);
+        let slot_ms = self.slot_length.num_milliseconds();
+        let mut pending: Vec<DateTime<Utc>> = (0..count)
+            .map(|_| slot_start + Duration::milliseconds(rng.gen_range(0..slot_ms)))
+            .collect();
+        pending.sort();
+        println!("Planned {} screenshot(s) for the slot starting {}.", count, slot_start);
+        SlotPlan { slot_start, pending }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn plans_within_aligned_slots_and_keeps_count_across_pause() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut scheduler = SlotScheduler::new(10, 3, 3);
+        let start = Utc.with_ymd_and_hms(2024, 5, 6, 9, 0, 0).unwrap();
+
+        let first = scheduler.next_capture(start, &mut rng);
+        assert_eq!(scheduler.slot_start(first), start);
+
+        // Pause right away and resume halfway through the slot: all 3 captures still happen in it
+        let resumed_at = start + Duration::minutes(5);
+        scheduler.resume(resumed_at, &mut rng);
+        let mut taken = Vec::new();
+        loop {
+            let next = scheduler.next_capture(resumed_at, &mut rng);
+            if scheduler.slot_start(next) != start {
+                break;
+            }
+            scheduler.mark_taken();
+            taken.push(next);
+        }
+        assert_eq!(taken.len(), 3);
+        assert!(taken.iter().all(|t| *t >= resumed_at));
+    }
+
+    #[test]
+    fn used_up_slot_is_not_planned_again() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut scheduler = SlotScheduler::new(10, 1, 1);
+        let start = Utc.with_ymd_and_hms(2024, 5, 6, 9, 0, 0).unwrap();
+
+        let first = scheduler.next_capture(start, &mut rng);
+        scheduler.mark_taken();
+
+        // Asking again later in the same slot moves on to the next slot
+        let next = scheduler.next_capture(first, &mut rng);
+        assert_eq!(scheduler.slot_start(next), start + Duration::minutes(10));
+        assert_eq!(scheduler.next_capture(first + Duration::seconds(1), &mut rng), next);
+    }
+}
@@ -0,0 +1,377 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::object_store::S3ScreenshotStore;
+
+/// Default cap on the total size of the local store (2 GiB).
+const DEFAULT_MAX_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+/// Default number of days a screenshot file is kept on disk.
+const DEFAULT_RETENTION_DAYS: u64 = 30;
+
+/// Content-addressable store for encoded screenshots under the app data dir.
+/// Files are named after the SHA-256 of their bytes, so identical frames are stored once.
+#[derive(Debug, Clone)]
+pub struct LocalScreenshotStore {
+    root: PathBuf,
+    max_bytes: u64,
+    max_age: Duration,
+    last_written: Arc<Mutex<Option<String>>>, // Hash of the latest put, possibly not in the DB yet
+}
+
+/// Result of writing a screenshot into the store.
+#[derive(Debug, Clone)]
+pub struct StoredScreenshot {
+    pub hash: String,
+    pub size: u64,
+    pub deduplicated: bool, // True if an identical file was already present
+}
+
+/// Summary of files removed by retention or an explicit purge.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct PurgeReport {
+    pub files_removed: u64,
+    pub bytes_removed: u64,
+    /// Hashes of the removed files, so their rows can be marked as expired
+    #[serde(skip)]
+    pub hashes: Vec<String>,
+}
+
+/// Where encoded screenshots are kept: always the local store, plus an
+/// S3-compatible bucket when one is configured. The local copy then acts as a cache.
+pub struct ScreenshotStorage {
+    pub local: LocalScreenshotStore,
+    pub remote: Option<S3ScreenshotStore>,
+}
+
+/// Reference to a saved screenshot, as recorded on its `screenshots` row.
+#[derive(Debug, Clone)]
+pub struct SavedScreenshot {
+    pub hash: String,
+    pub size: u64,
+    pub key: Option<String>, // Object key, set when the image was uploaded to the bucket
+    pub deduplicated: bool,
+}
+
+impl ScreenshotStorage {
+    /// Saves the bytes locally and, if a bucket is configured, uploads them.
+    pub async fn save(&self, bytes: &[u8]) -> Result<SavedScreenshot, String> {
+        let stored = self.local.put(bytes)?;
+        let key = match &self.remote {
+            Some(remote) => Some(remote.put(&stored.hash, bytes).await?),
+            None => None,
+        };
+        Ok(SavedScreenshot {
+            hash: stored.hash,
+            size: stored.size,
+            key,
+            deduplicated: stored.deduplicated,
+        })
+    }
+
+    /// Loads a screenshot from the local store, falling back to the bucket.
+    pub async fn load(&self, hash: Option<&str>, key: Option<&str>) -> Result<Vec<u8>, String> {
+        let local_result = match hash {
+            Some(hash) => self.local.get(hash),
+            None => Err("Screenshot has no content hash".to_string()),
+        };
+        match (local_result, key, &self.remote) {
+            (Ok(bytes), _, _) => Ok(bytes),
+            (Err(_), Some(key), Some(remote)) => {
+                let bytes = remote.get(key).await?;
+                // Keep a local copy so the next read does not hit the bucket again
+                if let Err(e) = self.local.put(&bytes) {
+                    eprintln!("Warning: failed to cache downloaded screenshot: {}", e);
+                }
+                Ok(bytes)
+            }
+            (Err(e), Some(_), None) => Err(format!(
+                "{} and object storage is not configured",
+                e
+            )),
+            (Err(e), None, _) => Err(e),
+        }
+    }
+}
+
+struct StoredFile {
+    hash: String,
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+}
+
+impl LocalScreenshotStore {
+    pub fn new(root: PathBuf, max_bytes: u64, max_age: Duration) -> Self {
+        LocalScreenshotStore {
+            root,
+            max_bytes,
+            max_age,
+            last_written: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Creates a store in `<app_data_dir>/screenshots`, reading limits from
+    /// `SCREENSHOT_STORE_MAX_MB` and `SCREENSHOT_RETENTION_DAYS` if set.
+    pub fn from_env(app_data_dir: &Path) -> Self {
+        let max_bytes = std::env::var("SCREENSHOT_STORE_MAX_MB")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .map(|mb| mb.saturating_mul(1024 * 1024))
+            .unwrap_or(DEFAULT_MAX_BYTES);
+        let retention_days = std::env::var("SCREENSHOT_RETENTION_DAYS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_RETENTION_DAYS);
+        Self::new(
+            app_data_dir.join("screenshots"),
+            max_bytes,
+            Duration::from_secs(retention_days.saturating_mul(24 * 60 * 60)),
+        )
+    }
+
+    /// Hex-encoded SHA-256 of the given bytes, used as the file name and DB reference.
+    pub fn hash(bytes: &[u8]) -> String {
+        format!("{:x}", Sha256::digest(bytes))
+    }
+
+    /// Path of a stored file. Files are sharded by the first two hex chars of the hash.
+    pub fn path_for(&self, hash: &str) -> Result<PathBuf, String> {
+        if hash.len() < 3 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("Invalid screenshot hash: {}", hash));
+        }
+        Ok(self.root.join(&hash[..2]).join(format!("{}.png", hash)))
+    }
+
+    /// Writes the bytes if no identical file exists yet. Retention runs separately,
+    /// see `enforce_retention`.
+    pub fn put(&self, bytes: &[u8]) -> Result<StoredScreenshot, String> {
+        let hash = Self::hash(bytes);
+        let path = self.path_for(&hash)?;
+        let deduplicated = path.exists();
+
+        if deduplicated {
+            // Refresh the modification time so retention treats the frame as recent
+            fs::File::options()
+                .append(true)
+                .open(&path)
+                .and_then(|f| f.set_modified(SystemTime::now()))
+                .map_err(|e| format!("Failed to touch stored screenshot: {}", e))?;
+        } else {
+            let dir = path.parent().expect("store path always has a shard directory");
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create screenshots directory: {}", e))?;
+            // Write to a temp file first so a crash never leaves a truncated file under the final name
+            let tmp_path = path.with_extension("png.tmp");
+            fs::write(&tmp_path, bytes)
+                .map_err(|e| format!("Failed to save screenshot file locally: {}", e))?;
+            fs::rename(&tmp_path, &path)
+                .map_err(|e| format!("Failed to move screenshot file into place: {}", e))?;
+        }
+
+        *self.last_written.lock().unwrap() = Some(hash.clone());
+
+        Ok(StoredScreenshot {
+            hash,
+            size: bytes.len() as u64,
+            deduplicated,
+        })
+    }
+
+    /// Reads a stored screenshot by hash.
+    pub fn get(&self, hash: &str) -> Result<Vec<u8>, String> {
+        let path = self.path_for(hash)?;
+        fs::read(&path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                format!("Screenshot file {} is no longer available locally", hash)
+            }
+            _ => format!("Failed to read screenshot file: {}", e),
+        })
+    }
+
+    /// Deletes files older than the retention period, then the oldest files until the store is
+    /// back under its size cap. The latest written file is never deleted, since its row may not
+    /// be saved yet. Files without a copy in the bucket are deleted too; the caller marks their
+    /// rows as expired.
+    pub fn enforce_retention(&self, now: SystemTime) -> Result<PurgeReport, String> {
+        let all_files = self.list_files()?;
+        let mut total: u64 = all_files.iter().map(|f| f.size).sum();
+        let mut files = self.removable(all_files);
+        let mut report = PurgeReport::default();
+
+        let cutoff = now.checked_sub(self.max_age).unwrap_or(SystemTime::UNIX_EPOCH);
+        files.retain(|file| {
+            if file.modified < cutoff {
+                if remove_file(file, &mut report) {
+                    total -= file.size;
+                }
+                false
+            } else {
+                true
+            }
+        });
+
+        if total > self.max_bytes {
+            files.sort_by_key(|f| f.modified);
+            for file in &files {
+                if total <= self.max_bytes {
+                    break;
+                }
+                if remove_file(file, &mut report) {
+                    total -= file.size;
+                }
+            }
+        }
+
+        if report.files_removed > 0 {
+            println!(
+                "Screenshot retention removed {} files ({} bytes)",
+                report.files_removed, report.bytes_removed
+            );
+        }
+        Ok(report)
+    }
+
+    /// Deletes every stored file, or only those last written before `older_than`.
+    /// Like retention, it leaves the latest written file alone.
+    pub fn purge(&self, older_than: Option<SystemTime>) -> Result<PurgeReport, String> {
+        let mut report = PurgeReport::default();
+        for file in self.removable(self.list_files()?) {
+            if older_than.is_none_or(|cutoff| file.modified < cutoff) {
+                remove_file(&file, &mut report);
+            }
+        }
+        println!(
+            "Purged {} local screenshot files ({} bytes)",
+            report.files_removed, report.bytes_removed
+        );
+        Ok(report)
+    }
+
+    // Drops the latest written file, which must stay.
+    fn removable(&self, mut files: Vec<StoredFile>) -> Vec<StoredFile> {
+        let last_written = self.last_written.lock().unwrap().clone();
+        files.retain(|file| last_written.as_ref() != Some(&file.hash));
+        files
+    }
+
+    fn list_files(&self) -> Result<Vec<StoredFile>, String> {
+        let mut files = Vec::new();
+        let shards = match fs::read_dir(&self.root) {
+            Ok(shards) => shards,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(files),
+            Err(e) => return Err(format!("Failed to read screenshots directory: {}", e)),
+        };
+        for shard in shards.flatten() {
+            if !shard.path().is_dir() {
+                continue;
+            }
+            let entries = fs::read_dir(shard.path())
+                .map_err(|e| format!("Failed to read screenshots directory: {}", e))?;
+            for entry in entries.flatten() {
+                let metadata = match entry.metadata() {
+                    Ok(m) if m.is_file() => m,
+                    _ => continue,
+                };
+                let path = entry.path();
+                let hash = path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                files.push(StoredFile {
+                    hash,
+                    path,
+                    size: metadata.len(),
+                    modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                });
+            }
+        }
+        Ok(files)
+    }
+}
+
+// Removes a file and records it in the report; returns false if deletion failed.
+fn remove_file(file: &StoredFile, report: &mut PurgeReport) -> bool {
+    match fs::remove_file(&file.path) {
+        Ok(()) => {
+            report.files_removed += 1;
+            report.bytes_removed += file.size;
+            report.hashes.push(file.hash.clone());
+            true
+        }
+        Err(e) => {
+            eprintln!("Failed to remove screenshot file {:?}: {}", file.path, e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_store(max_bytes: u64, max_age: Duration) -> LocalScreenshotStore {
+        let root = std::env::temp_dir().join(format!("screenshot-store-{}", uuid::Uuid::new_v4()));
+        LocalScreenshotStore::new(root, max_bytes, max_age)
+    }
+
+    #[test]
+    fn identical_frames_are_stored_once() {
+        let store = temp_store(1024 * 1024, Duration::from_secs(3600));
+        let first = store.put(b"frame").unwrap();
+        let second = store.put(b"frame").unwrap();
+        assert_eq!(first.hash, second.hash);
+        assert!(!first.deduplicated);
+        assert!(second.deduplicated);
+        assert_eq!(store.get(&first.hash).unwrap(), b"frame");
+        assert_eq!(store.list_files().unwrap().len(), 1);
+        fs::remove_dir_all(&store.root).unwrap();
+    }
+
+    #[test]
+    fn size_cap_evicts_oldest_files() {
+        let store = temp_store(10, Duration::from_secs(3600));
+        let old = store.put(b"0123456").unwrap();
+        let old_path = store.path_for(&old.hash).unwrap();
+        fs::File::options()
+            .append(true)
+            .open(&old_path)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(60))
+            .unwrap();
+        let new = store.put(b"abcdefg").unwrap();
+        store.enforce_retention(SystemTime::now()).unwrap();
+        assert!(store.get(&old.hash).is_err());
+        assert!(store.get(&new.hash).is_ok());
+        fs::remove_dir_all(&store.root).unwrap();
+    }
+
+    #[test]
+    fn size_cap_keeps_only_the_latest_file() {
+        let store = temp_store(1, Duration::from_secs(3600));
+        let older = store.put(b"older").unwrap();
+        let latest = store.put(b"latest").unwrap();
+
+        let report = store.enforce_retention(SystemTime::now()).unwrap();
+
+        assert_eq!(report.hashes, vec![older.hash]);
+        assert!(store.get(&latest.hash).is_ok());
+        fs::remove_dir_all(&store.root).unwrap();
+    }
+
+    #[test]
+    fn purge_keeps_the_latest_file() {
+        let store = temp_store(1024 * 1024, Duration::from_secs(3600));
+        store.put(b"older").unwrap();
+        let latest = store.put(b"latest").unwrap();
+
+        let report = store.purge(None).unwrap();
+
+        assert_eq!(report.files_removed, 1);
+        assert!(store.get(&latest.hash).is_ok());
+        fs::remove_dir_all(&store.root).unwrap();
+    }
+}
@@ -0,0 +1,32 @@
+use base64::{engine::general_purpose::STANDARD as BASE64_STANDARD, Engine as _};
+use crypto_box::{aead::OsRng, PublicKey, SecretKey};
+
+/// Encrypts an unblurred screenshot to the organization admins' X25519 public key
+/// (base64, from the capture policy). The device that took the screenshot cannot
+/// decrypt it again; only holders of the matching secret key can.
+pub fn seal(public_key_b64: &str, png: &[u8]) -> Result<Vec<u8>, String> {
+    let public_key = PublicKey::from(decode_key(public_key_b64, "public")?);
+    public_key
+        .seal(&mut OsRng, png)
+        .map_err(|e| format!("Failed to encrypt unblurred screenshot: {}", e))
+}
+
+/// Decrypts a sealed screenshot with the admin secret key from `SCREENSHOT_ORIGINALS_SECRET_KEY`.
+/// The variable should only be set on administrators' machines.
+pub fn unseal_with_env_key(sealed: &[u8]) -> Result<Vec<u8>, String> {
+    let secret_b64 = std::env::var("SCREENSHOT_ORIGINALS_SECRET_KEY")
+        .map_err(|_| "Unblurred screenshots can only be opened by an administrator".to_string())?;
+    let secret_key = SecretKey::from(decode_key(&secret_b64, "secret")?);
+    secret_key
+        .unseal(sealed)
+        .map_err(|_| "Failed to decrypt unblurred screenshot: wrong key or corrupted data".to_string())
+}
+
+fn decode_key(b64: &str, kind: &str) -> Result<[u8; 32], String> {
+    let bytes = BASE64_STANDARD
+        .decode(b64.trim())
+        .map_err(|e| format!("Invalid {} key encoding: {}", kind, e))?;
+    bytes
+        .try_into()
+        .map_err(|_| format!("Invalid {} key: expected 32 bytes", kind))
+}
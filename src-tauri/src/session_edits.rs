@@ -0,0 +1,567 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{PgConnection, Pool, Postgres, Row};
+use uuid::Uuid;
+
+use crate::authorization::{self, Owner};
+use crate::errors::AppError;
+use crate::identity::CurrentUser;
+use crate::pauses;
+use crate::timesheets;
+
+/// A correction to a finished session.
+#[derive(Debug, Clone)]
+pub enum SessionEdit {
+    /// Moves the start later and/or the end earlier.
+    Trim {
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+    },
+    /// Removes time in the middle; the session is split around the gap.
+    DeleteRange { from: DateTime<Utc>, to: DateTime<Utc> },
+    /// Splits the session in two at the given time.
+    Split { at: DateTime<Utc> },
+}
+
+/// A session as recorded before or after an edit; the audit log stores these as JSON.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SessionSnapshot {
+    pub id: Uuid,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub key_press_count: Option<i32>,
+    pub mouse_click_count: Option<i32>,
+}
+
+type Range = (DateTime<Utc>, DateTime<Utc>);
+
+impl SessionEdit {
+    fn edit_type(&self) -> &'static str {
+        match self {
+            SessionEdit::Trim { .. } => "trim",
+            SessionEdit::DeleteRange { .. } => "delete_range",
+            SessionEdit::Split { .. } => "split",
+        }
+    }
+
+    /// The parts of `[start, end]` that remain after the edit, in order. The first part keeps
+    /// the session's id; a second part becomes a new session.
+    fn kept_ranges(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<Range>, String> {
+        match *self {
+            SessionEdit::Trim { start_time, end_time } => {
+                let (new_start, new_end) = (start_time.unwrap_or(start), end_time.unwrap_or(end));
+                if new_start < start || new_end > end {
+                    return Err("Trimming can only shorten a session.".to_string());
+                }
+                if new_start >= new_end {
+                    return Err("A trimmed session must still end after it starts.".to_string());
+                }
+                if (new_start, new_end) == (start, end) {
+                    return Err("Nothing to trim.".to_string());
+                }
+                Ok(vec![(new_start, new_end)])
+            }
+            SessionEdit::DeleteRange { from, to } => {
+                if from >= to {
+                    return Err("The range to delete must end after it starts.".to_string());
+                }
+                if from <= start || to >= end {
+                    return Err("To remove time at the start or end of a session, trim it instead.".to_string());
+                }
+                Ok(vec![(start, from), (to, end)])
+            }
+            SessionEdit::Split { at } => {
+                if at <= start || at >= end {
+                    return Err("A session can only be split at a time inside it.".to_string());
+                }
+                Ok(vec![(start, at), (at, end)])
+            }
+        }
+    }
+}
+
+/// Activity counts only exist as totals, so the share of a count belonging to part of a span
+/// is taken proportionally to time. Cumulative rounding keeps the shares of adjacent parts
+/// adding up to the total.
+fn prorate(total: Option<i32>, span: Range, part: Range) -> Option<i32> {
+    let length = (span.1 - span.0).num_milliseconds().max(1);
+    let offset = |t: DateTime<Utc>| (t - span.0).num_milliseconds().clamp(0, length);
+    total.map(|total| {
+        let total = i64::from(total);
+        (total * offset(part.1) / length - total * offset(part.0) / length) as i32
+    })
+}
+
+fn intersect(a: Range, b: Range) -> Option<Range> {
+    let start = a.0.max(b.0);
+    let end = a.1.min(b.1);
+    (start < end).then_some((start, end))
+}
+
+/// Applies an edit in a single transaction and records it in `session_edits`.
+///
+/// Everything recorded in removed time is handled the same way: screenshots are kept for the
+/// audit trail but marked with `removed_by_edit`, task segments and pauses are clipped (or
+/// deleted if nothing of them remains), and activity counts shrink in proportion to the removed
+/// time. When the edit leaves two parts, screenshots, segments and pauses after the cut move to
+/// the new session, which keeps the original's flags and work schedule.
+pub async fn apply_edit(
+    pool: &Pool<Postgres>,
+    user: &CurrentUser,
+    session_id: Uuid,
+    edit: SessionEdit,
+    reason: &str,
+    now: DateTime<Utc>,
+) -> Result<Vec<SessionSnapshot>, AppError> {
+    let reason = reason.trim();
+    if reason.is_empty() {
+        return Err(AppError::Invalid("Please give a reason for editing the session.".to_string()));
+    }
+
+    let mut tx = pool.begin().await.map_err(|e| AppError::Db(format!("Failed to begin transaction: {}", e)))?;
+    let row = sqlx::query(
+        r#"
+        SELECT s.start_time, s.end_time, s.key_press_count, s.mouse_click_count, s.team_member_id,
+               m."organizationId" AS owner_organization
+        FROM sessions s
+        LEFT JOIN team_members m ON m.id = s.team_member_id
+        WHERE s.id = $1
+        FOR UPDATE OF s
+        "#,
+    )
+    .bind(session_id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| AppError::Db(format!("Database error fetching session: {}", e)))?
+    .ok_or_else(|| AppError::NotFound(format!("Session {} not found.", session_id)))?;
+
+    let column_error = |name: &str, e: sqlx::Error| AppError::Db(format!("Failed to get {} column: {}", name, e));
+    let end_time: Option<DateTime<Utc>> = row.try_get("end_time").map_err(|e| column_error("end_time", e))?;
+    let Some(end_time) = end_time else {
+        return Err(AppError::Invalid("Stop the timer before editing the running session.".to_string()));
+    };
+    let before = SessionSnapshot {
+        id: session_id,
+        start_time: row.try_get("start_time").map_err(|e| column_error("start_time", e))?,
+        end_time,
+        key_press_count: row.try_get("key_press_count").map_err(|e| column_error("key_press_count", e))?,
+        mouse_click_count: row.try_get("mouse_click_count").map_err(|e| column_error("mouse_click_count", e))?,
+    };
+    let owner = Owner {
+        team_member_id: row.try_get("team_member_id").map_err(|e| column_error("team_member_id", e))?,
+        organization_id: row.try_get("owner_organization").map_err(|e| column_error("owner_organization", e))?,
+    };
+    authorization::check_owner(user, "edit a session", owner)?;
+    if let Some(owner) = owner.team_member_id {
+        timesheets::ensure_unlocked(&mut *tx, owner, before.start_time, before.end_time).await?;
+    }
+
+    let span = (before.start_time, before.end_time);
+    let kept = edit.kept_ranges(span.0, span.1).map_err(AppError::Invalid)?;
+    let after: Vec<SessionSnapshot> = kept
+        .iter()
+        .enumerate()
+        .map(|(i, range)| SessionSnapshot {
+            id: if i == 0 { session_id } else { Uuid::new_v4() },
+            start_time: range.0,
+            end_time: range.1,
+            key_press_count: prorate(before.key_press_count, span, *range),
+            mouse_click_count: prorate(before.mouse_click_count, span, *range),
+        })
+        .collect();
+
+    // Write the audit row first so removed screenshots can point at it
+    let edit_id = Uuid::new_v4();
+    let placement = classify_screenshots(&mut tx, session_id, &after).await?;
+    sqlx::query(
+        r#"
+        INSERT INTO session_edits (id, session_id, edit_type, before_state, after_state, removed_screenshots, edited_by, reason, edited_at)
+        VALUES ($1, $2, $3, $4::jsonb, $5::jsonb, $6, $7, $8, $9)
+        "#,
+    )
+    .bind(edit_id)
+    .bind(session_id)
+    .bind(edit.edit_type())
+    .bind(serde_json::to_string(&[&before]).map_err(|e| AppError::Db(format!("Failed to serialize session: {}", e)))?)
+    .bind(serde_json::to_string(&after).map_err(|e| AppError::Db(format!("Failed to serialize sessions: {}", e)))?)
+    .bind(placement.removed.len() as i32)
+    .bind(user.team_member_id)
+    .bind(reason)
+    .bind(now)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to record session edit: {}", e)))?;
+
+    let first = &after[0];
+    sqlx::query(
+        r#"
+        UPDATE sessions
+        SET start_time = $2, end_time = $3, key_press_count = $4, mouse_click_count = $5
+        WHERE id = $1
+        "#,
+    )
+    .bind(first.id)
+    .bind(first.start_time)
+    .bind(first.end_time)
+    .bind(first.key_press_count)
+    .bind(first.mouse_click_count)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to write edited session: {}", e)))?;
+    // A second part is a copy of the original session with its own times and counts
+    for part in after.iter().skip(1) {
+        sqlx::query(
+            r#"
+            INSERT INTO sessions (id, start_time, end_time, key_press_count, mouse_click_count, team_member_id, clock_moved_backwards, work_schedule)
+            SELECT $1, $2, $3, $4, $5, team_member_id, clock_moved_backwards, work_schedule FROM sessions WHERE id = $6
+            "#,
+        )
+        .bind(part.id)
+        .bind(part.start_time)
+        .bind(part.end_time)
+        .bind(part.key_press_count)
+        .bind(part.mouse_click_count)
+        .bind(session_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::Db(format!("Failed to insert session part: {}", e)))?;
+    }
+
+    sqlx::query("UPDATE screenshots SET removed_by_edit = $1 WHERE id = ANY($2)")
+        .bind(edit_id)
+        .bind(&placement.removed)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::Db(format!("Failed to mark removed screenshots: {}", e)))?;
+    for (part, ids) in after.iter().zip(&placement.by_part).skip(1) {
+        sqlx::query("UPDATE screenshots SET session_id = $1 WHERE id = ANY($2)")
+            .bind(part.id)
+            .bind(ids)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| AppError::Db(format!("Failed to move screenshots: {}", e)))?;
+    }
+
+    split_task_segments(&mut tx, session_id, span, &after).await?;
+    if let Some(second) = after.get(1) {
+        pauses::split_pauses(&mut tx, session_id, second.id, second.start_time).await?;
+    }
+    for part in &after {
+        pauses::clip_pauses(&mut tx, part.id, (part.start_time, part.end_time)).await?;
+    }
+
+    tx.commit().await.map_err(|e| AppError::Db(format!("Failed to commit session edit: {}", e)))?;
+    println!(
+        "Session {} edited ({}) by team member {}: {} part(s), {} screenshot(s) removed.",
+        session_id,
+        edit.edit_type(),
+        user.team_member_id,
+        after.len(),
+        placement.removed.len()
+    );
+    Ok(after)
+}
+
+// Screenshots of the session, grouped by the part of the edited session they fall in
+struct ScreenshotPlacement {
+    by_part: Vec<Vec<Uuid>>,
+    removed: Vec<Uuid>,
+}
+
+async fn classify_screenshots(
+    conn: &mut PgConnection,
+    session_id: Uuid,
+    parts: &[SessionSnapshot],
+) -> Result<ScreenshotPlacement, AppError> {
+    let rows: Vec<(Uuid, DateTime<Utc>)> = sqlx::query_as(
+        "SELECT id, capture_time FROM screenshots WHERE session_id = $1 AND removed_by_edit IS NULL",
+    )
+    .bind(session_id)
+    .fetch_all(conn)
+    .await
+    .map_err(|e| AppError::Db(format!("Database error fetching session screenshots: {}", e)))?;
+
+    let session_end = parts.last().map(|p| p.end_time);
+    let mut placement = ScreenshotPlacement { by_part: vec![Vec::new(); parts.len()], removed: Vec::new() };
+    for (id, capture_time) in rows {
+        // A capture exactly on a cut belongs to the later part, or to the last part at the very end
+        let part = parts.iter().position(|p| {
+            capture_time >= p.start_time
+                && (capture_time < p.end_time || Some(capture_time) == session_end)
+        });
+        match part {
+            Some(i) => placement.by_part[i].push(id),
+            None => placement.removed.push(id),
+        }
+    }
+    Ok(placement)
+}
+
+// Clips each task segment to the parts of the edited session, prorating its activity counts
+async fn split_task_segments(
+    conn: &mut PgConnection,
+    session_id: Uuid,
+    span: Range,
+    parts: &[SessionSnapshot],
+) -> Result<(), AppError> {
+    let rows = sqlx::query(
+        "SELECT id, task, project, start_time, end_time, key_press_count, mouse_click_count FROM task_segments WHERE session_id = $1",
+    )
+    .bind(session_id)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| AppError::Db(format!("Database error fetching task segments: {}", e)))?;
+
+    for row in rows {
+        let column_error = |name: &str, e: sqlx::Error| AppError::Db(format!("Failed to get {} column: {}", name, e));
+        let id: Uuid = row.try_get("id").map_err(|e| column_error("id", e))?;
+        let task: Option<String> = row.try_get("task").map_err(|e| column_error("task", e))?;
+        let project: Option<String> = row.try_get("project").map_err(|e| column_error("project", e))?;
+        let start: DateTime<Utc> = row.try_get("start_time").map_err(|e| column_error("start_time", e))?;
+        let end: Option<DateTime<Utc>> = row.try_get("end_time").map_err(|e| column_error("end_time", e))?;
+        let keys: Option<i32> = row.try_get("key_press_count").map_err(|e| column_error("key_press_count", e))?;
+        let clicks: Option<i32> = row.try_get("mouse_click_count").map_err(|e| column_error("mouse_click_count", e))?;
+        let segment = (start, end.unwrap_or(span.1));
+
+        let pieces: Vec<(Uuid, Range)> = parts
+            .iter()
+            .filter_map(|part| intersect(segment, (part.start_time, part.end_time)).map(|piece| (part.id, piece)))
+            .collect();
+        if pieces.is_empty() {
+            sqlx::query("DELETE FROM task_segments WHERE id = $1")
+                .bind(id)
+                .execute(&mut *conn)
+                .await
+                .map_err(|e| AppError::Db(format!("Failed to delete task segment: {}", e)))?;
+            continue;
+        }
+        for (i, (part_id, piece)) in pieces.iter().enumerate() {
+            let query = if i == 0 {
+                r#"
+                UPDATE task_segments
+                SET session_id = $2, start_time = $3, end_time = $4, key_press_count = $5, mouse_click_count = $6,
+                    task = $7, project = $8
+                WHERE id = $1
+                "#
+            } else {
+                r#"
+                INSERT INTO task_segments (id, session_id, start_time, end_time, key_press_count, mouse_click_count, task, project)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                "#
+            };
+            sqlx::query(query)
+                .bind(if i == 0 { id } else { Uuid::new_v4() })
+                .bind(part_id)
+                .bind(piece.0)
+                .bind(piece.1)
+                .bind(prorate(keys, segment, *piece))
+                .bind(prorate(clicks, segment, *piece))
+                .bind(&task)
+                .bind(&project)
+                .execute(&mut *conn)
+                .await
+                .map_err(|e| AppError::Db(format!("Failed to write task segment: {}", e)))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authorization::Denial;
+    use crate::identity::TeamMemberRole;
+    use crate::test_db;
+    use chrono::{Duration, TimeZone};
+
+    // Minutes into a session running from 9:00 to 12:00
+    fn at(minutes: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 6, 9, 0, 0).unwrap() + Duration::minutes(minutes)
+    }
+
+    #[test]
+    fn trim_keeps_the_shortened_range() {
+        let trim = SessionEdit::Trim { start_time: None, end_time: Some(at(120)) };
+        assert_eq!(trim.kept_ranges(at(0), at(180)).unwrap(), vec![(at(0), at(120))]);
+    }
+
+    #[test]
+    fn trim_cannot_lengthen_a_session() {
+        let trim = SessionEdit::Trim { start_time: None, end_time: Some(at(200)) };
+        assert!(trim.kept_ranges(at(0), at(180)).is_err());
+    }
+
+    #[test]
+    fn deleting_a_range_keeps_the_time_around_it() {
+        let delete = SessionEdit::DeleteRange { from: at(60), to: at(90) };
+        assert_eq!(delete.kept_ranges(at(0), at(180)).unwrap(), vec![(at(0), at(60)), (at(90), at(180))]);
+    }
+
+    #[test]
+    fn deleting_a_range_at_the_start_is_left_to_trim() {
+        let delete = SessionEdit::DeleteRange { from: at(0), to: at(30) };
+        assert!(delete.kept_ranges(at(0), at(180)).is_err());
+    }
+
+    #[test]
+    fn split_shares_add_up_to_the_total() {
+        let parts = SessionEdit::Split { at: at(61) }.kept_ranges(at(0), at(180)).unwrap();
+        let shares: Vec<i32> = parts.iter().map(|part| prorate(Some(1001), (at(0), at(180)), *part).unwrap()).collect();
+        assert_eq!(shares.iter().sum::<i32>(), 1001);
+    }
+
+    #[test]
+    fn counts_are_shared_in_proportion_to_time() {
+        assert_eq!(prorate(Some(900), (at(0), at(180)), (at(0), at(60))), Some(300));
+    }
+
+    async fn session_times(pool: &Pool<Postgres>, session_id: Uuid) -> (DateTime<Utc>, Option<DateTime<Utc>>) {
+        sqlx::query_as("SELECT start_time, end_time FROM sessions WHERE id = $1")
+            .bind(session_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    async fn pauses_of(pool: &Pool<Postgres>, session_id: Uuid) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+        sqlx::query_as("SELECT start_time, end_time FROM pause_segments WHERE session_id = $1 ORDER BY start_time")
+            .bind(session_id)
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    // Needs Postgres, see `test_db`
+    #[tokio::test]
+    #[ignore]
+    async fn deleting_a_range_splits_the_session_and_its_screenshots() {
+        let pool = test_db::pool().await;
+        let organization = test_db::organization(&pool).await;
+        let user = test_db::team_member(&pool, organization, TeamMemberRole::Employee).await;
+        let session = test_db::session(&pool, user.team_member_id, at(0), Some(at(180))).await;
+        let before = test_db::screenshot(&pool, session, at(30)).await;
+        let removed = test_db::screenshot(&pool, session, at(75)).await;
+        let after = test_db::screenshot(&pool, session, at(150)).await;
+
+        let edit = SessionEdit::DeleteRange { from: at(60), to: at(90) };
+        let parts = apply_edit(&pool, &user, session, edit, "Lunch", at(300)).await.unwrap();
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!(session_times(&pool, session).await, (at(0), Some(at(60))));
+        assert_eq!(session_times(&pool, parts[1].id).await, (at(90), Some(at(180))));
+        let placed: Vec<(Uuid, Option<Uuid>, bool)> = sqlx::query_as(
+            "SELECT id, session_id, removed_by_edit IS NOT NULL FROM screenshots WHERE id = ANY($1) ORDER BY capture_time",
+        )
+        .bind(vec![before, removed, after])
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(placed, vec![(before, Some(session), false), (removed, Some(session), true), (after, Some(parts[1].id), false)]);
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn trimming_clips_task_segments() {
+        let pool = test_db::pool().await;
+        let organization = test_db::organization(&pool).await;
+        let user = test_db::team_member(&pool, organization, TeamMemberRole::Employee).await;
+        let session = test_db::session(&pool, user.team_member_id, at(0), Some(at(180))).await;
+        let segment = Uuid::new_v4();
+        sqlx::query("INSERT INTO task_segments (id, session_id, project, start_time, end_time) VALUES ($1, $2, 'Billing', $3, $4)")
+            .bind(segment)
+            .bind(session)
+            .bind(at(0))
+            .bind(at(180))
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let edit = SessionEdit::Trim { start_time: None, end_time: Some(at(120)) };
+        apply_edit(&pool, &user, session, edit, "Forgot to stop", at(300)).await.unwrap();
+
+        let end_time: DateTime<Utc> = sqlx::query_scalar("SELECT end_time FROM task_segments WHERE id = $1")
+            .bind(segment)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(end_time, at(120));
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn cutting_a_range_out_moves_and_clips_pauses() {
+        let pool = test_db::pool().await;
+        let organization = test_db::organization(&pool).await;
+        let user = test_db::team_member(&pool, organization, TeamMemberRole::Employee).await;
+        let session = test_db::session(&pool, user.team_member_id, at(0), Some(at(180))).await;
+        let mut conn = pool.acquire().await.unwrap();
+        pauses::record_pause(&mut conn, session, pauses::PauseReason::User, at(50), at(70)).await.unwrap();
+        pauses::record_pause(&mut conn, session, pauses::PauseReason::User, at(80), at(100)).await.unwrap();
+        pauses::record_pause(&mut conn, session, pauses::PauseReason::User, at(120), at(130)).await.unwrap();
+
+        let edit = SessionEdit::DeleteRange { from: at(60), to: at(90) };
+        let parts = apply_edit(&pool, &user, session, edit, "Lunch", at(300)).await.unwrap();
+
+        assert_eq!(pauses_of(&pool, session).await, vec![(at(50), at(60))]);
+        assert_eq!(pauses_of(&pool, parts[1].id).await, vec![(at(90), at(100)), (at(120), at(130))]);
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn split_parts_keep_the_sessions_flags() {
+        let pool = test_db::pool().await;
+        let organization = test_db::organization(&pool).await;
+        let user = test_db::team_member(&pool, organization, TeamMemberRole::Employee).await;
+        let session = test_db::session(&pool, user.team_member_id, at(0), Some(at(180))).await;
+        sqlx::query(r#"UPDATE sessions SET clock_moved_backwards = TRUE, work_schedule = '{"timezone": "UTC"}' WHERE id = $1"#)
+            .bind(session)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let parts = apply_edit(&pool, &user, session, SessionEdit::Split { at: at(60) }, "Two tasks", at(300)).await.unwrap();
+
+        let copied: (i32, bool, Option<String>) =
+            sqlx::query_as("SELECT team_member_id, clock_moved_backwards, work_schedule::text FROM sessions WHERE id = $1")
+                .bind(parts[1].id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(copied, (user.team_member_id, true, Some(r#"{"timezone": "UTC"}"#.to_string())));
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn edits_are_logged_with_their_reason() {
+        let pool = test_db::pool().await;
+        let organization = test_db::organization(&pool).await;
+        let user = test_db::team_member(&pool, organization, TeamMemberRole::Employee).await;
+        let session = test_db::session(&pool, user.team_member_id, at(0), Some(at(180))).await;
+
+        apply_edit(&pool, &user, session, SessionEdit::Split { at: at(60) }, " Two tasks ", at(300)).await.unwrap();
+
+        let logged: (String, String, i32) =
+            sqlx::query_as("SELECT edit_type, reason, edited_by FROM session_edits WHERE session_id = $1")
+                .bind(session)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(logged, ("split".to_string(), "Two tasks".to_string(), user.team_member_id));
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn employees_cannot_edit_a_colleagues_session() {
+        let pool = test_db::pool().await;
+        let organization = test_db::organization(&pool).await;
+        let user = test_db::team_member(&pool, organization, TeamMemberRole::Employee).await;
+        let colleague = test_db::team_member(&pool, organization, TeamMemberRole::Employee).await;
+        let session = test_db::session(&pool, colleague.team_member_id, at(0), Some(at(180))).await;
+
+        let edit = SessionEdit::Split { at: at(60) };
+        let result = apply_edit(&pool, &user, session, edit, "Two tasks", at(300)).await;
+
+        assert_eq!(result, Err(AppError::Unauthorized(Denial::NotOwner)));
+        assert_eq!(session_times(&pool, session).await, (at(0), Some(at(180))));
+    }
+}
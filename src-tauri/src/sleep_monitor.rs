@@ -0,0 +1,73 @@
+use chrono::{DateTime, Utc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// How long after a clock jump a sleep signal may still arrive and explain it. The signal
+/// sent before sleeping is often only read after waking up.
+const SIGNAL_GRACE: chrono::Duration = chrono::Duration::minutes(1);
+
+/// Times at which the system announced it was going to sleep or waking up, as reported by
+/// systemd-logind. Used to tell a suspend from the clock being set forwards, which look the
+/// same when only comparing wall-clock and monotonic time.
+#[derive(Default)]
+pub struct SleepLog {
+    watching: AtomicBool,
+    signals: Mutex<Vec<DateTime<Utc>>>,
+}
+
+impl SleepLog {
+    fn record(&self, at: DateTime<Utc>) {
+        let mut signals = self.signals.lock().unwrap();
+        // Only the recent past is ever asked about
+        signals.retain(|signal| at - *signal < chrono::Duration::days(1));
+        signals.push(at);
+    }
+
+    /// Whether the system slept during a jump from `from` to `to`. `None` when sleep signals
+    /// aren't available on this system.
+    pub fn slept_between(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Option<bool> {
+        if !self.watching.load(Ordering::Relaxed) {
+            return None;
+        }
+        let signals = self.signals.lock().unwrap();
+        Some(signals.iter().any(|at| *at >= from && *at <= to + SIGNAL_GRACE))
+    }
+}
+
+/// Listens for logind's `PrepareForSleep` signal on the system bus until the connection ends.
+#[cfg(target_os = "linux")]
+pub async fn watch_logind(log: std::sync::Arc<SleepLog>) -> Result<(), String> {
+    use futures_util::StreamExt;
+
+    let connection = zbus::Connection::system()
+        .await
+        .map_err(|e| format!("Failed to connect to the system bus: {}", e))?;
+    let proxy = zbus::Proxy::new(
+        &connection,
+        "org.freedesktop.login1",
+        "/org/freedesktop/login1",
+        "org.freedesktop.login1.Manager",
+    )
+    .await
+    .map_err(|e| format!("Failed to reach logind: {}", e))?;
+    let mut signals = proxy
+        .receive_signal("PrepareForSleep")
+        .await
+        .map_err(|e| format!("Failed to subscribe to logind sleep signals: {}", e))?;
+
+    log.watching.store(true, Ordering::Relaxed);
+    println!("Watching logind for suspend and resume.");
+    while let Some(message) = signals.next().await {
+        let going_to_sleep: bool = message.body().deserialize().unwrap_or_default();
+        println!("logind: system is {}.", if going_to_sleep { "going to sleep" } else { "waking up" });
+        log.record(Utc::now());
+    }
+    log.watching.store(false, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Sleep signals are only read from logind; elsewhere suspends are inferred from clock drift alone.
+#[cfg(not(target_os = "linux"))]
+pub async fn watch_logind(_log: std::sync::Arc<SleepLog>) -> Result<(), String> {
+    Ok(())
+}
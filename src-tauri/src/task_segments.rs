@@ -0,0 +1,77 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::activity_monitor::ActivityData;
+use crate::errors::AppError;
+
+/// The task currently being worked on within a session. Activity counters are never reset
+/// during a session, so the counts at the start of the segment are kept to split them later.
+#[derive(Clone, Debug)]
+pub struct OpenSegment {
+    pub id: Uuid,
+    pub session_id: Uuid,
+    pub task: Option<String>,
+    pub project: Option<String>,
+    pub start_time: DateTime<Utc>,
+    start_counts: ActivityData,
+}
+
+/// Inserts a new open segment starting at `at`.
+pub async fn open_segment(
+    conn: &mut PgConnection,
+    session_id: Uuid,
+    task: Option<String>,
+    project: Option<String>,
+    at: DateTime<Utc>,
+    counts: ActivityData,
+) -> Result<OpenSegment, AppError> {
+    let segment = OpenSegment {
+        id: Uuid::new_v4(),
+        session_id,
+        task,
+        project,
+        start_time: at,
+        start_counts: counts,
+    };
+    sqlx::query(
+        r#"
+        INSERT INTO task_segments (id, session_id, task, project, start_time)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(segment.id)
+    .bind(segment.session_id)
+    .bind(&segment.task)
+    .bind(&segment.project)
+    .bind(segment.start_time)
+    .execute(conn)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to insert task segment: {}", e)))?;
+    Ok(segment)
+}
+
+/// Ends the segment at `at` and stores the activity that happened during it.
+pub async fn close_segment(
+    conn: &mut PgConnection,
+    segment: &OpenSegment,
+    at: DateTime<Utc>,
+    counts: &ActivityData,
+) -> Result<ActivityData, AppError> {
+    let activity = counts.since(&segment.start_counts);
+    sqlx::query(
+        r#"
+        UPDATE task_segments
+        SET end_time = $1, key_press_count = $2, mouse_click_count = $3
+        WHERE id = $4
+        "#,
+    )
+    .bind(at)
+    .bind(activity.key_presses as i32)
+    .bind(activity.mouse_clicks as i32)
+    .bind(segment.id)
+    .execute(conn)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to close task segment: {}", e)))?;
+    Ok(activity)
+}
@@ -0,0 +1,98 @@
+//! Fixtures for tests that run against Postgres. They're `#[ignore]`d: point `TEST_DATABASE_URL`
+//! at a scratch database with the web app's Prisma schema applied and run them with `--ignored`.
+
+use chrono::{DateTime, Utc};
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{Pool, Postgres};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::identity::{CurrentUser, TeamMemberRole};
+
+// Tests run in parallel, and two `setup_database` runs at once trip over each other
+static SET_UP: Mutex<bool> = Mutex::const_new(false);
+
+/// Connects to `TEST_DATABASE_URL` and makes sure the app's tables exist.
+pub async fn pool() -> Pool<Postgres> {
+    let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set for this test");
+    let pool = PgPoolOptions::new()
+        .max_connections(2)
+        .connect(&url)
+        .await
+        .expect("Failed to connect to the test database");
+    let mut set_up = SET_UP.lock().await;
+    if !*set_up {
+        crate::setup_database(&pool).await.expect("Failed to set up the test database");
+        *set_up = true;
+    }
+    pool
+}
+
+/// A new organization and the user owning it. Names are unique, so tests don't see each other's data.
+pub async fn organization(pool: &Pool<Postgres>) -> i32 {
+    let user_id = Uuid::new_v4().to_string();
+    sqlx::query("INSERT INTO users (id, email) VALUES ($1, $2)")
+        .bind(&user_id)
+        .bind(format!("{}@example.com", user_id))
+        .execute(pool)
+        .await
+        .expect("Failed to insert user");
+    sqlx::query_scalar(r#"INSERT INTO organizations (name, "userId") VALUES ($1, $2) RETURNING id"#)
+        .bind(format!("Test organization {}", user_id))
+        .bind(&user_id)
+        .fetch_one(pool)
+        .await
+        .expect("Failed to insert organization")
+}
+
+/// A new team member of the organization, as `load_current_user` would return them.
+pub async fn team_member(pool: &Pool<Postgres>, organization_id: i32, role: TeamMemberRole) -> CurrentUser {
+    let email = format!("{}@example.com", Uuid::new_v4());
+    let role_name = match role {
+        TeamMemberRole::Employee => "employee",
+        TeamMemberRole::Manager => "manager",
+        TeamMemberRole::Admin => "admin",
+    };
+    let team_member_id = sqlx::query_scalar(
+        r#"INSERT INTO team_members ("organizationId", role, email) VALUES ($1, $2::"TeamMemberRole", $3) RETURNING id"#,
+    )
+    .bind(organization_id)
+    .bind(role_name)
+    .bind(&email)
+    .fetch_one(pool)
+    .await
+    .expect("Failed to insert team member");
+    CurrentUser { team_member_id, organization_id, email, role }
+}
+
+/// A session of the team member; `end_time` is `None` for one still running.
+pub async fn session(
+    pool: &Pool<Postgres>,
+    team_member_id: i32,
+    start_time: DateTime<Utc>,
+    end_time: Option<DateTime<Utc>>,
+) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query("INSERT INTO sessions (id, start_time, end_time, team_member_id) VALUES ($1, $2, $3, $4)")
+        .bind(id)
+        .bind(start_time)
+        .bind(end_time)
+        .bind(team_member_id)
+        .execute(pool)
+        .await
+        .expect("Failed to insert session");
+    id
+}
+
+/// A screenshot of the session without an image, which is all most queries look at.
+pub async fn screenshot(pool: &Pool<Postgres>, session_id: Uuid, capture_time: DateTime<Utc>) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query("INSERT INTO screenshots (id, session_id, capture_time) VALUES ($1, $2, $3)")
+        .bind(id)
+        .bind(session_id)
+        .bind(capture_time)
+        .execute(pool)
+        .await
+        .expect("Failed to insert screenshot");
+    id
+}
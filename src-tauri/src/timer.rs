@@ -0,0 +1,752 @@
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+use crate::breaks::{BreakDue, BreakTracker, PomodoroSettings, PomodoroState};
+use crate::clock::{BoxFuture, Clock, ClockJump, ClockJumpDetector};
+use crate::errors::AppError;
+use crate::policy::CapturePolicy;
+use crate::rng::SharedRng;
+use crate::scheduler::SlotScheduler;
+use crate::timer_state::TimerStatus;
+use crate::work_schedule::{ScheduleEnd, WorkSchedule};
+use crate::workday;
+
+// Represents the possible states of the timer/screenshot task
+#[derive(Clone, serde::Serialize, Debug, PartialEq)]
+pub enum TimerCommand {
+    Pause,
+    Resume,
+    Stop,
+    SetPomodoro(Option<PomodoroSettings>),
+}
+
+/// How often the timer task compares wall-clock and monotonic time when nothing else wakes it.
+const CLOCK_CHECK_INTERVAL: Duration = Duration::seconds(10);
+
+/// What the timer task needs from the rest of the app: taking screenshots, reporting
+/// failures, acting on the session limits, clock jumps and breaks. Tests provide a recording implementation.
+pub trait TimerHost: Send + Sync {
+    fn capture(&self) -> BoxFuture<'_, Result<(), String>>;
+    fn capture_failed(&self, error: String);
+    /// The run is about to reach its maximum length and will stop at `stops_at`.
+    fn session_length_warning(&self, stops_at: DateTime<Utc>);
+    /// Stops the run because it reached its maximum length.
+    fn auto_stop(&self) -> BoxFuture<'_, Result<(), AppError>>;
+    /// Ends the current session at a local day boundary and continues in a new one.
+    fn split_session(&self, at: DateTime<Utc>) -> BoxFuture<'_, Result<(), AppError>>;
+    /// The system was suspended, or its clock was set, since the task last looked.
+    fn clock_jumped(&self, jump: ClockJump) -> BoxFuture<'_, Result<(), AppError>>;
+    /// A Pomodoro work interval is over: pause the session for the break.
+    fn break_started(&self) -> BoxFuture<'_, Result<(), AppError>>;
+    /// The Pomodoro break is over: resume the session.
+    fn break_ended(&self) -> BoxFuture<'_, Result<(), AppError>>;
+    /// The user has been active for `worked` without a break.
+    fn break_reminder(&self, worked: Duration);
+    fn pomodoro_changed(&self, state: Option<PomodoroState>);
+    /// The work schedule's window ended while running: pause or stop the session as `at_end` says.
+    fn schedule_ended(&self, at_end: ScheduleEnd) -> BoxFuture<'_, Result<(), AppError>>;
+}
+
+/// Limits on a tracking run that the timer task enforces.
+#[derive(Debug, Clone)]
+pub struct SessionLimits {
+    pub started_at: DateTime<Utc>,
+    /// The run is stopped after this long; `None` disables the limit.
+    pub max_length: Option<Duration>,
+    pub warn_before: Duration,
+    /// Sessions are split at the start of each day in this timezone.
+    pub timezone: Tz,
+    /// Running sessions are paused or stopped when a window of the schedule ends.
+    pub schedule: Option<WorkSchedule>,
+}
+
+impl SessionLimits {
+    pub fn from_policy(
+        policy: &CapturePolicy,
+        started_at: DateTime<Utc>,
+        timezone: Tz,
+        schedule: Option<WorkSchedule>,
+    ) -> Self {
+        SessionLimits {
+            started_at,
+            max_length: (policy.max_session_minutes > 0)
+                .then(|| Duration::minutes(i64::from(policy.max_session_minutes))),
+            warn_before: Duration::minutes(i64::from(policy.session_warning_minutes)),
+            timezone,
+            schedule,
+        }
+    }
+}
+
+// Things the timer task waits for besides commands
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Deadline {
+    DayStart,
+    AutoStop,
+    LengthWarning,
+    Capture,
+    ClockCheck,
+    Break(BreakDue),
+    ScheduleEnd,
+}
+
+// The main async task for the screenshot schedule and session limits. Status changes go
+// through the `TimerStateMachine`; the commands received here only pause and resume the schedule.
+pub async fn timer_task(
+    host: Arc<dyn TimerHost>,
+    clock: Arc<dyn Clock>,
+    rng: SharedRng,
+    mut scheduler: SlotScheduler,
+    limits: SessionLimits,
+    mut breaks: BreakTracker,
+    mut command_rx: mpsc::Receiver<TimerCommand>,
+) {
+    // The session can change underneath (midnight splits), so the host keeps track of it
+    println!("Timer task started.");
+    let mut is_paused = false;
+    let mut max_length = limits.max_length;
+    let mut warned = false;
+    let mut next_day_start = workday::next_day_start(limits.started_at, limits.timezone);
+    let mut clock_jumps = ClockJumpDetector::new(clock.as_ref());
+    breaks.resume(clock.instant(), clock.now());
+    host.pomodoro_changed(breaks.pomodoro_state(clock.instant(), clock.now()));
+
+    loop {
+        // Wait for whichever comes first: a command, or the next deadline. Day boundaries and
+        // the length limit apply while paused too; captures are only scheduled while running.
+        let now = clock.now();
+        let stops_at = max_length.map(|max| limits.started_at + max);
+        let mut deadlines = vec![
+            (next_day_start, Deadline::DayStart),
+            (now + CLOCK_CHECK_INTERVAL, Deadline::ClockCheck),
+        ];
+        if let Some(stops_at) = stops_at {
+            deadlines.push((stops_at, Deadline::AutoStop));
+            if !warned {
+                deadlines.push((stops_at - limits.warn_before, Deadline::LengthWarning));
+            }
+        }
+        if !is_paused {
+            deadlines.push((scheduler.next_capture(now, &mut *rng.lock().unwrap()), Deadline::Capture));
+            // Only a window being worked in ends; a run started outside the hours isn't cut short
+            if let Some((_, end)) = limits.schedule.as_ref().and_then(|schedule| schedule.window_at(now)) {
+                deadlines.push((end, Deadline::ScheduleEnd));
+            }
+        }
+        if let Some((at, due)) = breaks.next_due(clock.instant(), now) {
+            deadlines.push((at, Deadline::Break(due)));
+        }
+        let (at, deadline) = deadlines
+            .into_iter()
+            .min_by_key(|(at, _)| *at)
+            .expect("the clock check is always pending");
+        let delay = (at - now).to_std().unwrap_or_default();
+
+        // `None` when a deadline is due, otherwise what the command channel gave
+        let command = tokio::select! {
+            command = command_rx.recv() => Some(command),
+            _ = clock.sleep(delay) => None,
+        };
+
+        // A suspend or clock change shows up as a jump of the wall clock, checked on every
+        // wake-up so nothing is done on stale timing. Captures planned in the gap are re-planned
+        // like after a pause, and the deadlines are worked out again.
+        if let Some(jump) = clock_jumps.check(clock.as_ref()) {
+            println!("Wall clock jumped from {} to {}.", jump.from, jump.to);
+            if let Err(e) = host.clock_jumped(jump).await {
+                eprintln!("Failed to record the clock jump: {}", e);
+            }
+            if jump.is_backwards() {
+                scheduler.reset();
+            } else if !is_paused {
+                scheduler.resume(clock.now(), &mut *rng.lock().unwrap());
+            }
+            if command.is_none() {
+                continue;
+            }
+        }
+
+        let Some(command) = command else {
+            match deadline {
+                Deadline::Capture => {
+                    scheduler.mark_taken();
+                    println!("Taking screenshot...");
+                    if let Err(e) = host.capture().await {
+                        eprintln!("Error capturing/saving screenshot: {}", e);
+                        host.capture_failed(e);
+                    }
+                }
+                Deadline::LengthWarning => {
+                    warned = true;
+                    if let Some(stops_at) = stops_at {
+                        println!("Tracking run will stop automatically at {}.", stops_at);
+                        host.session_length_warning(stops_at);
+                    }
+                }
+                Deadline::AutoStop => {
+                    println!("Tracking run reached its maximum length. Stopping.");
+                    match host.auto_stop().await {
+                        Ok(()) => break,
+                        Err(e) => {
+                            // Don't retry in a loop; the user can still stop the timer by hand
+                            eprintln!("Failed to stop the timer automatically: {}", e);
+                            max_length = None;
+                        }
+                    }
+                }
+                Deadline::DayStart => {
+                    println!("New local day started. Splitting the session.");
+                    if let Err(e) = host.split_session(at).await {
+                        eprintln!("Failed to split the session at the day boundary: {}", e);
+                    }
+                    next_day_start = workday::next_day_start(at, limits.timezone);
+                }
+                Deadline::ClockCheck => {} // Only there to wake the task for the jump check
+                Deadline::Break(BreakDue::WorkDone) => {
+                    println!("Pomodoro work interval over. Pausing for a break.");
+                    breaks.start_break(clock.instant(), clock.now());
+                    is_paused = true;
+                    if let Err(e) = host.break_started().await {
+                        // Most likely paused or stopped by the user meanwhile; their command follows
+                        eprintln!("Failed to pause for the break: {}", e);
+                        breaks.resume(clock.instant(), clock.now());
+                        is_paused = false;
+                    }
+                    host.pomodoro_changed(breaks.pomodoro_state(clock.instant(), clock.now()));
+                }
+                Deadline::Break(BreakDue::BreakOver) => {
+                    println!("Pomodoro break over. Resuming.");
+                    breaks.end_break();
+                    match host.break_ended().await {
+                        Ok(()) => {
+                            breaks.resume(clock.instant(), clock.now());
+                            is_paused = false;
+                            scheduler.resume(clock.now(), &mut *rng.lock().unwrap());
+                        }
+                        // Stays paused until resumed by hand; a stop by the user meanwhile follows as a command
+                        Err(e) => eprintln!("Failed to resume after the break: {}", e),
+                    }
+                    host.pomodoro_changed(breaks.pomodoro_state(clock.instant(), clock.now()));
+                }
+                Deadline::Break(BreakDue::Nudge) => {
+                    let worked = breaks.nudge(clock.instant());
+                    println!("Worked {} minutes without a break.", worked.num_minutes());
+                    host.break_reminder(worked);
+                }
+                Deadline::ScheduleEnd => {
+                    let at_end = limits.schedule.as_ref().map_or(ScheduleEnd::Pause, |schedule| schedule.at_end);
+                    println!("Work schedule window ended.");
+                    match host.schedule_ended(at_end).await {
+                        Ok(()) if at_end == ScheduleEnd::Stop => break,
+                        Ok(()) => {
+                            is_paused = true;
+                            breaks.pause(clock.instant(), clock.now());
+                            host.pomodoro_changed(breaks.pomodoro_state(clock.instant(), clock.now()));
+                        }
+                        // Past the window end now, so this isn't retried
+                        Err(e) => eprintln!("Failed to end tracking at the schedule end: {}", e),
+                    }
+                }
+            }
+            continue;
+        };
+
+        match command {
+            Some(TimerCommand::Pause) => {
+                println!("Timer task received PAUSE command.");
+                is_paused = true;
+                breaks.pause(clock.instant(), clock.now());
+                host.pomodoro_changed(breaks.pomodoro_state(clock.instant(), clock.now()));
+            }
+            Some(TimerCommand::Resume) => {
+                println!("Timer task received RESUME command.");
+                is_paused = false;
+                // Continue the current slot, re-planning captures that fell into the pause
+                scheduler.resume(clock.now(), &mut *rng.lock().unwrap());
+                // Also ends a Pomodoro break early
+                breaks.resume(clock.instant(), clock.now());
+                host.pomodoro_changed(breaks.pomodoro_state(clock.instant(), clock.now()));
+            }
+            Some(TimerCommand::SetPomodoro(settings)) => {
+                println!("Timer task received POMODORO command.");
+                breaks.set_pomodoro(settings, clock.instant());
+                host.pomodoro_changed(breaks.pomodoro_state(clock.instant(), clock.now()));
+            }
+            Some(TimerCommand::Stop) | None => {
+                if command.is_some() {
+                    println!("Timer task received STOP command.");
+                } else {
+                    println!("Timer command channel disconnected. Stopping task.");
+                }
+                break; // Exit the loop
+            }
+        }
+    }
+
+    println!("Timer task finished.");
+}
+
+/// Seconds worked since the session started, leaving out `paused` time, or 0 when the timer is stopped.
+pub fn elapsed_seconds(
+    status: &TimerStatus,
+    start_time: Option<DateTime<Utc>>,
+    paused: Duration,
+    now: DateTime<Utc>,
+) -> u64 {
+    match status {
+        TimerStatus::Running | TimerStatus::Paused => {
+            if let Some(start_time) = start_time {
+                let duration = now.signed_duration_since(start_time) - paused;
+                // Ensure duration is non-negative before converting
+                duration.num_seconds().max(0) as u64
+            } else {
+                // Should not happen if running/paused, but return 0 defensively
+                println!("Warning: Timer is running/paused but session start time is missing.");
+                0
+            }
+        }
+        TimerStatus::Stopped => 0, // Return 0 if stopped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::rng::seeded_rng;
+    use chrono::TimeZone;
+    use std::time::Duration;
+
+    // Records what the timer task asked the app to do, stamped with virtual time
+    struct RecordingHost {
+        clock: Arc<MockClock>,
+        captures: std::sync::Mutex<Vec<DateTime<Utc>>>,
+        events: std::sync::Mutex<Vec<(&'static str, DateTime<Utc>)>>,
+        jumps: std::sync::Mutex<Vec<ClockJump>>,
+        refuse_break_end: std::sync::atomic::AtomicBool,
+    }
+
+    impl RecordingHost {
+        fn record(&self, event: &'static str) {
+            self.events.lock().unwrap().push((event, self.clock.now()));
+        }
+    }
+
+    impl TimerHost for RecordingHost {
+        fn capture(&self) -> BoxFuture<'_, Result<(), String>> {
+            Box::pin(async move {
+                self.captures.lock().unwrap().push(self.clock.now());
+                Ok(())
+            })
+        }
+
+        fn capture_failed(&self, error: String) {
+            panic!("capture failed: {}", error);
+        }
+
+        fn session_length_warning(&self, _stops_at: DateTime<Utc>) {
+            self.record("warning");
+        }
+
+        fn auto_stop(&self) -> BoxFuture<'_, Result<(), AppError>> {
+            self.record("auto_stop");
+            Box::pin(async { Ok(()) })
+        }
+
+        fn split_session(&self, _at: DateTime<Utc>) -> BoxFuture<'_, Result<(), AppError>> {
+            self.record("split");
+            Box::pin(async { Ok(()) })
+        }
+
+        fn clock_jumped(&self, jump: ClockJump) -> BoxFuture<'_, Result<(), AppError>> {
+            self.jumps.lock().unwrap().push(jump);
+            Box::pin(async { Ok(()) })
+        }
+
+        fn break_started(&self) -> BoxFuture<'_, Result<(), AppError>> {
+            self.record("break");
+            Box::pin(async { Ok(()) })
+        }
+
+        fn break_ended(&self) -> BoxFuture<'_, Result<(), AppError>> {
+            if self.refuse_break_end.load(std::sync::atomic::Ordering::Relaxed) {
+                return Box::pin(async { Err(AppError::NotPaused) });
+            }
+            self.record("work");
+            Box::pin(async { Ok(()) })
+        }
+
+        fn break_reminder(&self, _worked: chrono::Duration) {
+            self.record("nudge");
+        }
+
+        fn pomodoro_changed(&self, _state: Option<PomodoroState>) {}
+
+        fn schedule_ended(&self, at_end: ScheduleEnd) -> BoxFuture<'_, Result<(), AppError>> {
+            self.record(match at_end {
+                ScheduleEnd::Pause => "schedule_pause",
+                ScheduleEnd::Stop => "schedule_stop",
+            });
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    struct Harness {
+        clock: Arc<MockClock>,
+        host: Arc<RecordingHost>,
+        tx: mpsc::Sender<TimerCommand>,
+        task: tokio::task::JoinHandle<()>,
+    }
+
+    fn no_limits(started_at: DateTime<Utc>) -> SessionLimits {
+        SessionLimits {
+            started_at,
+            max_length: None,
+            warn_before: chrono::Duration::zero(),
+            timezone: Tz::UTC,
+            schedule: None,
+        }
+    }
+
+    fn no_breaks() -> BreakTracker {
+        BreakTracker::new(None, None)
+    }
+
+    fn start(
+        start_wall: DateTime<Utc>,
+        scheduler: SlotScheduler,
+        limits: SessionLimits,
+        breaks: BreakTracker,
+        seed: u64,
+    ) -> Harness {
+        let clock = Arc::new(MockClock::new(start_wall));
+        let host = Arc::new(RecordingHost {
+            clock: Arc::clone(&clock),
+            captures: Default::default(),
+            events: Default::default(),
+            jumps: Default::default(),
+            refuse_break_end: Default::default(),
+        });
+        let (tx, rx) = mpsc::channel(1);
+        let task = tokio::spawn(timer_task(
+            host.clone(),
+            clock.clone(),
+            seeded_rng(seed),
+            scheduler,
+            limits,
+            breaks,
+            rx,
+        ));
+        Harness { clock, host, tx, task }
+    }
+
+    async fn advance(minutes: u64) {
+        tokio::time::sleep(Duration::from_secs(minutes * 60)).await;
+    }
+
+    // Monday 6 May 2024, 9:00 UTC
+    fn nine() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 6, 9, 0, 0).unwrap()
+    }
+
+    fn minutes(m: i64) -> DateTime<Utc> {
+        nine() + chrono::Duration::minutes(m)
+    }
+
+    // Stops the task by closing its channel and returns the captures it took
+    async fn finish(h: Harness) -> Vec<DateTime<Utc>> {
+        drop(h.tx);
+        h.task.await.unwrap();
+        let captures = h.host.captures.lock().unwrap().clone();
+        captures
+    }
+
+    // An hour of captures, 1 to 3 per 10 minutes, from the given seed
+    async fn hour_of_captures(seed: u64) -> Vec<DateTime<Utc>> {
+        let h = start(nine(), SlotScheduler::new(10, 1, 3), no_limits(nine()), no_breaks(), seed);
+        advance(60).await;
+        finish(h).await
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn running_timer_captures_the_planned_count_per_slot() {
+        let h = start(nine(), SlotScheduler::new(10, 2, 2), no_limits(nine()), no_breaks(), 1);
+        advance(20).await;
+        assert_eq!(h.host.captures.lock().unwrap().len(), 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pausing_takes_effect_while_waiting_for_a_capture() {
+        let h = start(nine(), SlotScheduler::new(10, 2, 2), no_limits(nine()), no_breaks(), 1);
+        advance(20).await;
+        h.tx.send(TimerCommand::Pause).await.unwrap();
+        advance(30).await;
+        assert!(finish(h).await.iter().all(|t| *t < minutes(20)), "captured while paused");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resuming_captures_again() {
+        let h = start(nine(), SlotScheduler::new(10, 2, 2), no_limits(nine()), no_breaks(), 1);
+        h.tx.send(TimerCommand::Pause).await.unwrap();
+        advance(30).await;
+        h.tx.send(TimerCommand::Resume).await.unwrap();
+        advance(10).await;
+        assert!(finish(h).await.iter().any(|t| *t >= minutes(30)), "no captures after resume");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stop_ends_the_task() {
+        let h = start(nine(), SlotScheduler::new(10, 2, 2), no_limits(nine()), no_breaks(), 1);
+        h.tx.send(TimerCommand::Stop).await.unwrap();
+        h.task.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn captures_are_spread_over_slots() {
+        let captures = hour_of_captures(42).await;
+        let scheduler = SlotScheduler::new(10, 1, 3);
+        for slot in 0..6 {
+            let in_slot = captures.iter().filter(|t| scheduler.slot_start(**t) == minutes(slot * 10)).count();
+            assert!((1..=3).contains(&in_slot), "slot {} had {} captures", slot, in_slot);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_same_seed_gives_the_same_captures() {
+        let first = hour_of_captures(42).await;
+        assert_eq!(first, hour_of_captures(42).await);
+    }
+
+    // 23:30 in Berlin
+    fn evening() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 6, 21, 30, 0).unwrap()
+    }
+
+    // An evening run that stops after an hour, warning 10 minutes before
+    fn hour_long(timezone: Tz) -> SessionLimits {
+        SessionLimits {
+            max_length: Some(chrono::Duration::minutes(60)),
+            warn_before: chrono::Duration::minutes(10),
+            timezone,
+            ..no_limits(evening())
+        }
+    }
+
+    fn events(host: &RecordingHost) -> Vec<(&'static str, DateTime<Utc>)> {
+        host.events.lock().unwrap().clone()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn splits_at_local_midnight() {
+        let limits = SessionLimits { timezone: "Europe/Berlin".parse().unwrap(), ..no_limits(evening()) };
+        let h = start(evening(), SlotScheduler::new(10, 1, 1), limits, no_breaks(), 3);
+        advance(40).await;
+        assert_eq!(events(&h.host), vec![("split", evening() + chrono::Duration::minutes(30))]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn warns_before_the_maximum_length() {
+        let h = start(evening(), SlotScheduler::new(10, 1, 1), hour_long(Tz::UTC), no_breaks(), 3);
+        advance(55).await;
+        assert_eq!(events(&h.host), vec![("warning", evening() + chrono::Duration::minutes(50))]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_at_the_maximum_length() {
+        let h = start(evening(), SlotScheduler::new(10, 1, 1), hour_long(Tz::UTC), no_breaks(), 3);
+        h.task.await.unwrap();
+        assert_eq!(events(&h.host).last(), Some(&("auto_stop", evening() + chrono::Duration::minutes(60))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn limits_apply_while_paused() {
+        let limits = hour_long("Europe/Berlin".parse().unwrap());
+        let h = start(evening(), SlotScheduler::new(10, 1, 1), limits, no_breaks(), 3);
+        h.tx.send(TimerCommand::Pause).await.unwrap();
+        h.task.await.unwrap();
+
+        let at = |m: i64| evening() + chrono::Duration::minutes(m);
+        assert_eq!(events(&h.host), vec![("split", at(30)), ("warning", at(50)), ("auto_stop", at(60))]);
+        assert!(h.host.captures.lock().unwrap().is_empty());
+    }
+
+    // Runs five minutes, then the clock jumps by `by` and it runs on for 20 minutes
+    async fn jumped(by: chrono::Duration) -> (Vec<ClockJump>, Vec<DateTime<Utc>>) {
+        let h = start(nine(), SlotScheduler::new(10, 1, 1), no_limits(nine()), no_breaks(), 5);
+        advance(5).await;
+        h.clock.jump(by);
+        advance(20).await;
+        let jumps = h.host.jumps.lock().unwrap().clone();
+        (jumps, finish(h).await)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reports_a_suspend_as_a_forward_jump() {
+        let (jumps, _) = jumped(chrono::Duration::hours(2)).await;
+        assert_eq!(jumps.len(), 1);
+        assert!(!jumps[0].is_backwards());
+        assert_eq!(jumps[0].to - jumps[0].from, chrono::Duration::hours(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn notices_a_jump_within_one_check_interval() {
+        let (jumps, _) = jumped(chrono::Duration::hours(2)).await;
+        assert!(jumps[0].from - minutes(5) <= CLOCK_CHECK_INTERVAL);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reports_a_clock_set_back_as_a_backward_jump() {
+        let (jumps, _) = jumped(-chrono::Duration::hours(1)).await;
+        assert_eq!(jumps.len(), 1);
+        assert!(jumps[0].is_backwards());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn captures_nothing_during_a_suspend() {
+        let (jumps, captures) = jumped(chrono::Duration::hours(2)).await;
+        assert!(captures.iter().all(|t| *t <= jumps[0].from || *t >= jumps[0].to), "captured during the suspend");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn captures_carry_on_after_the_clock_is_set_back() {
+        let (_, captures) = jumped(-chrono::Duration::hours(1)).await;
+        assert!(captures.iter().any(|t| *t < nine()), "no captures after set back");
+    }
+
+    // Pomodoro with two 25 minute intervals per long break, paused by the user for 3 minutes
+    // in the first interval
+    async fn pomodoro_with_a_pause() -> Harness {
+        let pomodoro = PomodoroSettings {
+            work_minutes: 25,
+            short_break_minutes: 5,
+            long_break_minutes: 15,
+            work_intervals_per_long_break: 2,
+        };
+        let h = start(nine(), SlotScheduler::new(10, 1, 1), no_limits(nine()), BreakTracker::new(Some(pomodoro), None), 7);
+        advance(10).await;
+        h.tx.send(TimerCommand::Pause).await.unwrap();
+        advance(3).await;
+        h.tx.send(TimerCommand::Resume).await.unwrap();
+        advance(80).await;
+        h
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pomodoro_breaks_follow_active_time() {
+        let h = pomodoro_with_a_pause().await;
+        assert_eq!(
+            events(&h.host),
+            vec![("break", minutes(28)), ("work", minutes(33)), ("break", minutes(58)), ("work", minutes(73))]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn captures_nothing_during_pomodoro_breaks() {
+        let captures = finish(pomodoro_with_a_pause().await).await;
+        assert!(captures.iter().all(|t| !(minutes(28)..minutes(33)).contains(t) && !(minutes(58)..minutes(73)).contains(t)));
+    }
+
+    fn nudging_every_half_hour(seed: u64) -> Harness {
+        let breaks = BreakTracker::new(None, Some(chrono::Duration::minutes(30)));
+        start(nine(), SlotScheduler::new(10, 1, 1), no_limits(nine()), breaks, seed)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn nudges_after_every_30_active_minutes() {
+        let h = nudging_every_half_hour(8);
+        advance(65).await;
+        assert_eq!(events(&h.host), vec![("nudge", minutes(30)), ("nudge", minutes(60))]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_short_pause_is_not_a_break() {
+        let h = nudging_every_half_hour(8);
+        advance(20).await;
+        h.tx.send(TimerCommand::Pause).await.unwrap();
+        advance(2).await;
+        h.tx.send(TimerCommand::Resume).await.unwrap();
+        advance(15).await;
+        assert_eq!(events(&h.host), vec![("nudge", minutes(32))]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_long_pause_is_a_break() {
+        let h = nudging_every_half_hour(8);
+        advance(20).await;
+        h.tx.send(TimerCommand::Pause).await.unwrap();
+        advance(10).await;
+        h.tx.send(TimerCommand::Resume).await.unwrap();
+        advance(31).await;
+        assert_eq!(events(&h.host), vec![("nudge", minutes(60))]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stays_paused_when_the_session_cant_resume_after_a_break() {
+        let pomodoro = PomodoroSettings { work_minutes: 25, short_break_minutes: 5, ..Default::default() };
+        let h = start(nine(), SlotScheduler::new(10, 1, 1), no_limits(nine()), BreakTracker::new(Some(pomodoro), None), 7);
+        h.host.refuse_break_end.store(true, std::sync::atomic::Ordering::Relaxed);
+        advance(60).await;
+        assert!(finish(h).await.iter().all(|t| *t < minutes(25)), "captured after the break");
+    }
+
+    // Works 9:00 to 9:30 and 10:00 to 10:30 on Mondays, pausing or stopping at the end of each window
+    fn scheduled(at_end: &str, seed: u64) -> Harness {
+        let schedule: WorkSchedule = serde_json::from_value(serde_json::json!({
+            "timezone": "UTC",
+            "weekly": {"Mon": [{"start": "09:00", "end": "09:30"}, {"start": "10:00", "end": "10:30"}]},
+            "at_end": at_end,
+        }))
+        .unwrap();
+        let limits = SessionLimits { schedule: Some(schedule), ..no_limits(nine()) };
+        start(nine(), SlotScheduler::new(10, 1, 1), limits, no_breaks(), seed)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pauses_at_the_end_of_the_schedule_window() {
+        let h = scheduled("pause", 9);
+        advance(45).await;
+        assert_eq!(events(&h.host), vec![("schedule_pause", minutes(30))]);
+        assert!(finish(h).await.iter().all(|t| *t < minutes(30)), "captured after the window");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resumed_between_windows_runs_until_the_next_window_ends() {
+        let h = scheduled("pause", 9);
+        advance(45).await;
+        // The app warned about resuming outside the hours
+        h.tx.send(TimerCommand::Resume).await.unwrap();
+        advance(60).await;
+        assert_eq!(events(&h.host), vec![("schedule_pause", minutes(30)), ("schedule_pause", minutes(90))]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_at_the_end_of_the_schedule_window() {
+        let h = scheduled("stop", 10);
+        h.task.await.unwrap();
+        assert_eq!(events(&h.host), vec![("schedule_stop", minutes(30))]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn elapsed_time_follows_the_clock_while_running_or_paused() {
+        let clock = MockClock::new(nine());
+        advance(5).await;
+        let none = chrono::Duration::zero();
+        assert_eq!(elapsed_seconds(&TimerStatus::Running, Some(nine()), none, clock.now()), 300);
+        assert_eq!(elapsed_seconds(&TimerStatus::Paused, Some(nine()), none, clock.now()), 300);
+    }
+
+    #[test]
+    fn a_stopped_timer_has_no_elapsed_time() {
+        let none = chrono::Duration::zero();
+        assert_eq!(elapsed_seconds(&TimerStatus::Stopped, Some(nine()), none, minutes(5)), 0);
+    }
+
+    #[test]
+    fn paused_time_is_not_elapsed() {
+        // A suspend moves the wall clock but isn't worked time
+        let paused = chrono::Duration::minutes(30) + chrono::Duration::seconds(20);
+        assert_eq!(elapsed_seconds(&TimerStatus::Running, Some(nine()), paused, minutes(35)), 280);
+    }
+}
@@ -0,0 +1,430 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{PgExecutor, Pool, Postgres};
+use std::sync::Arc;
+use tokio::sync::{Mutex, MutexGuard};
+use uuid::Uuid;
+
+use crate::clock::{BoxFuture, Clock};
+use crate::errors::AppError;
+use crate::timesheets;
+
+// Represents the current status of the timer
+#[derive(Clone, Copy, Serialize, Debug, PartialEq)]
+pub enum TimerStatus {
+    Stopped,
+    Running,
+    Paused,
+}
+
+impl TimerStatus {
+    /// Value stored in the transition log.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TimerStatus::Stopped => "stopped",
+            TimerStatus::Running => "running",
+            TimerStatus::Paused => "paused",
+        }
+    }
+
+    fn can_become(self, to: TimerStatus) -> bool {
+        matches!(
+            (self, to),
+            (TimerStatus::Stopped, TimerStatus::Running)
+                | (TimerStatus::Running, TimerStatus::Paused)
+                | (TimerStatus::Paused, TimerStatus::Running)
+                | (TimerStatus::Running, TimerStatus::Stopped)
+                | (TimerStatus::Paused, TimerStatus::Stopped)
+        )
+    }
+}
+
+/// Why the timer changed state.
+#[derive(Clone, Copy, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TransitionReason {
+    User,
+    Schedule,
+    CrashRecovery,
+    MaxSessionLength,
+    Break,
+    HourLimit,
+}
+
+impl TransitionReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TransitionReason::User => "user",
+            TransitionReason::Schedule => "schedule",
+            TransitionReason::CrashRecovery => "crash_recovery",
+            TransitionReason::MaxSessionLength => "max_session_length",
+            TransitionReason::Break => "break",
+            TransitionReason::HourLimit => "hour_limit",
+        }
+    }
+}
+
+/// One accepted status change, as written to `timer_transitions`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transition {
+    pub session_id: Option<Uuid>,
+    pub from: TimerStatus,
+    pub to: TimerStatus,
+    pub reason: TransitionReason,
+    pub at: DateTime<Utc>,
+}
+
+/// Receives every accepted transition: the app logs it to the database and tells the frontend.
+pub trait TransitionObserver: Send + Sync {
+    fn persist(&self, transition: &Transition) -> BoxFuture<'_, Result<(), AppError>>;
+    fn emit(&self, status: TimerStatus);
+}
+
+/// Owns the timer status. Every change goes through `transition`, which rejects moves the
+/// timer can't make (e.g. pausing a stopped timer), logs the change and notifies the frontend.
+pub struct TimerStateMachine {
+    status: Mutex<TimerStatus>,
+    observer: Arc<dyn TransitionObserver>,
+    clock: Arc<dyn Clock>,
+}
+
+/// Holds the state machine locked, so a command can check the status, do its work and
+/// transition without another command changing the status in between.
+pub struct TimerStateGuard<'a> {
+    status: MutexGuard<'a, TimerStatus>,
+    machine: &'a TimerStateMachine,
+}
+
+impl TimerStateMachine {
+    pub fn new(observer: Arc<dyn TransitionObserver>, clock: Arc<dyn Clock>) -> Self {
+        TimerStateMachine {
+            status: Mutex::new(TimerStatus::Stopped),
+            observer,
+            clock,
+        }
+    }
+
+    pub async fn status(&self) -> TimerStatus {
+        *self.status.lock().await
+    }
+
+    pub async fn lock(&self) -> TimerStateGuard<'_> {
+        TimerStateGuard {
+            status: self.status.lock().await,
+            machine: self,
+        }
+    }
+}
+
+impl TimerStateGuard<'_> {
+    pub fn status(&self) -> TimerStatus {
+        *self.status
+    }
+
+    pub async fn transition(
+        &mut self,
+        to: TimerStatus,
+        reason: TransitionReason,
+        session_id: Option<Uuid>,
+    ) -> Result<Transition, AppError> {
+        let from = *self.status;
+        if !from.can_become(to) {
+            // Only running and paused timers stop or pause, and only from running can it not start
+            return Err(match to {
+                TimerStatus::Running => AppError::AlreadyRunning,
+                TimerStatus::Paused => AppError::NotRunning,
+                TimerStatus::Stopped => AppError::AlreadyStopped,
+            });
+        }
+        let transition = Transition {
+            session_id,
+            from,
+            to,
+            reason,
+            at: self.machine.clock.now(),
+        };
+        *self.status = to;
+        println!("Timer {} -> {} ({}).", from.as_str(), to.as_str(), reason.as_str());
+
+        // A failed log write must not leave the timer in a state the user didn't ask for
+        if let Err(e) = self.machine.observer.persist(&transition).await {
+            eprintln!("Warning: failed to log timer transition: {}", e);
+            e.report();
+        }
+        self.machine.observer.emit(to);
+        Ok(transition)
+    }
+}
+
+/// Appends a transition to the `timer_transitions` log.
+pub async fn persist_transition<'e>(executor: impl PgExecutor<'e>, transition: &Transition) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        INSERT INTO timer_transitions (session_id, from_status, to_status, reason, occurred_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(transition.session_id)
+    .bind(transition.from.as_str())
+    .bind(transition.to.as_str())
+    .bind(transition.reason.as_str())
+    .bind(transition.at)
+    .execute(executor)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to insert timer transition: {}", e)))?;
+    Ok(())
+}
+
+/// Closes the team member's sessions left open by a crash or forced quit. Each one ends at
+/// its last screenshot (or its start if there is none) and gets a crash-recovery transition,
+/// all in one transaction per session so a failure leaves it open to recover next time.
+/// Activity counts of the interrupted session are lost, since they only lived in memory.
+/// Other team members' open sessions are left alone: they may still be running elsewhere.
+/// So are sessions in a week whose timesheet is locked, until a manager reopens it.
+pub async fn recover_open_sessions(
+    pool: &Pool<Postgres>,
+    clock: &dyn Clock,
+    team_member_id: Option<i32>,
+) -> Result<u64, AppError> {
+    let open: Vec<(Uuid, DateTime<Utc>, DateTime<Utc>)> = sqlx::query_as(
+        r#"
+        SELECT s.id, s.start_time,
+               COALESCE((SELECT MAX(capture_time) FROM screenshots WHERE session_id = s.id), s.start_time)
+        FROM sessions s
+        WHERE s.end_time IS NULL AND s.team_member_id IS NOT DISTINCT FROM $1
+        "#,
+    )
+    .bind(team_member_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to load open sessions: {}", e)))?;
+
+    let mut recovered = 0;
+    for (session_id, start_time, end_time) in open {
+        let mut tx = pool
+            .begin()
+            .await
+            .map_err(|e| AppError::Db(format!("Failed to start recovering session {}: {}", session_id, e)))?;
+        if let Some(owner) = team_member_id {
+            match timesheets::ensure_unlocked(&mut *tx, owner, start_time, end_time).await {
+                Ok(()) => {}
+                Err(AppError::TimesheetLocked(message)) => {
+                    eprintln!("Leaving session {} open: {}", session_id, message);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        sqlx::query("UPDATE sessions SET end_time = $2 WHERE id = $1")
+            .bind(session_id)
+            .bind(end_time)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| AppError::Db(format!("Failed to close session {}: {}", session_id, e)))?;
+
+        // Its open task segment ends with it
+        sqlx::query("UPDATE task_segments SET end_time = $2 WHERE session_id = $1 AND end_time IS NULL")
+            .bind(session_id)
+            .bind(end_time)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| AppError::Db(format!("Failed to close open task segments: {}", e)))?;
+
+        // As does an open pause, which may have begun after the last screenshot
+        let closed_pauses = sqlx::query(
+            "UPDATE pause_segments SET end_time = GREATEST(start_time, $2) WHERE session_id = $1 AND end_time IS NULL",
+        )
+        .bind(session_id)
+        .bind(end_time)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::Db(format!("Failed to close open pauses: {}", e)))?
+        .rows_affected();
+
+        let transition = Transition {
+            session_id: Some(session_id),
+            from: if closed_pauses > 0 { TimerStatus::Paused } else { TimerStatus::Running },
+            to: TimerStatus::Stopped,
+            reason: TransitionReason::CrashRecovery,
+            at: clock.now(),
+        };
+        persist_transition(&mut *tx, &transition).await?;
+        tx.commit()
+            .await
+            .map_err(|e| AppError::Db(format!("Failed to commit recovery of session {}: {}", session_id, e)))?;
+        println!("Closed session {} left open by a crash, ending at {}.", session_id, end_time);
+        recovered += 1;
+    }
+    Ok(recovered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::identity::TeamMemberRole;
+    use crate::{pauses, test_db};
+    use chrono::{Duration, TimeZone};
+
+    #[derive(Default)]
+    struct RecordingObserver {
+        persisted: std::sync::Mutex<Vec<Transition>>,
+        emitted: std::sync::Mutex<Vec<TimerStatus>>,
+    }
+
+    impl TransitionObserver for RecordingObserver {
+        fn persist(&self, transition: &Transition) -> BoxFuture<'_, Result<(), AppError>> {
+            self.persisted.lock().unwrap().push(transition.clone());
+            Box::pin(async { Ok(()) })
+        }
+
+        fn emit(&self, status: TimerStatus) {
+            self.emitted.lock().unwrap().push(status);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn validates_logs_and_emits_each_transition() {
+        let observer = Arc::new(RecordingObserver::default());
+        let clock = Arc::new(MockClock::new(Utc.with_ymd_and_hms(2024, 5, 6, 9, 0, 0).unwrap()));
+        let machine = TimerStateMachine::new(observer.clone(), clock);
+        let session = Some(Uuid::new_v4());
+        let mut timer = machine.lock().await;
+
+        assert_eq!(timer.transition(TimerStatus::Paused, TransitionReason::User, None).await, Err(AppError::NotRunning));
+        timer.transition(TimerStatus::Running, TransitionReason::User, session).await.unwrap();
+        timer.transition(TimerStatus::Paused, TransitionReason::Break, session).await.unwrap();
+        assert_eq!(timer.transition(TimerStatus::Paused, TransitionReason::User, session).await, Err(AppError::NotRunning));
+        timer.transition(TimerStatus::Running, TransitionReason::User, session).await.unwrap();
+        timer.transition(TimerStatus::Stopped, TransitionReason::Schedule, session).await.unwrap();
+        drop(timer);
+
+        assert_eq!(machine.status().await, TimerStatus::Stopped);
+        assert_eq!(
+            *observer.emitted.lock().unwrap(),
+            vec![TimerStatus::Running, TimerStatus::Paused, TimerStatus::Running, TimerStatus::Stopped]
+        );
+        let reasons: Vec<_> = observer.persisted.lock().unwrap().iter().map(|t| t.reason).collect();
+        assert_eq!(
+            reasons,
+            vec![TransitionReason::User, TransitionReason::Break, TransitionReason::User, TransitionReason::Schedule]
+        );
+    }
+
+    async fn end_time(pool: &Pool<Postgres>, session_id: Uuid) -> Option<DateTime<Utc>> {
+        sqlx::query_scalar("SELECT end_time FROM sessions WHERE id = $1")
+            .bind(session_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    // Needs Postgres, see `test_db`
+    #[tokio::test]
+    #[ignore]
+    async fn recovery_ends_a_session_at_its_last_screenshot() {
+        let pool = test_db::pool().await;
+        let organization = test_db::organization(&pool).await;
+        let user = test_db::team_member(&pool, organization, TeamMemberRole::Employee).await;
+        let start = Utc.with_ymd_and_hms(2024, 5, 6, 9, 0, 0).unwrap();
+        let session = test_db::session(&pool, user.team_member_id, start, None).await;
+        test_db::screenshot(&pool, session, start + Duration::minutes(20)).await;
+        let clock = MockClock::new(start + Duration::hours(5));
+
+        let recovered = recover_open_sessions(&pool, &clock, Some(user.team_member_id)).await;
+
+        assert_eq!(recovered, Ok(1));
+        assert_eq!(end_time(&pool, session).await, Some(start + Duration::minutes(20)));
+        let reason: String = sqlx::query_scalar("SELECT reason FROM timer_transitions WHERE session_id = $1")
+            .bind(session)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(reason, "crash_recovery");
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn recovery_closes_a_pause_begun_after_the_last_screenshot_where_it_began() {
+        let pool = test_db::pool().await;
+        let organization = test_db::organization(&pool).await;
+        let user = test_db::team_member(&pool, organization, TeamMemberRole::Employee).await;
+        let start = Utc.with_ymd_and_hms(2024, 5, 6, 9, 0, 0).unwrap();
+        let session = test_db::session(&pool, user.team_member_id, start, None).await;
+        test_db::screenshot(&pool, session, start + Duration::minutes(20)).await;
+        let mut conn = pool.acquire().await.unwrap();
+        let pause = pauses::open_pause(&mut conn, session, pauses::PauseReason::User, start + Duration::minutes(30))
+            .await
+            .unwrap();
+        let clock = MockClock::new(start + Duration::hours(5));
+
+        recover_open_sessions(&pool, &clock, Some(user.team_member_id)).await.unwrap();
+
+        let pause_end: Option<DateTime<Utc>> = sqlx::query_scalar("SELECT end_time FROM pause_segments WHERE id = $1")
+            .bind(pause.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(pause_end, Some(pause.start_time));
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn recovery_logs_a_session_left_paused_as_paused() {
+        let pool = test_db::pool().await;
+        let organization = test_db::organization(&pool).await;
+        let user = test_db::team_member(&pool, organization, TeamMemberRole::Employee).await;
+        let start = Utc.with_ymd_and_hms(2024, 5, 6, 9, 0, 0).unwrap();
+        let session = test_db::session(&pool, user.team_member_id, start, None).await;
+        let mut conn = pool.acquire().await.unwrap();
+        pauses::open_pause(&mut conn, session, pauses::PauseReason::User, start + Duration::minutes(30))
+            .await
+            .unwrap();
+        let clock = MockClock::new(start + Duration::hours(5));
+
+        recover_open_sessions(&pool, &clock, Some(user.team_member_id)).await.unwrap();
+
+        let from: String = sqlx::query_scalar("SELECT from_status FROM timer_transitions WHERE session_id = $1")
+            .bind(session)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(from, "paused");
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn recovery_leaves_other_team_members_sessions_open() {
+        let pool = test_db::pool().await;
+        let organization = test_db::organization(&pool).await;
+        let user = test_db::team_member(&pool, organization, TeamMemberRole::Employee).await;
+        let colleague = test_db::team_member(&pool, organization, TeamMemberRole::Employee).await;
+        let start = Utc.with_ymd_and_hms(2024, 5, 6, 9, 0, 0).unwrap();
+        let theirs = test_db::session(&pool, colleague.team_member_id, start, None).await;
+        let clock = MockClock::new(start + Duration::hours(5));
+
+        let recovered = recover_open_sessions(&pool, &clock, Some(user.team_member_id)).await;
+
+        assert_eq!(recovered, Ok(0));
+        assert_eq!(end_time(&pool, theirs).await, None);
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn recovery_leaves_a_session_in_a_locked_week_open() {
+        let pool = test_db::pool().await;
+        let organization = test_db::organization(&pool).await;
+        let user = test_db::team_member(&pool, organization, TeamMemberRole::Employee).await;
+        let start = Utc.with_ymd_and_hms(2024, 5, 6, 9, 0, 0).unwrap();
+        let session = test_db::session(&pool, user.team_member_id, start, None).await;
+        let rounding = timesheets::Rounding { minutes: 0, mode: timesheets::RoundingMode::Nearest };
+        let after_the_week = start + Duration::days(14);
+        timesheets::submit(&pool, &user, start.date_naive(), chrono_tz::UTC, rounding, after_the_week).await.unwrap();
+        let clock = MockClock::new(after_the_week);
+
+        let recovered = recover_open_sessions(&pool, &clock, Some(user.team_member_id)).await;
+
+        assert_eq!(recovered, Ok(0));
+        assert_eq!(end_time(&pool, session).await, None);
+    }
+}
@@ -0,0 +1,979 @@
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{PgConnection, PgExecutor, Pool, Postgres, Row};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+use crate::authorization::{authorize_team_member, require_manager_of};
+use crate::errors::AppError;
+use crate::identity::{CurrentUser, TeamMemberRole};
+use crate::workday;
+
+/// Which way day and project totals are rounded to the increment.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundingMode {
+    #[default]
+    Nearest,
+    Up,
+    Down,
+}
+
+/// How a timesheet's totals are rounded, from the organization's policy.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Rounding {
+    /// Increment in minutes, e.g. 6 or 15; 0 rounds to whole minutes
+    pub minutes: u32,
+    pub mode: RoundingMode,
+}
+
+impl Rounding {
+    /// `seconds` in whole minutes, rounded to the increment.
+    pub fn apply(&self, seconds: i64) -> i64 {
+        let minutes = i64::from(self.minutes.max(1));
+        let step = minutes * 60;
+        let seconds = seconds.max(0);
+        let steps = match self.mode {
+            RoundingMode::Nearest => (seconds + step / 2) / step,
+            RoundingMode::Up => (seconds + step - 1) / step,
+            RoundingMode::Down => seconds / step,
+        };
+        steps * minutes
+    }
+}
+
+/// Where a timesheet is in its review. Submitting locks the period until a manager rejects or
+/// reopens it; approving keeps it locked.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TimesheetStatus {
+    /// Not submitted yet
+    Open,
+    Submitted,
+    Approved,
+    /// Sent back with a comment, to be corrected and submitted again
+    Rejected,
+    Reopened,
+}
+
+impl TimesheetStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TimesheetStatus::Open => "open",
+            TimesheetStatus::Submitted => "submitted",
+            TimesheetStatus::Approved => "approved",
+            TimesheetStatus::Rejected => "rejected",
+            TimesheetStatus::Reopened => "reopened",
+        }
+    }
+
+    fn parse(value: &str) -> Result<Self, AppError> {
+        match value {
+            "open" => Ok(TimesheetStatus::Open),
+            "submitted" => Ok(TimesheetStatus::Submitted),
+            "approved" => Ok(TimesheetStatus::Approved),
+            "rejected" => Ok(TimesheetStatus::Rejected),
+            "reopened" => Ok(TimesheetStatus::Reopened),
+            other => Err(AppError::Db(format!("Unknown timesheet status '{}'", other))),
+        }
+    }
+
+    /// Whether the week's time can't be changed.
+    pub fn is_locked(self) -> bool {
+        matches!(self, TimesheetStatus::Submitted | TimesheetStatus::Approved)
+    }
+}
+
+/// Time on one project (or none) on one day.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ProjectTotal {
+    pub project: Option<String>,
+    /// Tracked sessions minus their pauses
+    pub tracked_seconds: i64,
+    /// Approved manual entries
+    pub manual_seconds: i64,
+    /// Both together, rounded
+    pub minutes: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TimesheetDay {
+    pub date: NaiveDate,
+    pub projects: Vec<ProjectTotal>,
+    /// Sum of the rounded project totals
+    pub minutes: i64,
+}
+
+/// A team member's week, Monday to Sunday. Computed from the stored time until it is
+/// submitted; from then on the totals as submitted are kept in `timesheets`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Timesheet {
+    pub team_member_id: i32,
+    pub period_start: NaiveDate,
+    pub period_end: NaiveDate,
+    pub status: TimesheetStatus,
+    pub rounding: Rounding,
+    pub days: Vec<TimesheetDay>,
+    pub minutes: i64,
+    /// Manual entries in the period still waiting for review, which aren't counted
+    pub pending_manual_entries: i64,
+    pub submitted_at: Option<DateTime<Utc>>,
+    /// The manager who last approved, rejected or reopened the timesheet, and their comment
+    pub reviewed_by: Option<i32>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub review_comment: Option<String>,
+}
+
+/// One step of a timesheet's review: the status it moved to, who moved it and why. Kept in
+/// `timesheet_events`; steps taken by a manager are also sent to the team member.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct TimesheetEvent {
+    pub id: Uuid,
+    pub team_member_id: i32,
+    pub period_start: NaiveDate,
+    pub status: TimesheetStatus,
+    pub actor: i32,
+    pub comment: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+/// A stored timesheet in a list, without its days.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct TimesheetSummary {
+    pub team_member_id: i32,
+    pub email: String,
+    pub name: Option<String>,
+    pub period_start: NaiveDate,
+    pub status: TimesheetStatus,
+    pub minutes: i64,
+    pub submitted_at: DateTime<Utc>,
+    pub reviewed_by: Option<i32>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub review_comment: Option<String>,
+}
+
+/// Worked time on a local day, from a task segment, a session or a manual entry.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeRecord {
+    pub date: NaiveDate,
+    pub project: Option<String>,
+    pub seconds: i64,
+    pub manual: bool,
+}
+
+/// Totals per day of the week starting `period_start` and per project within each day; every
+/// day is listed, worked or not. Each day's project totals are rounded on their own.
+pub fn day_totals(period_start: NaiveDate, records: &[TimeRecord], rounding: Rounding) -> Vec<TimesheetDay> {
+    let mut by_day: BTreeMap<NaiveDate, BTreeMap<Option<String>, (i64, i64)>> = (0..7)
+        .map(|offset| (period_start + Days::new(offset), BTreeMap::new()))
+        .collect();
+    for record in records {
+        let Some(projects) = by_day.get_mut(&record.date) else {
+            continue;
+        };
+        let (tracked, manual) = projects.entry(record.project.clone()).or_default();
+        if record.manual {
+            *manual += record.seconds;
+        } else {
+            *tracked += record.seconds;
+        }
+    }
+    by_day
+        .into_iter()
+        .map(|(date, projects)| {
+            let projects: Vec<ProjectTotal> = projects
+                .into_iter()
+                .map(|(project, (tracked_seconds, manual_seconds))| ProjectTotal {
+                    project,
+                    tracked_seconds,
+                    manual_seconds,
+                    minutes: rounding.apply(tracked_seconds + manual_seconds),
+                })
+                .collect();
+            TimesheetDay { date, minutes: projects.iter().map(|total| total.minutes).sum(), projects }
+        })
+        .collect()
+}
+
+// The local week `date` falls in, as dates and as the UTC instants it starts and ends at
+fn period(date: NaiveDate, tz: Tz) -> (NaiveDate, DateTime<Utc>, DateTime<Utc>) {
+    let start = workday::week_start(date);
+    (start, workday::day_start(start, tz), workday::day_start(start + Days::new(7), tz))
+}
+
+// The team member's finished time in [from, to), and how many manual entries there are still pending
+async fn load_records(
+    pool: &Pool<Postgres>,
+    team_member_id: i32,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    tz: Tz,
+) -> Result<(Vec<TimeRecord>, i64), AppError> {
+    // Task segments carry the project; sessions from before segments existed count as a whole
+    let tracked = sqlx::query(
+        r#"
+        SELECT s.start_time, NULLIF(btrim(ts.project), '') AS project,
+               EXTRACT(EPOCH FROM (ts.end_time - ts.start_time))::float8 - COALESCE((
+                   SELECT SUM(EXTRACT(EPOCH FROM (LEAST(COALESCE(p.end_time, ts.end_time), ts.end_time) - GREATEST(p.start_time, ts.start_time))))
+                   FROM pause_segments p
+                   WHERE p.session_id = s.id AND p.start_time < ts.end_time AND COALESCE(p.end_time, ts.end_time) > ts.start_time
+               ), 0)::float8 AS seconds
+        FROM sessions s
+        JOIN task_segments ts ON ts.session_id = s.id AND ts.end_time IS NOT NULL
+        WHERE s.team_member_id = $1 AND s.start_time >= $2 AND s.start_time < $3 AND s.end_time IS NOT NULL
+        UNION ALL
+        SELECT s.start_time, NULL AS project,
+               EXTRACT(EPOCH FROM (s.end_time - s.start_time))::float8 - COALESCE((
+                   SELECT SUM(EXTRACT(EPOCH FROM (COALESCE(p.end_time, s.end_time) - p.start_time)))
+                   FROM pause_segments p
+                   WHERE p.session_id = s.id
+               ), 0)::float8 AS seconds
+        FROM sessions s
+        WHERE s.team_member_id = $1 AND s.start_time >= $2 AND s.start_time < $3 AND s.end_time IS NOT NULL
+          AND NOT EXISTS (SELECT 1 FROM task_segments ts WHERE ts.session_id = s.id)
+        "#,
+    )
+    .bind(team_member_id)
+    .bind(from)
+    .bind(to)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to load tracked time for the timesheet: {}", e)))?;
+
+    let manual = sqlx::query(
+        r#"
+        SELECT start_time, NULLIF(btrim(project), '') AS project,
+               EXTRACT(EPOCH FROM (end_time - start_time))::float8 AS seconds, status
+        FROM manual_time_entries
+        WHERE team_member_id = $1 AND start_time >= $2 AND start_time < $3 AND status IN ('approved', 'pending')
+        "#,
+    )
+    .bind(team_member_id)
+    .bind(from)
+    .bind(to)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to load manual entries for the timesheet: {}", e)))?;
+
+    let column_error = |column: &str, e: sqlx::Error| AppError::Db(format!("Failed to get {} column: {}", column, e));
+    let record = |row: &sqlx::postgres::PgRow, manual: bool| -> Result<TimeRecord, AppError> {
+        let start_time: DateTime<Utc> = row.try_get("start_time").map_err(|e| column_error("start_time", e))?;
+        let seconds: f64 = row.try_get("seconds").map_err(|e| column_error("seconds", e))?;
+        Ok(TimeRecord {
+            date: start_time.with_timezone(&tz).date_naive(),
+            project: row.try_get("project").map_err(|e| column_error("project", e))?,
+            seconds: seconds.max(0.0).round() as i64,
+            manual,
+        })
+    };
+    let mut records = tracked.iter().map(|row| record(row, false)).collect::<Result<Vec<_>, AppError>>()?;
+    let mut pending = 0;
+    for row in &manual {
+        let status: String = row.try_get("status").map_err(|e| column_error("status", e))?;
+        if status == "pending" {
+            pending += 1;
+        } else {
+            records.push(record(row, true)?);
+        }
+    }
+    Ok((records, pending))
+}
+
+// The stored timesheet of the period, if it has been submitted before
+async fn load_stored(pool: &Pool<Postgres>, team_member_id: i32, period_start: NaiveDate) -> Result<Option<Timesheet>, AppError> {
+    let row = sqlx::query(
+        r#"
+        SELECT status, totals::text AS totals, submitted_at, reviewed_by, reviewed_at, review_comment
+        FROM timesheets
+        WHERE team_member_id = $1 AND period_start = $2
+        "#,
+    )
+    .bind(team_member_id)
+    .bind(period_start)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to load timesheet: {}", e)))?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let column_error = |column: &str, e: sqlx::Error| AppError::Db(format!("Failed to get {} column: {}", column, e));
+    let status: String = row.try_get("status").map_err(|e| column_error("status", e))?;
+    let totals: String = row.try_get("totals").map_err(|e| column_error("totals", e))?;
+    let timesheet: Timesheet =
+        serde_json::from_str(&totals).map_err(|e| AppError::Db(format!("Invalid timesheet JSON: {}", e)))?;
+    Ok(Some(Timesheet {
+        status: TimesheetStatus::parse(&status)?,
+        submitted_at: row.try_get("submitted_at").map_err(|e| column_error("submitted_at", e))?,
+        reviewed_by: row.try_get("reviewed_by").map_err(|e| column_error("reviewed_by", e))?,
+        reviewed_at: row.try_get("reviewed_at").map_err(|e| column_error("reviewed_at", e))?,
+        review_comment: row.try_get("review_comment").map_err(|e| column_error("review_comment", e))?,
+        ..timesheet
+    }))
+}
+
+// The week's timesheet as the stored time makes it now
+async fn compute(
+    pool: &Pool<Postgres>,
+    team_member_id: i32,
+    date: NaiveDate,
+    tz: Tz,
+    rounding: Rounding,
+) -> Result<Timesheet, AppError> {
+    let (period_start, from, to) = period(date, tz);
+    let (records, pending_manual_entries) = load_records(pool, team_member_id, from, to, tz).await?;
+    let days = day_totals(period_start, &records, rounding);
+    Ok(Timesheet {
+        team_member_id,
+        period_start,
+        period_end: period_start + Days::new(6),
+        status: TimesheetStatus::Open,
+        rounding,
+        minutes: days.iter().map(|day| day.minutes).sum(),
+        days,
+        pending_manual_entries,
+        submitted_at: None,
+        reviewed_by: None,
+        reviewed_at: None,
+        review_comment: None,
+    })
+}
+
+/// The timesheet of the week `date` falls in. Team members see their own; managers those of
+/// their organization. A submitted or approved week shows the totals as submitted.
+pub async fn timesheet(
+    pool: &Pool<Postgres>,
+    user: &CurrentUser,
+    team_member_id: i32,
+    date: NaiveDate,
+    tz: Tz,
+    rounding: Rounding,
+) -> Result<Timesheet, AppError> {
+    authorize_team_member(pool, user, "view a timesheet", team_member_id).await?;
+    let stored = load_stored(pool, team_member_id, workday::week_start(date)).await?;
+    match stored {
+        Some(stored) if stored.status.is_locked() => Ok(stored),
+        Some(stored) => Ok(Timesheet {
+            status: stored.status,
+            submitted_at: stored.submitted_at,
+            reviewed_by: stored.reviewed_by,
+            reviewed_at: stored.reviewed_at,
+            review_comment: stored.review_comment,
+            ..compute(pool, team_member_id, date, tz, rounding).await?
+        }),
+        None => Ok(compute(pool, team_member_id, date, tz, rounding).await?),
+    }
+}
+
+// Adds a step to the timesheet's history. Steps the team member took themselves need no notification.
+async fn record_event(
+    conn: &mut PgConnection,
+    timesheet_id: Uuid,
+    status: TimesheetStatus,
+    actor: &CurrentUser,
+    comment: Option<&str>,
+    now: DateTime<Utc>,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        INSERT INTO timesheet_events (id, timesheet_id, status, actor, comment, occurred_at, notified_at)
+        SELECT $1, t.id, $2, $3, $4, $5, CASE WHEN t.team_member_id = $3 THEN $5 END
+        FROM timesheets t
+        WHERE t.id = $6
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(status.as_str())
+    .bind(actor.team_member_id)
+    .bind(comment)
+    .bind(now)
+    .bind(timesheet_id)
+    .execute(conn)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to record timesheet history: {}", e)))?;
+    Ok(())
+}
+
+/// Submits the user's timesheet for a week that is over, which locks the week against edits.
+/// A rejected or reopened week can be submitted again.
+pub async fn submit(
+    pool: &Pool<Postgres>,
+    user: &CurrentUser,
+    date: NaiveDate,
+    tz: Tz,
+    rounding: Rounding,
+    now: DateTime<Utc>,
+) -> Result<Timesheet, AppError> {
+    let (period_start, from, to) = period(date, tz);
+    if to > now {
+        return Err(AppError::Invalid("A week's timesheet can be submitted once the week is over.".to_string()));
+    }
+    let timesheet = Timesheet {
+        status: TimesheetStatus::Submitted,
+        submitted_at: Some(now),
+        ..compute(pool, user.team_member_id, date, tz, rounding).await?
+    };
+    if timesheet.pending_manual_entries > 0 {
+        return Err(AppError::Invalid(format!(
+            "{} manual entries of this week are still waiting for review.",
+            timesheet.pending_manual_entries
+        )));
+    }
+    let totals =
+        serde_json::to_string(&timesheet).map_err(|e| AppError::Db(format!("Failed to serialize timesheet: {}", e)))?;
+    let mut tx = pool.begin().await.map_err(|e| AppError::Db(format!("Failed to begin transaction: {}", e)))?;
+    let saved = sqlx::query(
+        r#"
+        INSERT INTO timesheets (id, team_member_id, period_start, starts_at, ends_at, status, totals, submitted_at)
+        VALUES ($1, $2, $3, $4, $5, 'submitted', $6::jsonb, $7)
+        ON CONFLICT (team_member_id, period_start) DO UPDATE
+        SET status = 'submitted', totals = EXCLUDED.totals, submitted_at = EXCLUDED.submitted_at
+        WHERE timesheets.status NOT IN ('submitted', 'approved')
+        RETURNING id, reviewed_by, reviewed_at, review_comment
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(user.team_member_id)
+    .bind(period_start)
+    .bind(from)
+    .bind(to)
+    .bind(totals)
+    .bind(now)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to submit timesheet: {}", e)))?
+    .ok_or_else(|| AppError::TimesheetLocked(format!("The timesheet for the week of {} has already been submitted.", period_start)))?;
+    let column_error = |column: &str, e: sqlx::Error| AppError::Db(format!("Failed to get {} column: {}", column, e));
+    let timesheet_id: Uuid = saved.try_get("id").map_err(|e| column_error("id", e))?;
+    record_event(&mut tx, timesheet_id, TimesheetStatus::Submitted, user, None, now).await?;
+    tx.commit().await.map_err(|e| AppError::Db(format!("Failed to commit timesheet submission: {}", e)))?;
+    println!("Timesheet for the week of {} submitted by team member {}.", period_start, user.team_member_id);
+    Ok(Timesheet {
+        reviewed_by: saved.try_get("reviewed_by").map_err(|e| column_error("reviewed_by", e))?,
+        reviewed_at: saved.try_get("reviewed_at").map_err(|e| column_error("reviewed_at", e))?,
+        review_comment: saved.try_get("review_comment").map_err(|e| column_error("review_comment", e))?,
+        ..timesheet
+    })
+}
+
+// A manager moving a team member's timesheet from one of the `from` statuses to `to`
+struct ManagerStep<'a> {
+    team_member_id: i32,
+    date: NaiveDate,
+    from: &'a [TimesheetStatus],
+    to: TimesheetStatus,
+    comment: Option<String>,
+}
+
+// Takes the step if the timesheet is in one of the `from` statuses, recording it in the history
+async fn take_manager_step(
+    pool: &Pool<Postgres>,
+    manager: &CurrentUser,
+    step: ManagerStep<'_>,
+    now: DateTime<Utc>,
+) -> Result<(), AppError> {
+    require_manager_of(pool, manager, "review or reopen a timesheet", step.team_member_id).await?;
+    let period_start = workday::week_start(step.date);
+    let comment = step.comment.map(|c| c.trim().to_string()).filter(|c| !c.is_empty());
+    let from: Vec<&str> = step.from.iter().map(|status| status.as_str()).collect();
+    let mut tx = pool.begin().await.map_err(|e| AppError::Db(format!("Failed to begin transaction: {}", e)))?;
+    let row = sqlx::query(
+        r#"
+        UPDATE timesheets SET status = $1, reviewed_by = $2, reviewed_at = $3, review_comment = $4
+        WHERE team_member_id = $5 AND period_start = $6 AND status = ANY($7)
+        RETURNING id
+        "#,
+    )
+    .bind(step.to.as_str())
+    .bind(manager.team_member_id)
+    .bind(now)
+    .bind(&comment)
+    .bind(step.team_member_id)
+    .bind(period_start)
+    .bind(&from)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to update timesheet: {}", e)))?
+    .ok_or_else(|| AppError::Invalid(format!("The timesheet for the week of {} isn't {}.", period_start, from.join(" or "))))?;
+    let timesheet_id: Uuid = row.try_get("id").map_err(|e| AppError::Db(format!("Failed to get id column: {}", e)))?;
+    record_event(&mut tx, timesheet_id, step.to, manager, comment.as_deref(), now).await?;
+    tx.commit().await.map_err(|e| AppError::Db(format!("Failed to commit timesheet update: {}", e)))?;
+    println!(
+        "Timesheet of team member {} for the week of {} {} by team member {}.",
+        step.team_member_id,
+        period_start,
+        step.to.as_str(),
+        manager.team_member_id
+    );
+    Ok(())
+}
+
+/// Approves or rejects a submitted timesheet. Managers of the team member's organization only,
+/// and never their own; a rejection needs a comment saying what to correct.
+pub async fn review(
+    pool: &Pool<Postgres>,
+    reviewer: &CurrentUser,
+    team_member_id: i32,
+    date: NaiveDate,
+    decision: TimesheetStatus,
+    comment: Option<String>,
+    now: DateTime<Utc>,
+) -> Result<(), AppError> {
+    if !matches!(decision, TimesheetStatus::Approved | TimesheetStatus::Rejected) {
+        return Err(AppError::Invalid("A review must approve or reject the timesheet.".to_string()));
+    }
+    if team_member_id == reviewer.team_member_id {
+        return Err(AppError::Invalid("You cannot review your own timesheet.".to_string()));
+    }
+    if decision == TimesheetStatus::Rejected && comment.as_deref().is_none_or(|c| c.trim().is_empty()) {
+        return Err(AppError::Invalid("Please say why the timesheet is rejected.".to_string()));
+    }
+    let step = ManagerStep { team_member_id, date, from: &[TimesheetStatus::Submitted], to: decision, comment };
+    take_manager_step(pool, reviewer, step, now).await
+}
+
+/// Unlocks a submitted or approved week so its time can be corrected and the timesheet submitted
+/// again. Managers of the team member's organization only.
+pub async fn reopen(
+    pool: &Pool<Postgres>,
+    user: &CurrentUser,
+    team_member_id: i32,
+    date: NaiveDate,
+    comment: Option<String>,
+    now: DateTime<Utc>,
+) -> Result<(), AppError> {
+    let step = ManagerStep {
+        team_member_id,
+        date,
+        from: &[TimesheetStatus::Submitted, TimesheetStatus::Approved],
+        to: TimesheetStatus::Reopened,
+        comment,
+    };
+    take_manager_step(pool, user, step, now).await
+}
+
+/// Stored timesheets visible to the user, newest week first: their own, their organization's
+/// for managers, or everyone's for admins. `status` narrows them down, e.g. to the submitted ones waiting for review.
+pub async fn list(
+    pool: &Pool<Postgres>,
+    user: &CurrentUser,
+    status: Option<TimesheetStatus>,
+) -> Result<Vec<TimesheetSummary>, AppError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT t.team_member_id, m.email, m.name, t.period_start, t.status, (t.totals->>'minutes')::bigint AS minutes,
+               t.submitted_at, t.reviewed_by, t.reviewed_at, t.review_comment
+        FROM timesheets t
+        JOIN team_members m ON m.id = t.team_member_id
+        WHERE ($5 OR m."organizationId" = $1)
+          AND ($2 OR t.team_member_id = $3)
+          AND ($4::text IS NULL OR t.status = $4)
+        ORDER BY t.period_start DESC, m.email
+        "#,
+    )
+    .bind(user.organization_id)
+    .bind(user.role.is_manager())
+    .bind(user.team_member_id)
+    .bind(status.map(TimesheetStatus::as_str))
+    .bind(user.role == TeamMemberRole::Admin)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Db(format!("Database error fetching timesheets: {}", e)))?;
+
+    let column_error = |column: &str, e: sqlx::Error| AppError::Db(format!("Failed to get {} column: {}", column, e));
+    rows.iter()
+        .map(|row| {
+            let status: String = row.try_get("status").map_err(|e| column_error("status", e))?;
+            Ok(TimesheetSummary {
+                team_member_id: row.try_get("team_member_id").map_err(|e| column_error("team_member_id", e))?,
+                email: row.try_get("email").map_err(|e| column_error("email", e))?,
+                name: row.try_get("name").map_err(|e| column_error("name", e))?,
+                period_start: row.try_get("period_start").map_err(|e| column_error("period_start", e))?,
+                status: TimesheetStatus::parse(&status)?,
+                minutes: row.try_get("minutes").map_err(|e| column_error("minutes", e))?,
+                submitted_at: row.try_get("submitted_at").map_err(|e| column_error("submitted_at", e))?,
+                reviewed_by: row.try_get("reviewed_by").map_err(|e| column_error("reviewed_by", e))?,
+                reviewed_at: row.try_get("reviewed_at").map_err(|e| column_error("reviewed_at", e))?,
+                review_comment: row.try_get("review_comment").map_err(|e| column_error("review_comment", e))?,
+            })
+        })
+        .collect()
+}
+
+const EVENT_COLUMNS: &str = "e.id, t.team_member_id, t.period_start, e.status, e.actor, e.comment, e.occurred_at";
+
+fn event_from_row(row: &PgRow) -> Result<TimesheetEvent, AppError> {
+    let column_error = |column: &str, e: sqlx::Error| AppError::Db(format!("Failed to get {} column: {}", column, e));
+    let status: String = row.try_get("status").map_err(|e| column_error("status", e))?;
+    Ok(TimesheetEvent {
+        id: row.try_get("id").map_err(|e| column_error("id", e))?,
+        team_member_id: row.try_get("team_member_id").map_err(|e| column_error("team_member_id", e))?,
+        period_start: row.try_get("period_start").map_err(|e| column_error("period_start", e))?,
+        status: TimesheetStatus::parse(&status)?,
+        actor: row.try_get("actor").map_err(|e| column_error("actor", e))?,
+        comment: row.try_get("comment").map_err(|e| column_error("comment", e))?,
+        occurred_at: row.try_get("occurred_at").map_err(|e| column_error("occurred_at", e))?,
+    })
+}
+
+/// Every submission, review and reopening of a week's timesheet, oldest first.
+pub async fn history(
+    pool: &Pool<Postgres>,
+    user: &CurrentUser,
+    team_member_id: i32,
+    date: NaiveDate,
+) -> Result<Vec<TimesheetEvent>, AppError> {
+    authorize_team_member(pool, user, "view timesheet history", team_member_id).await?;
+    let rows = sqlx::query(&format!(
+        r#"
+        SELECT {}
+        FROM timesheet_events e
+        JOIN timesheets t ON t.id = e.timesheet_id
+        WHERE t.team_member_id = $1 AND t.period_start = $2
+        ORDER BY e.occurred_at
+        "#,
+        EVENT_COLUMNS
+    ))
+    .bind(team_member_id)
+    .bind(workday::week_start(date))
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Db(format!("Database error fetching timesheet history: {}", e)))?;
+    rows.iter().map(event_from_row).collect()
+}
+
+/// Managers' steps on the team member's timesheets that haven't been delivered to them yet,
+/// oldest first. They stay pending until `mark_notified`.
+pub async fn pending_notifications(pool: &Pool<Postgres>, team_member_id: i32) -> Result<Vec<TimesheetEvent>, AppError> {
+    let rows = sqlx::query(&format!(
+        r#"
+        SELECT {}
+        FROM timesheet_events e
+        JOIN timesheets t ON t.id = e.timesheet_id
+        WHERE t.team_member_id = $1 AND e.notified_at IS NULL
+        ORDER BY e.occurred_at
+        "#,
+        EVENT_COLUMNS
+    ))
+    .bind(team_member_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to load timesheet notifications: {}", e)))?;
+    rows.iter().map(event_from_row).collect()
+}
+
+/// Records that a step was delivered to the team member, so it isn't sent again.
+pub async fn mark_notified(pool: &Pool<Postgres>, event_id: Uuid, now: DateTime<Utc>) -> Result<(), AppError> {
+    sqlx::query("UPDATE timesheet_events SET notified_at = $2 WHERE id = $1 AND notified_at IS NULL")
+        .bind(event_id)
+        .bind(now)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Db(format!("Failed to mark timesheet notification as sent: {}", e)))?;
+    Ok(())
+}
+
+/// Fails if any of [from, to) lies in a week whose timesheet is submitted or approved.
+/// Checked before session edits, manual entries and project assignments, when a session
+/// starts (possibly backdated) and before crash recovery closes a session.
+pub async fn ensure_unlocked<'e>(
+    executor: impl PgExecutor<'e>,
+    team_member_id: i32,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<(), AppError> {
+    let locked = sqlx::query(
+        r#"
+        SELECT period_start FROM timesheets
+        WHERE team_member_id = $1 AND status IN ('submitted', 'approved') AND starts_at < $3 AND ends_at > $2
+        ORDER BY period_start
+        LIMIT 1
+        "#,
+    )
+    .bind(team_member_id)
+    .bind(from)
+    .bind(to.max(from + chrono::Duration::seconds(1)))
+    .fetch_optional(executor)
+    .await
+    .map_err(|e| AppError::Db(format!("Failed to check timesheet locks: {}", e)))?;
+    match locked {
+        Some(row) => {
+            let period_start: NaiveDate =
+                row.try_get("period_start").map_err(|e| AppError::Db(format!("Failed to get period_start column: {}", e)))?;
+            Err(AppError::TimesheetLocked(format!(
+                "The week of {} has been submitted on a timesheet. Ask a manager to reopen it first.",
+                period_start
+            )))
+        }
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authorization::Denial;
+    use crate::test_db;
+    use chrono::{Duration, TimeZone};
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 5, day).unwrap()
+    }
+
+    fn record(day: u32, project: Option<&str>, minutes: i64, manual: bool) -> TimeRecord {
+        TimeRecord { date: date(day), project: project.map(str::to_string), seconds: minutes * 60 + 20, manual }
+    }
+
+    // The week of Monday 6 May 2024, rounded to the nearest quarter hour
+    fn week() -> Vec<TimesheetDay> {
+        let records = [
+            record(6, Some("Billing"), 50, false),
+            record(6, Some("Billing"), 20, true),
+            record(6, None, 4, false),
+            record(8, Some("Website"), 8, false),
+            // Outside the week
+            record(13, Some("Website"), 60, false),
+        ];
+        day_totals(date(6), &records, Rounding { minutes: 15, mode: RoundingMode::Nearest })
+    }
+
+    #[test]
+    fn every_day_of_the_week_has_a_row() {
+        assert_eq!(week().len(), 7);
+    }
+
+    #[test]
+    fn each_days_project_totals_are_rounded() {
+        let minutes: Vec<i64> = week()[0].projects.iter().map(|total| total.minutes).collect();
+        assert_eq!(minutes, vec![0, 75]);
+    }
+
+    #[test]
+    fn tracked_and_manual_time_are_kept_apart() {
+        let billing = &week()[0].projects[1];
+        assert_eq!((billing.tracked_seconds, billing.manual_seconds), (3020, 1220));
+    }
+
+    #[test]
+    fn days_add_up_their_rounded_projects() {
+        let days = week();
+        assert_eq!((days[0].minutes, days[1].minutes, days[2].minutes), (75, 0, 15));
+    }
+
+    #[test]
+    fn time_of_other_weeks_is_left_out() {
+        assert_eq!(week().iter().map(|day| day.minutes).sum::<i64>(), 90);
+    }
+
+    #[test]
+    fn rounding_up_and_down_goes_to_the_next_increment() {
+        assert_eq!(Rounding { minutes: 6, mode: RoundingMode::Up }.apply(6 * 60 + 1), 12);
+        assert_eq!(Rounding { minutes: 6, mode: RoundingMode::Down }.apply(11 * 60 + 59), 6);
+    }
+
+    #[test]
+    fn no_increment_rounds_to_whole_minutes() {
+        assert_eq!(Rounding { minutes: 0, mode: RoundingMode::Nearest }.apply(90), 2);
+    }
+
+    #[test]
+    fn submitted_and_approved_weeks_are_locked() {
+        assert!(TimesheetStatus::Submitted.is_locked() && TimesheetStatus::Approved.is_locked());
+    }
+
+    #[test]
+    fn rejected_and_reopened_weeks_can_be_corrected() {
+        assert!(!TimesheetStatus::Rejected.is_locked() && !TimesheetStatus::Reopened.is_locked());
+    }
+
+    fn rounding() -> Rounding {
+        Rounding { minutes: 0, mode: RoundingMode::Nearest }
+    }
+
+    fn at(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, day, hour, 0, 0).unwrap()
+    }
+
+    // Well after the week of 6 May is over
+    fn after_the_week() -> DateTime<Utc> {
+        at(20, 9)
+    }
+
+    // An employee with two hours on Monday 6 May, and a manager of their organization
+    async fn team_with_a_worked_week(pool: &Pool<Postgres>) -> (CurrentUser, CurrentUser) {
+        let organization = test_db::organization(pool).await;
+        let employee = test_db::team_member(pool, organization, TeamMemberRole::Employee).await;
+        let manager = test_db::team_member(pool, organization, TeamMemberRole::Manager).await;
+        test_db::session(pool, employee.team_member_id, at(6, 9), Some(at(6, 11))).await;
+        (employee, manager)
+    }
+
+    // Needs Postgres, see `test_db`
+    #[tokio::test]
+    #[ignore]
+    async fn submitting_keeps_the_weeks_totals() {
+        let pool = test_db::pool().await;
+        let (employee, _) = team_with_a_worked_week(&pool).await;
+
+        let timesheet = submit(&pool, &employee, date(8), chrono_tz::UTC, rounding(), after_the_week()).await.unwrap();
+
+        assert_eq!((timesheet.status, timesheet.period_start, timesheet.minutes), (TimesheetStatus::Submitted, date(6), 120));
+        let stored = load_stored(&pool, employee.team_member_id, date(6)).await.unwrap().unwrap();
+        assert_eq!(stored.minutes, 120);
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn a_week_still_running_cannot_be_submitted() {
+        let pool = test_db::pool().await;
+        let (employee, _) = team_with_a_worked_week(&pool).await;
+
+        let result = submit(&pool, &employee, date(8), chrono_tz::UTC, rounding(), at(10, 9)).await;
+
+        assert!(matches!(result, Err(AppError::Invalid(_))));
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn pending_manual_entries_hold_up_submission() {
+        let pool = test_db::pool().await;
+        let (employee, _) = team_with_a_worked_week(&pool).await;
+        sqlx::query(
+            r#"
+            INSERT INTO manual_time_entries (id, team_member_id, organization_id, task, start_time, end_time, reason)
+            VALUES ($1, $2, $3, 'Planning', $4, $5, 'Laptop closed')
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(employee.team_member_id)
+        .bind(employee.organization_id)
+        .bind(at(7, 9))
+        .bind(at(7, 10))
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let result = submit(&pool, &employee, date(8), chrono_tz::UTC, rounding(), after_the_week()).await;
+
+        assert!(matches!(result, Err(AppError::Invalid(_))));
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn a_submitted_week_cannot_be_submitted_again() {
+        let pool = test_db::pool().await;
+        let (employee, _) = team_with_a_worked_week(&pool).await;
+        submit(&pool, &employee, date(8), chrono_tz::UTC, rounding(), after_the_week()).await.unwrap();
+
+        let result = submit(&pool, &employee, date(8), chrono_tz::UTC, rounding(), after_the_week()).await;
+
+        assert!(matches!(result, Err(AppError::TimesheetLocked(_))));
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn only_time_in_a_submitted_week_is_locked() {
+        let pool = test_db::pool().await;
+        let (employee, _) = team_with_a_worked_week(&pool).await;
+        submit(&pool, &employee, date(8), chrono_tz::UTC, rounding(), after_the_week()).await.unwrap();
+
+        let inside = ensure_unlocked(&pool, employee.team_member_id, at(12, 23), at(13, 1)).await;
+        let after = ensure_unlocked(&pool, employee.team_member_id, at(13, 0), at(13, 1)).await;
+
+        assert!(matches!(inside, Err(AppError::TimesheetLocked(_))));
+        assert_eq!(after, Ok(()));
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn other_team_members_time_is_not_locked() {
+        let pool = test_db::pool().await;
+        let (employee, manager) = team_with_a_worked_week(&pool).await;
+        submit(&pool, &employee, date(8), chrono_tz::UTC, rounding(), after_the_week()).await.unwrap();
+
+        assert_eq!(ensure_unlocked(&pool, manager.team_member_id, at(6, 9), at(6, 11)).await, Ok(()));
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn a_rejected_week_unlocks() {
+        let pool = test_db::pool().await;
+        let (employee, manager) = team_with_a_worked_week(&pool).await;
+        submit(&pool, &employee, date(8), chrono_tz::UTC, rounding(), after_the_week()).await.unwrap();
+
+        let comment = Some("Monday is missing the standup".to_string());
+        review(&pool, &manager, employee.team_member_id, date(8), TimesheetStatus::Rejected, comment, after_the_week())
+            .await
+            .unwrap();
+
+        assert_eq!(ensure_unlocked(&pool, employee.team_member_id, at(6, 9), at(6, 11)).await, Ok(()));
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn an_approved_week_stays_locked_until_reopened() {
+        let pool = test_db::pool().await;
+        let (employee, manager) = team_with_a_worked_week(&pool).await;
+        submit(&pool, &employee, date(8), chrono_tz::UTC, rounding(), after_the_week()).await.unwrap();
+        review(&pool, &manager, employee.team_member_id, date(8), TimesheetStatus::Approved, None, after_the_week())
+            .await
+            .unwrap();
+        let approved = ensure_unlocked(&pool, employee.team_member_id, at(6, 9), at(6, 11)).await;
+
+        reopen(&pool, &manager, employee.team_member_id, date(8), None, after_the_week()).await.unwrap();
+
+        assert!(matches!(approved, Err(AppError::TimesheetLocked(_))));
+        assert_eq!(ensure_unlocked(&pool, employee.team_member_id, at(6, 9), at(6, 11)).await, Ok(()));
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn manager_steps_are_kept_in_the_history() {
+        let pool = test_db::pool().await;
+        let (employee, manager) = team_with_a_worked_week(&pool).await;
+        submit(&pool, &employee, date(8), chrono_tz::UTC, rounding(), after_the_week()).await.unwrap();
+        let reviewed_at = after_the_week() + Duration::hours(1);
+
+        review(&pool, &manager, employee.team_member_id, date(8), TimesheetStatus::Approved, None, reviewed_at)
+            .await
+            .unwrap();
+
+        let steps: Vec<_> = history(&pool, &employee, employee.team_member_id, date(8))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|event| (event.status, event.actor))
+            .collect();
+        assert_eq!(
+            steps,
+            vec![(TimesheetStatus::Submitted, employee.team_member_id), (TimesheetStatus::Approved, manager.team_member_id)]
+        );
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn only_submitted_weeks_can_be_reviewed() {
+        let pool = test_db::pool().await;
+        let (employee, manager) = team_with_a_worked_week(&pool).await;
+
+        let result =
+            review(&pool, &manager, employee.team_member_id, date(8), TimesheetStatus::Approved, None, after_the_week()).await;
+
+        assert!(matches!(result, Err(AppError::Invalid(_))));
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn managers_of_another_organization_cannot_review() {
+        let pool = test_db::pool().await;
+        let (employee, _) = team_with_a_worked_week(&pool).await;
+        let (_, outsider) = team_with_a_worked_week(&pool).await;
+        submit(&pool, &employee, date(8), chrono_tz::UTC, rounding(), after_the_week()).await.unwrap();
+
+        let result =
+            review(&pool, &outsider, employee.team_member_id, date(8), TimesheetStatus::Approved, None, after_the_week()).await;
+
+        assert_eq!(result, Err(AppError::Unauthorized(Denial::OtherOrganization)));
+    }
+}
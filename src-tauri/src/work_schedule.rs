@@ -0,0 +1,250 @@
+use chrono::{DateTime, Datelike, Days, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, Row};
+use std::collections::HashMap;
+
+use crate::authorization::require_manager_of;
+use crate::errors::AppError;
+use crate::identity::CurrentUser;
+
+/// How far ahead to look for the next window, e.g. past a long holiday.
+const LOOKAHEAD_DAYS: u64 = 31;
+
+/// Agreed working hours on one day, in the schedule's timezone. A window whose end is not after
+/// its start runs past midnight into the next day (`22:00`–`06:00`, or `18:00`–`00:00`).
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct TimeWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+/// Days that differ from the weekly hours. No windows means a day off.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ScheduleException {
+    pub date: NaiveDate,
+    #[serde(default)]
+    pub windows: Vec<TimeWindow>,
+}
+
+/// What starting the timer outside the schedule does.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OutsideHours {
+    /// Refuse to start
+    #[default]
+    Block,
+    /// Start, but warn the user
+    Warn,
+}
+
+/// What happens to a running session when its window ends.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleEnd {
+    #[default]
+    Pause,
+    Stop,
+}
+
+/// A team member's agreed working hours. Stored as JSON in `work_schedules` and copied onto
+/// every session tracked under it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct WorkSchedule {
+    /// IANA name, e.g. `Europe/Berlin`
+    pub timezone: String,
+    /// Windows for each day of the week; missing days are days off
+    #[serde(default)]
+    pub weekly: HashMap<Weekday, Vec<TimeWindow>>,
+    #[serde(default)]
+    pub holidays: Vec<NaiveDate>,
+    /// Take precedence over both the weekly hours and holidays
+    #[serde(default)]
+    pub exceptions: Vec<ScheduleException>,
+    #[serde(default)]
+    pub outside_hours: OutsideHours,
+    #[serde(default)]
+    pub at_end: ScheduleEnd,
+}
+
+impl WorkSchedule {
+    pub fn validated(self) -> Result<Self, AppError> {
+        self.timezone
+            .parse::<Tz>()
+            .map_err(|_| AppError::Invalid(format!("Unknown timezone '{}'.", self.timezone)))?;
+        let windows = self.weekly.values().chain(self.exceptions.iter().map(|exception| &exception.windows));
+        if windows.flatten().any(|window| window.start == window.end) {
+            return Err(AppError::Invalid("A schedule window cannot start and end at the same time.".to_string()));
+        }
+        Ok(self)
+    }
+
+    fn tz(&self) -> Tz {
+        self.timezone.parse().unwrap_or(Tz::UTC)
+    }
+
+    fn windows_on(&self, date: NaiveDate) -> &[TimeWindow] {
+        if let Some(exception) = self.exceptions.iter().find(|exception| exception.date == date) {
+            return &exception.windows;
+        }
+        if self.holidays.contains(&date) {
+            return &[];
+        }
+        self.weekly.get(&date.weekday()).map_or(&[], Vec::as_slice)
+    }
+
+    // Windows of the days from `first` on, as UTC intervals in order of their start
+    fn intervals(&self, first: NaiveDate, days: u64) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+        let tz = self.tz();
+        let mut intervals: Vec<_> = (0..days)
+            .filter_map(|offset| first.checked_add_days(Days::new(offset)))
+            .flat_map(|date| {
+                self.windows_on(date).iter().map(move |window| {
+                    let end_date = if window.end > window.start { date } else { date + Days::new(1) };
+                    (
+                        local_to_utc(tz, date.and_time(window.start)),
+                        local_to_utc(tz, end_date.and_time(window.end)),
+                    )
+                })
+            })
+            .collect();
+        intervals.sort();
+        intervals
+    }
+
+    /// The window `at` falls into, with windows that touch or overlap merged.
+    pub fn window_at(&self, at: DateTime<Utc>) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        let yesterday = at.with_timezone(&self.tz()).date_naive() - Days::new(1);
+        let mut current: Option<(DateTime<Utc>, DateTime<Utc>)> = None;
+        for (start, end) in self.intervals(yesterday, LOOKAHEAD_DAYS) {
+            match current.as_mut() {
+                Some(window) if start <= window.1 => window.1 = window.1.max(end),
+                Some(_) => break,
+                None if start <= at && at < end => current = Some((start, end)),
+                None => {}
+            }
+        }
+        current
+    }
+
+    /// Start of the next window after `at`, if there is one coming up.
+    pub fn next_window_start(&self, at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let today = at.with_timezone(&self.tz()).date_naive();
+        self.intervals(today, LOOKAHEAD_DAYS)
+            .into_iter()
+            .map(|(start, _)| start)
+            .find(|start| *start > at)
+    }
+}
+
+// Local time to UTC. Times skipped by a DST change count from the first valid time after them.
+fn local_to_utc(tz: Tz, local: NaiveDateTime) -> DateTime<Utc> {
+    (0..4)
+        .find_map(|half_hours| {
+            tz.from_local_datetime(&(local + chrono::Duration::minutes(30 * half_hours))).earliest()
+        })
+        .map(|at| at.with_timezone(&Utc))
+        .unwrap_or_else(|| Utc.from_utc_datetime(&local))
+}
+
+/// Describes why tracking isn't allowed at `at`, for error messages and warnings.
+pub fn outside_hours_message(schedule: &WorkSchedule, at: DateTime<Utc>) -> String {
+    match schedule.next_window_start(at) {
+        Some(next) => format!(
+            "Outside your work schedule. The next window starts at {}.",
+            next.with_timezone(&schedule.tz()).format("%a %Y-%m-%d %H:%M")
+        ),
+        None => "Outside your work schedule, with no upcoming window.".to_string(),
+    }
+}
+
+/// The team member's schedule, or `None` if their hours aren't restricted.
+pub async fn load_schedule(pool: &Pool<Postgres>, team_member_id: i32) -> Result<Option<WorkSchedule>, AppError> {
+    let row = sqlx::query("SELECT schedule::text AS schedule FROM work_schedules WHERE team_member_id = $1")
+        .bind(team_member_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::Db(format!("Failed to load work schedule: {}", e)))?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let json: String = row.try_get("schedule").map_err(|e| AppError::Db(format!("Failed to get schedule column: {}", e)))?;
+    serde_json::from_str(&json)
+        .map(Some)
+        .map_err(|e| AppError::Db(format!("Invalid work schedule JSON: {}", e)))
+}
+
+/// Sets or (with `None`) removes a team member's schedule. Only managers of their organization may.
+pub async fn save_schedule(
+    pool: &Pool<Postgres>,
+    user: &CurrentUser,
+    team_member_id: i32,
+    schedule: Option<WorkSchedule>,
+) -> Result<(), AppError> {
+    require_manager_of(pool, user, "set a work schedule", team_member_id).await?;
+    match schedule {
+        Some(schedule) => {
+            let json = serde_json::to_string(&schedule.validated()?)
+                .map_err(|e| AppError::Db(format!("Failed to serialize work schedule: {}", e)))?;
+            sqlx::query(
+                r#"
+                INSERT INTO work_schedules (team_member_id, schedule, updated_at)
+                VALUES ($1, $2::jsonb, now())
+                ON CONFLICT (team_member_id) DO UPDATE SET schedule = EXCLUDED.schedule, updated_at = now()
+                "#,
+            )
+            .bind(team_member_id)
+            .bind(json)
+            .execute(pool)
+            .await
+            .map_err(|e| AppError::Db(format!("Failed to save work schedule: {}", e)))?;
+        }
+        None => {
+            sqlx::query("DELETE FROM work_schedules WHERE team_member_id = $1")
+                .bind(team_member_id)
+                .execute(pool)
+                .await
+                .map_err(|e| AppError::Db(format!("Failed to remove work schedule: {}", e)))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_follow_weekdays_holidays_exceptions_and_midnight() {
+        let window = |start: &str, end: &str| TimeWindow { start: start.parse().unwrap(), end: end.parse().unwrap() };
+        let schedule: WorkSchedule = serde_json::from_value(serde_json::json!({
+            "timezone": "Europe/Berlin",
+            "weekly": {
+                "Mon": [{"start": "09:00", "end": "12:00"}, {"start": "12:00", "end": "17:00"}],
+                "Tue": [{"start": "22:00", "end": "06:00"}],
+            },
+            "holidays": ["2024-05-13"],
+            "exceptions": [
+                {"date": "2024-05-14", "windows": [{"start": "10:00", "end": "11:00"}]},
+                {"date": "2024-03-31", "windows": [{"start": "02:30", "end": "04:00"}]},
+            ],
+        }))
+        .unwrap();
+        assert_eq!(schedule.weekly[&Weekday::Tue], vec![window("22:00", "06:00")]);
+        let utc = |d: u32, h: u32, m: u32| Utc.with_ymd_and_hms(2024, 5, d, h, m, 0).unwrap();
+
+        // Monday the 6th, 10:00 in Berlin: adjacent windows merge
+        assert_eq!(schedule.window_at(utc(6, 8, 0)), Some((utc(6, 7, 0), utc(6, 15, 0))));
+        assert_eq!(schedule.window_at(utc(6, 15, 0)), None);
+        // Tuesday's night shift runs into Wednesday
+        assert_eq!(schedule.window_at(utc(8, 3, 0)), Some((utc(7, 20, 0), utc(8, 4, 0))));
+        // Monday the 13th is a holiday, and Tuesday the 14th has its own hours
+        assert_eq!(schedule.next_window_start(utc(8, 4, 0)), Some(utc(14, 8, 0)));
+        assert_eq!(schedule.window_at(utc(13, 8, 0)), None);
+        assert_eq!(schedule.window_at(utc(14, 8, 30)), Some((utc(14, 8, 0), utc(14, 9, 0))));
+        assert!(outside_hours_message(&schedule, utc(13, 8, 0)).contains("Tue 2024-05-14 10:00"));
+        // 02:30 doesn't exist when DST starts; the window opens at 03:00 CEST
+        let march = |h: u32, m: u32| Utc.with_ymd_and_hms(2024, 3, 31, h, m, 0).unwrap();
+        assert_eq!(schedule.window_at(march(1, 30)), Some((march(1, 0), march(2, 0))));
+    }
+}
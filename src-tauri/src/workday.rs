@@ -0,0 +1,61 @@
+use chrono::{DateTime, Datelike, Days, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// The user's timezone, taken from the operating system. Falls back to UTC if it can't be determined.
+pub fn user_timezone() -> Tz {
+    match iana_time_zone::get_timezone() {
+        Ok(name) => name.parse().unwrap_or_else(|_| {
+            eprintln!("Warning: unknown system timezone '{}'. Using UTC for workday boundaries.", name);
+            Tz::UTC
+        }),
+        Err(e) => {
+            eprintln!("Warning: could not determine the system timezone ({}). Using UTC for workday boundaries.", e);
+            Tz::UTC
+        }
+    }
+}
+
+/// Start of the next local day after `now`. Normally midnight; where a DST change skips
+/// midnight, the first valid time of that day.
+pub fn next_day_start(now: DateTime<Utc>, tz: Tz) -> DateTime<Utc> {
+    let next_date = now
+        .with_timezone(&tz)
+        .date_naive()
+        .checked_add_days(Days::new(1))
+        .expect("date out of range");
+    day_start(next_date, tz)
+}
+
+/// Start of the local day `date`, with the same DST handling as `next_day_start`.
+pub fn day_start(date: NaiveDate, tz: Tz) -> DateTime<Utc> {
+    (0..24)
+        .filter_map(|hour| {
+            let time = NaiveTime::from_hms_opt(hour, 0, 0)?;
+            tz.from_local_datetime(&date.and_time(time)).earliest()
+        })
+        .next()
+        .map(|start| start.with_timezone(&Utc))
+        .expect("every day has a valid hour")
+}
+
+/// The Monday of the week `date` falls in.
+pub fn week_start(date: NaiveDate) -> NaiveDate {
+    date - Days::new(u64::from(date.weekday().num_days_from_monday()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn day_starts_at_local_midnight_even_when_dst_skips_it() {
+        let berlin: Tz = "Europe/Berlin".parse().unwrap();
+        let evening = Utc.with_ymd_and_hms(2024, 5, 6, 21, 30, 0).unwrap(); // 23:30 in Berlin
+        assert_eq!(next_day_start(evening, berlin), Utc.with_ymd_and_hms(2024, 5, 6, 22, 0, 0).unwrap());
+
+        // Santiago skipped from 00:00 to 01:00 on 2024-09-08
+        let santiago: Tz = "America/Santiago".parse().unwrap();
+        let before = Utc.with_ymd_and_hms(2024, 9, 8, 2, 0, 0).unwrap(); // 22:00 on the 7th
+        assert_eq!(next_day_start(before, santiago), Utc.with_ymd_and_hms(2024, 9, 8, 4, 0, 0).unwrap());
+    }
+}
@@ -1,7 +1,7 @@
 import { useState, useEffect, useRef } from "react";
 import { invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
-import { TimerStatus, ActivityData } from "../types/timer";
+import { TimerStatus, ActivityData, AppError, errorMessage, isAppError } from "../types/timer";
 
 interface UseTimerResult {
   timerStatus: TimerStatus;
@@ -40,7 +40,7 @@ export function useTimer(): UseTimerResult {
       }
     } catch (err) {
       console.error("Error getting initial state:", err);
-      setLastError(`Error getting initial state: ${err}`);
+      setLastError(`Error getting initial state: ${errorMessage(err)}`);
       setElapsedTime(0);
     }
   };
@@ -62,19 +62,21 @@ export function useTimer(): UseTimerResult {
         invoke<number>("get_elapsed_time")
           .then(setElapsedTime)
           .catch((err) => {
-            setLastError(`Error getting time after pause: ${err}`);
+            setLastError(`Error getting time after pause: ${errorMessage(err)}`);
           });
       } else if (newStatus === TimerStatus.Running) {
         invoke<number>("get_elapsed_time")
           .then(setElapsedTime)
           .catch((err) => {
-            setLastError(`Error getting time after start/resume: ${err}`);
+            setLastError(`Error getting time after start/resume: ${errorMessage(err)}`);
           });
       }
     });
 
-    const unlistenError = listen<string>("screenshot_error", (event) => {
-      setLastError(`Screenshot Error: ${event.payload}`);
+    const unlistenError = listen<AppError>("screenshot_error", (event) => {
+      // The message is generic; the cause says what to fix, e.g. a missing screen recording permission
+      const cause = event.payload.details?.cause;
+      setLastError(`Screenshot Error: ${event.payload.message}${typeof cause === "string" ? ` ${cause}` : ""}`);
     });
 
     const unlistenNewScreenshot = listen<string>("new_screenshot", async (event) => {
@@ -84,7 +86,7 @@ export function useTimer(): UseTimerResult {
         const dataUri = await invoke<string>("get_screenshot_data", { id: screenshotId });
         setLastScreenshots((prev) => [prev[1], dataUri]);
       } catch (err) {
-        setLastError(`Error fetching screenshot ${screenshotId}: ${err}`);
+        setLastError(`Error fetching screenshot ${screenshotId}: ${errorMessage(err)}`);
       }
     });
 
@@ -159,7 +161,12 @@ export function useTimer(): UseTimerResult {
     try {
       await invoke("start_timer");
     } catch (err) {
-      setLastError(`Error starting timer: ${err}`);
+      // Started elsewhere (e.g. from a reminder); just catch up
+      if (isAppError(err) && err.code === "AlreadyRunning") {
+        await fetchInitialState();
+        return;
+      }
+      setLastError(`Error starting timer: ${errorMessage(err)}`);
     }
   };
 
@@ -168,7 +175,12 @@ export function useTimer(): UseTimerResult {
     try {
       await invoke("stop_timer");
     } catch (err) {
-      setLastError(`Error stopping timer: ${err}`);
+      // Stopped in the meantime, e.g. at a limit
+      if (isAppError(err) && err.code === "AlreadyStopped") {
+        await fetchInitialState();
+        return;
+      }
+      setLastError(`Error stopping timer: ${errorMessage(err)}`);
     }
   };
 
@@ -177,7 +189,7 @@ export function useTimer(): UseTimerResult {
     try {
       await invoke("pause_timer");
     } catch (err) {
-      setLastError(`Error pausing timer: ${err}`);
+      setLastError(`Error pausing timer: ${errorMessage(err)}`);
     }
   };
 
@@ -186,7 +198,7 @@ export function useTimer(): UseTimerResult {
     try {
       await invoke("resume_timer");
     } catch (err) {
-      setLastError(`Error resuming timer: ${err}`);
+      setLastError(`Error resuming timer: ${errorMessage(err)}`);
     }
   };
 
@@ -14,3 +14,41 @@ export interface ActivityData {
   key_presses: number;
   mouse_clicks: number;
 }
+
+/**
+ * Error returned by backend commands. Branch on `code`; `message` is for display.
+ */
+export interface AppError {
+  code:
+    | "AlreadyRunning"
+    | "AlreadyStopped"
+    | "NotRunning"
+    | "NotPaused"
+    | "Busy"
+    | "TimerUnavailable"
+    | "OutsideWorkHours"
+    | "HourLimitReached"
+    | "TimesheetLocked"
+    | "Invalid"
+    | "NotFound"
+    | "Unauthorized"
+    | "Db"
+    | "Capture"
+    | "Storage";
+  message: string;
+  details: Record<string, unknown> | null;
+}
+
+/**
+ * Whether an error thrown by `invoke` is an AppError from the backend.
+ */
+export function isAppError(err: unknown): err is AppError {
+  return typeof err === "object" && err !== null && "code" in err && "message" in err;
+}
+
+/**
+ * Message of an error thrown by `invoke`, for display.
+ */
+export function errorMessage(err: unknown): string {
+  return isAppError(err) ? err.message : String(err);
+}